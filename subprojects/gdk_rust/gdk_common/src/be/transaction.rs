@@ -27,10 +27,98 @@ use std::str::FromStr;
 
 pub const DUST_VALUE: u64 = 546;
 
-// 52-bit rangeproof size
-const DEFAULT_RANGEPROOF_SIZE: usize = 4174;
-// 3-input ASP size
-const DEFAULT_SURJECTIONPROOF_SIZE: usize = 135;
+// Max number of inputs used in an asset surjection proof, as in Elements
+const MAX_SURJECTIONPROOF_USED_INPUTS: usize = 3;
+
+/// Parameters used to create the rangeproofs of Liquid outputs, the defaults are the ones used by
+/// Elements (`-ct_bits` and `-ct_exponent`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CtParams {
+    pub bits: i32,
+    pub exponent: i32,
+    pub min_value: u64,
+}
+
+impl Default for CtParams {
+    fn default() -> Self {
+        CtParams {
+            bits: 52,
+            exponent: 0,
+            min_value: 1,
+        }
+    }
+}
+
+impl CtParams {
+    /// Size in bytes of the rangeproof for an output of `value`, mirroring the choice of the
+    /// proof parameters done by libsecp256k1-zkp
+    pub fn rangeproof_size(&self, value: u64) -> usize {
+        let mut min_bits = self.bits.max(0).min(64) as u32;
+        let max_bits = if self.min_value > 0 {
+            self.min_value.leading_zeros()
+        } else {
+            64
+        };
+        min_bits = min_bits.min(max_bits);
+        let mut exp = self.exponent.max(0).min(18) as u32;
+        if min_bits > 61 || value > i64::MAX as u64 {
+            exp = 0;
+        }
+        let mut v = value.saturating_sub(self.min_value);
+        let mut v2 = if min_bits > 0 {
+            u64::MAX >> (64 - min_bits)
+        } else {
+            0
+        };
+        let mut i = 0;
+        while i < exp && v2 <= u64::MAX / 10 {
+            v /= 10;
+            v2 *= 10;
+            i += 1;
+        }
+        let min_value = value - v * 10u64.pow(i);
+        let mantissa = if v > 0 {
+            64 - v.leading_zeros()
+        } else {
+            1
+        };
+        proof_size(mantissa.max(min_bits) as usize, min_value > 0)
+    }
+
+    /// Size in bytes of the biggest rangeproof for an output which value is not yet known
+    pub fn max_rangeproof_size(&self) -> usize {
+        let min_bits = self.bits.max(1).min(64) as usize;
+        proof_size(min_bits, true)
+    }
+}
+
+/// Size of a rangeproof with the given mantissa, see `secp256k1_rangeproof_sign`
+fn proof_size(mantissa: usize, has_min_value: bool) -> usize {
+    let rings = (mantissa + 1) / 2;
+    let pubkeys = rings * 4 - 2 * (mantissa % 2);
+    let header = 2 + if has_min_value {
+        8
+    } else {
+        0
+    };
+    header + (rings - 1 + 7) / 8 + 32 * (rings - 1) + 32 + 32 * pubkeys
+}
+
+/// Size in bytes of the asset surjection proof of an output of a tx with `num_inputs` inputs
+pub fn surjectionproof_size(num_inputs: usize) -> usize {
+    let used_inputs = num_inputs.min(MAX_SURJECTIONPROOF_USED_INPUTS);
+    2 + (num_inputs + 7) / 8 + 32 * (1 + used_inputs)
+}
+
+/// Size of the length prefix of a serialized vector
+fn varint_size(len: usize) -> usize {
+    match len {
+        0..=0xfc => 1,
+        0xfd..=0xffff => 3,
+        0x10000..=0xffffffff => 5,
+        _ => 9,
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash)]
 pub enum BETransaction {
@@ -316,6 +404,7 @@ impl BETransaction {
         value: u64,
         asset: Option<elements::issuance::AssetId>,
         id: NetworkId,
+        allow_unconfidential: bool,
     ) -> Result<(), Error> {
         match (self, id) {
             (BETransaction::Bitcoin(tx), NetworkId::Bitcoin(_)) => {
@@ -329,13 +418,18 @@ impl BETransaction {
             (BETransaction::Elements(tx), NetworkId::Elements(net)) => {
                 let address = elements::Address::parse_with_params(&address, net.address_params())
                     .map_err(|_| Error::InvalidAddress)?;
-                let blinding_pubkey = address.blinding_pubkey.ok_or(Error::InvalidAddress)?;
+                let nonce = match address.blinding_pubkey {
+                    Some(blinding_pubkey) => confidential::Nonce::Confidential(blinding_pubkey),
+                    // the output is left explicit when blinding the transaction
+                    None if allow_unconfidential => confidential::Nonce::Null,
+                    None => return Err(Error::InvalidAddress),
+                };
                 let asset_id =
                     asset.expect("add_output must be called with a non empty asset in liquid");
                 let new_out = elements::TxOut {
                     asset: confidential::Asset::Explicit(asset_id),
                     value: confidential::Value::Explicit(value),
-                    nonce: confidential::Nonce::Confidential(blinding_pubkey),
+                    nonce,
                     script_pubkey: address.script_pubkey(),
                     witness: TxOutWitness::default(),
                 };
@@ -362,7 +456,15 @@ impl BETransaction {
 
     /// estimates the fee of the final transaction given the `fee_rate`
     /// called when the tx is being built and miss things like signatures and changes outputs.
-    pub fn estimated_fee(&self, fee_rate: f64, more_changes: u8, script_type: ScriptType) -> u64 {
+    ///
+    /// For elements `ct_params` are used to compute the size of the proofs added when blinding.
+    pub fn estimated_fee(
+        &self,
+        fee_rate: f64,
+        more_changes: u8,
+        script_type: ScriptType,
+        ct_params: CtParams,
+    ) -> u64 {
         let dummy_tx = self.clone();
         match dummy_tx {
             BETransaction::Bitcoin(mut tx) => {
@@ -409,15 +511,32 @@ impl BETransaction {
                     tx.output.push(new_out);
                 }
 
-                let proofs_size = (DEFAULT_RANGEPROOF_SIZE + DEFAULT_SURJECTIONPROOF_SIZE)
-                    * tx.output.iter().filter(|o| o.witness.is_empty()).count();
+                // weight added by blinding, explicit outputs (with a null nonce) are not blinded
+                let surjectionproof_size = surjectionproof_size(tx.input.len());
+                let mut blinding_weight = 0;
+                for output in tx.output.iter() {
+                    if !output.witness.is_empty() || output.nonce == confidential::Nonce::Null {
+                        continue;
+                    }
+                    let rangeproof_size = match output.value {
+                        Value::Explicit(value) => {
+                            // the explicit value (9 bytes) is replaced by a commitment (33 bytes)
+                            blinding_weight += (33 - 9) * 4;
+                            ct_params.rangeproof_size(value)
+                        }
+                        _ => ct_params.max_rangeproof_size(),
+                    };
+                    // the empty proofs are already serialized with a 1 byte length prefix
+                    blinding_weight += varint_size(rangeproof_size) - 1 + rangeproof_size;
+                    blinding_weight += varint_size(surjectionproof_size) - 1 + surjectionproof_size;
+                }
 
                 tx.output.push(elements::TxOut::new_fee(
                     0,
                     elements::issuance::AssetId::from_slice(&[0u8; 32]).unwrap(),
                 )); // mockup for the explicit fee output
-                let vbytes = (tx.get_weight() + proofs_size) as f64 / 4.0;
-                let fee_val = (vbytes * fee_rate * 1.03) as u64; // increasing estimated fee by 3% to stay over relay fee, TODO improve fee estimation and lower this
+                let vbytes = ((tx.get_weight() + blinding_weight + 3) / 4) as f64;
+                let fee_val = (vbytes * fee_rate * 1.03).ceil() as u64; // increasing estimated fee by 3% to stay over relay fee, TODO improve fee estimation and lower this
                 info!(
                     "DUMMYTX inputs:{} outputs:{} num_changes:{} vbytes:{} fee_val:{}",
                     tx.input.len(),
@@ -447,11 +566,16 @@ impl BETransaction {
                         .unwrap();
                     different_assets.insert(asset);
                 }
-                if different_assets.is_empty() {
-                    0
-                } else {
-                    different_assets.len() as u8 - send_all as u8
+                if send_all {
+                    // send_all sweeps the assets in the outputs, changes are needed only for the
+                    // other assets in the inputs (e.g. the policy asset paying the fee)
+                    for output in tx.output.iter() {
+                        if let Asset::Explicit(asset) = output.asset {
+                            different_assets.remove(&asset);
+                        }
+                    }
                 }
+                different_assets.len() as u8
             }
         }
     }
@@ -467,6 +591,7 @@ impl BETransaction {
         all_txs: &BETransactions,
         unblinded: &HashMap<elements::OutPoint, elements::TxOutSecrets>,
         script_type: ScriptType,
+        ct_params: CtParams,
     ) -> Vec<AssetValue> {
         match self {
             Self::Bitcoin(tx) => {
@@ -476,6 +601,7 @@ impl BETransaction {
                     fee_rate,
                     self.estimated_changes(no_change, all_txs, unblinded),
                     script_type,
+                    ct_params,
                 ); // send all does not create change
                 if sum_outputs + estimated_fee > sum_inputs {
                     vec![AssetValue::new_bitcoin(sum_outputs + estimated_fee - sum_inputs)]
@@ -514,6 +640,7 @@ impl BETransaction {
                     fee_rate,
                     self.estimated_changes(no_change, all_txs, unblinded),
                     script_type,
+                    ct_params,
                 );
                 *outputs.entry(policy_asset.clone()).or_insert(0) += estimated_fee;
 
@@ -538,23 +665,26 @@ impl BETransaction {
     }
 
     /// return a Vector with changes of this transaction
-    /// requires inputs are greater than outputs for earch asset
+    /// requires inputs are greater than outputs for earch asset, and than outputs plus
+    /// `estimated_fee` for the fee asset, or returns `InsufficientFunds`
     pub fn changes(
         &self,
         estimated_fee: u64,
         policy_asset: Option<elements::issuance::AssetId>,
         all_txs: &BETransactions,
         unblinded: &HashMap<elements::OutPoint, elements::TxOutSecrets>,
-    ) -> Vec<AssetValue> {
+    ) -> Result<Vec<AssetValue>, Error> {
         match self {
             Self::Bitcoin(tx) => {
                 let sum_inputs = sum_inputs(tx, all_txs);
                 let sum_outputs: u64 = tx.output.iter().map(|o| o.value).sum();
-                let change_value = sum_inputs - sum_outputs - estimated_fee;
+                let change_value = sum_inputs
+                    .checked_sub(sum_outputs + estimated_fee)
+                    .ok_or(Error::InsufficientFunds)?;
                 if change_value > DUST_VALUE {
-                    vec![AssetValue::new_bitcoin(change_value)]
+                    Ok(vec![AssetValue::new_bitcoin(change_value)])
                 } else {
-                    vec![]
+                    Ok(vec![])
                 }
             }
             Self::Elements(tx) => {
@@ -589,7 +719,7 @@ impl BETransaction {
                     if asset == &policy_asset.unwrap() {
                         // from a purely privacy perspective could make sense to always create the change output in liquid, so min change = 0
                         // however elements core use the dust anyway for 2 reasons: rebasing from core and economical considerations
-                        sum -= estimated_fee;
+                        sum = sum.checked_sub(estimated_fee).ok_or(Error::InsufficientFunds)?;
                         if sum > DUST_VALUE {
                             // we apply dust rules for liquid bitcoin as elements do
                             result.push(AssetValue::new(*asset, sum));
@@ -599,7 +729,7 @@ impl BETransaction {
                     }
                 }
                 assert!(outputs_asset_amounts.is_empty());
-                result
+                Ok(result)
             }
         }
     }
@@ -1086,3 +1216,30 @@ impl AssetValue {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proofs_size() {
+        let ct_params = CtParams::default();
+        // 52-bit rangeproof
+        assert_eq!(ct_params.rangeproof_size(1_000), 4174);
+        assert_eq!(ct_params.rangeproof_size(21_000_000 * 100_000_000), 4174);
+        assert_eq!(ct_params.max_rangeproof_size(), 4174);
+        // values bigger than 52 bits need a bigger proof
+        assert_eq!(ct_params.rangeproof_size(1 << 60), 4814);
+
+        let ct_params = CtParams {
+            bits: 36,
+            exponent: 1,
+            min_value: 1,
+        };
+        assert!(ct_params.rangeproof_size(1_000) < CtParams::default().rangeproof_size(1_000));
+
+        assert_eq!(surjectionproof_size(1), 67);
+        assert_eq!(surjectionproof_size(3), 131);
+        assert_eq!(surjectionproof_size(9), 132);
+    }
+}
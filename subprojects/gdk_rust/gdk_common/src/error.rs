@@ -41,6 +41,9 @@ pub enum Error {
     #[error("Invalid address")]
     InvalidAddress,
 
+    #[error("Insufficient funds")]
+    InsufficientFunds,
+
     #[error("Generic({0})")]
     Generic(String),
 }
@@ -4,7 +4,7 @@ use crate::NetworkId;
 use bitcoin::Network;
 use elements::confidential;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use crate::error::Error;
 use crate::scripts::ScriptType;
@@ -91,6 +91,10 @@ pub struct AddressAmount {
     pub satoshi: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub asset_id: Option<String>,
+    /// Liquid only, allow sending to a non-confidential address, creating an explicit output
+    /// where amount and asset are public
+    #[serde(default)]
+    pub allow_unconfidential: bool,
 }
 
 impl AddressAmount {
@@ -128,6 +132,10 @@ pub struct CreateTransaction {
     pub subaccount: u32,
     #[serde(default)]
     pub send_all: bool,
+    /// Liquid only, together with `send_all` sweep every asset of the given utxos to the single
+    /// addressee, which must be for the policy asset, the fee is deducted from the policy asset
+    #[serde(default)]
+    pub all_assets: bool,
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub previous_transaction: Option<TxListItem>,
@@ -172,6 +180,22 @@ pub struct GetUnspentOpt {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LoadStoreOpt {
     pub master_xpub: ExtendedPubKey,
+
+    /// Hex of a 32 bytes secret to encrypt the store with, instead of a key derived from
+    /// `master_xpub`
+    ///
+    /// It can be derived from the seed or protected with `encrypt_with_pin`. A store encrypted
+    /// with the key derived from `master_xpub` is migrated to this key, while a store encrypted
+    /// with a secret can't be loaded without it.
+    #[serde(default)]
+    pub encryption_key: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RotateStoreKeyOpt {
+    /// Hex of the 32 bytes secret to encrypt the store with from now on, if None the store is
+    /// encrypted again with the key derived from the master xpub
+    pub encryption_key: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -190,6 +214,49 @@ pub struct SetMasterBlindingKeyOpt {
     pub master_blinding_key: MasterBlindingKey,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExportWalletBackupResult {
+    /// The encrypted backup of settings, memos and subaccount settings, hex encoded
+    pub backup: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportWalletBackupOpt {
+    /// A backup returned by `export_wallet_backup` for the same wallet
+    pub backup: String,
+
+    /// Whether the values in the backup replace the conflicting ones in the store
+    ///
+    /// By default, values already set in the store are kept.
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImportWalletBackupResult {
+    /// Number of memos imported
+    pub memos: usize,
+
+    /// Number of subaccount settings imported
+    pub subaccounts: usize,
+
+    /// Whether the wallet settings were imported
+    pub settings: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VerifyStoreResult {
+    /// Whether the cache was loaded and no inconsistencies were found
+    pub valid: bool,
+
+    /// The error encountered loading the cache, which was then reset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_load_error: Option<String>,
+
+    /// A description of every inconsistency found in the cache
+    pub problems: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct GetAddressOpt {
     pub subaccount: u32,
@@ -278,6 +345,38 @@ pub struct SPVVerifyTxParams {
     pub height: u32,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SPVTx {
+    /// The `txid` of the transaction
+    pub txid: String,
+
+    /// The `height` of the block containing the transaction
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SPVVerifyTxsParams {
+    #[serde(flatten)]
+    pub params: SPVCommonParams,
+
+    /// The transactions to verify
+    pub txs: Vec<SPVTx>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SPVVerifyTxsItem {
+    #[serde(flatten)]
+    pub tx: SPVTx,
+
+    pub result: SPVVerifyTxResult,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SPVVerifyTxsResult {
+    /// The verification result of every transaction, in the same order of the request
+    pub txs: Vec<SPVVerifyTxsItem>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct SPVDownloadHeadersParams {
     #[serde(flatten)]
@@ -298,6 +397,55 @@ pub struct SPVDownloadHeadersResult {
     pub reorg: bool,
 }
 
+/// A fork between the local headers chain and the chain of a server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SPVFork {
+    /// The height of the last block in common
+    pub common_ancestor: u32,
+
+    /// The height of the tip of the server chain
+    pub fork_height: u32,
+
+    /// The absolute difference of work between the two branches since the common ancestor, in hex
+    pub work_diff: String,
+
+    /// Whether the local branch has less work than the server one
+    pub local_is_minority: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SPVServerStatus {
+    pub server: String,
+
+    /// The height of the tip of the server, None if the server couldn't be checked
+    pub tip_height: Option<u32>,
+
+    /// The time needed to cross-validate the local chain against the server
+    pub response_time_ms: u64,
+
+    /// The fork detected with this server, if any
+    pub fork: Option<SPVFork>,
+
+    /// The error occurred cross-validating against this server, if any
+    pub error: Option<String>,
+
+    /// Seconds since the unix epoch of the last check
+    pub last_checked: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SPVStatus {
+    /// Height and hash of the tip of the local headers chain
+    pub tip_height: u32,
+    pub tip_hash: String,
+
+    /// Whether the local headers chain is on a minority fork according to the last cross-validation
+    pub minority_fork: bool,
+
+    /// The servers used to cross-validate the local headers chain, with the last check outcome
+    pub servers: Vec<SPVServerStatus>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum SPVVerifyTxResult {
@@ -347,6 +495,11 @@ pub struct TransactionMeta {
     #[serde(rename = "transaction_locktime")]
     pub lock_time: u32,
     pub transaction_outputs: Vec<TransactionOutput>,
+    /// Non-fatal issues the caller should show to the user before sending, e.g. outputs
+    /// revealing amount and asset
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
 }
 
 impl From<BETransaction> for TransactionMeta {
@@ -382,6 +535,7 @@ impl From<BETransaction> for TransactionMeta {
             version: transaction.version(),
             lock_time: transaction.lock_time(),
             transaction_outputs: vec![],
+            warnings: vec![],
         }
     }
 }
@@ -584,6 +738,42 @@ pub struct TxListItem {
     pub transaction_size: usize,
     pub transaction_vsize: usize,
     pub transaction_weight: usize,
+    /// Set if the transaction, while unconfirmed, has been double spent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<TxConflict>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictType {
+    /// The transaction signaled replaceability (BIP125) and has been replaced
+    Replaced,
+
+    /// The transaction didn't signal replaceability and has been double spent anyway
+    Conflicted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxConflict {
+    #[serde(rename = "type")]
+    pub type_: ConflictType,
+
+    /// The txid of the transaction spending some of the same outputs
+    pub txhash: String,
+}
+
+impl TxConflict {
+    /// The conflict of `tx`, double spent by `txhash`
+    pub fn new(tx: &BETransaction, txhash: String) -> Self {
+        TxConflict {
+            type_: if tx.rbf_optin() {
+                ConflictType::Replaced
+            } else {
+                ConflictType::Conflicted
+            },
+            txhash,
+        }
+    }
 }
 
 // Negative (sent) amounts are expected to be provided as positive numbers.
@@ -647,6 +837,10 @@ pub struct Credentials {
     pub mnemonic: String,
     #[serde(default)]
     pub bip39_passphrase: String,
+
+    /// Hex of the 32 bytes secret the store is encrypted with, see [`LoadStoreOpt`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption_key: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -667,6 +861,148 @@ pub struct AddressPointer {
 // This one is simple enough to derive a serializer
 #[derive(Serialize, Debug, Clone, Deserialize)]
 pub struct FeeEstimate(pub u64);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FeeEstimatesByTarget {
+    /// The minimum relay fee, in satoshi per 1000 bytes
+    pub relay_fee: u64,
+
+    /// The fee rate, in satoshi per 1000 bytes, to confirm within the number of blocks of the key
+    pub estimates: BTreeMap<u32, u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EstimateConfirmationTimeOpt {
+    /// The txid of a wallet transaction in the mempool
+    pub txid: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EstimateConfirmationTimeResult {
+    /// The fee rate paid by the transaction, in satoshi per 1000 bytes
+    pub fee_rate: u64,
+
+    /// The number of blocks expected before the transaction is confirmed
+    pub blocks: u32,
+
+    /// The expected time before the transaction is confirmed
+    pub minutes: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GetMaxSendableOpt {
+    pub subaccount: u32,
+
+    /// The asset to send, defaults to the policy asset on Liquid and must be omitted on Bitcoin
+    #[serde(default)]
+    pub asset_id: Option<String>,
+
+    /// In satoshi per 1000 bytes, defaults to the minimum fee rate of the network
+    #[serde(default)]
+    pub fee_rate: Option<u64>,
+
+    /// The address receiving the amount, its type affects the size of the transaction
+    pub address: String,
+
+    #[serde(default)]
+    pub allow_unconfidential: bool,
+
+    /// Minimum number of confirmations of the coins considered
+    #[serde(default)]
+    pub num_confs: u32,
+
+    #[serde(default)]
+    pub confidential_utxos_only: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct GetMaxSendableResult {
+    /// The greatest amount of `asset_id` that can be sent, 0 if the fee can't be paid
+    pub satoshi: u64,
+
+    /// The fee paid, in the policy asset, when sending `satoshi`
+    pub fee: u64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asset_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PreviewFeeResult {
+    /// The fee the transaction would pay, in satoshi of the policy asset
+    pub fee: u64,
+
+    /// The fee rate used, in satoshi per 1000 bytes
+    pub fee_rate: u64,
+
+    /// The amount sent by the transaction for every asset, fee included
+    pub satoshi: Balances,
+
+    /// The number of change outputs the transaction would have
+    pub changes_used: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SubaccountSyncStatus {
+    pub subaccount: u32,
+
+    /// The number of scripts of the external chain whose history has been requested
+    pub external_scripts: u32,
+
+    /// The number of scripts of the internal (change) chain whose history has been requested
+    pub internal_scripts: u32,
+
+    /// The number of transactions found in the history of the scripts
+    pub txs_discovered: usize,
+
+    /// The number of discovered transactions available locally
+    pub txs_downloaded: usize,
+
+    /// Whether every script of the subaccount has been scanned and every transaction downloaded
+    pub synced: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SyncStatus {
+    /// The number of subaccounts synced so far.
+    pub synced_subaccounts: usize,
+
+    /// The number of subaccounts to sync.
+    pub total_subaccounts: usize,
+
+    /// Whether the initial sync of the wallet transactions is completed.
+    pub completed: bool,
+
+    pub subaccounts: Vec<SubaccountSyncStatus>,
+
+    /// The height of the local headers chain, None if SPV is not enabled or on Liquid
+    pub headers_height: Option<u32>,
+
+    /// The height of the tip of the server
+    pub tip_height: u32,
+}
+
+impl SyncStatus {
+    /// Returns the status of `subaccount`, adding it if missing
+    pub fn subaccount_mut(&mut self, subaccount: u32) -> &mut SubaccountSyncStatus {
+        let pos = match self.subaccounts.iter().position(|s| s.subaccount == subaccount) {
+            Some(pos) => pos,
+            None => {
+                self.subaccounts.push(SubaccountSyncStatus {
+                    subaccount,
+                    ..Default::default()
+                });
+                self.subaccounts.len() - 1
+            }
+        };
+        &mut self.subaccounts[pos]
+    }
+
+    /// Whether the wallet is synced and the headers chain, if any, reached the tip
+    pub fn is_complete(&self) -> bool {
+        self.completed && self.headers_height.map_or(true, |h| h >= self.tip_height)
+    }
+}
 pub struct TxsResult(pub Vec<TxListItem>);
 
 /// Change to the model of Settings and Pricing structs could break old versions.
@@ -972,6 +1308,63 @@ impl From<&BETransactionEntry> for TransactionDetails {
     }
 }
 
+// Output of get_unblinded_transaction
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnblindedTransaction {
+    pub txhash: String,
+
+    /// The outputs of the transaction belonging to the wallet, with their unblinded values.
+    pub outputs: Vec<UnblindedOutput>,
+
+    /// The fragment to append to a Blockstream explorer transaction url to show the unblinded
+    /// values of `outputs`, e.g. `#blinded=<satoshi>,<asset>,<amountblinder>,<assetblinder>,...`
+    pub url_fragment: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnblindedOutput {
+    pub pt_idx: u32,
+    pub asset_id: String,
+    pub satoshi: u64,
+    #[serde(rename = "assetblinder")]
+    pub asset_blinder: String,
+    #[serde(rename = "amountblinder")]
+    pub amount_blinder: String,
+}
+
+impl UnblindedOutput {
+    pub fn new(pt_idx: u32, secrets: &elements::TxOutSecrets) -> Self {
+        Self {
+            pt_idx,
+            asset_id: secrets.asset.to_hex(),
+            satoshi: secrets.value,
+            asset_blinder: secrets.asset_bf.to_hex(),
+            amount_blinder: secrets.value_bf.to_hex(),
+        }
+    }
+}
+
+impl UnblindedTransaction {
+    pub fn new(txhash: String, outputs: Vec<UnblindedOutput>) -> Self {
+        let values: Vec<_> = outputs
+            .iter()
+            .map(|o| {
+                format!("{},{},{},{}", o.satoshi, o.asset_id, o.amount_blinder, o.asset_blinder)
+            })
+            .collect();
+        let url_fragment = if values.is_empty() {
+            String::new()
+        } else {
+            format!("#blinded={}", values.join(","))
+        };
+        Self {
+            txhash,
+            outputs,
+            url_fragment,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct GetPreviousAddressesOpt {
     /// The subaccount to get the addresses for.
@@ -1043,7 +1436,10 @@ pub struct PreviousAddresses {
 
 #[cfg(test)]
 mod test {
-    use crate::model::{parse_path, CreateTxUtxos, GetUnspentOutputs};
+    use crate::model::{
+        parse_path, CreateTxUtxos, GetUnspentOutputs, SyncStatus, UnblindedOutput,
+        UnblindedTransaction,
+    };
     use bitcoin::util::bip32::DerivationPath;
 
     #[test]
@@ -1060,4 +1456,46 @@ mod test {
         let _json: GetUnspentOutputs = serde_json::from_str(json_str).unwrap();
         let _json: CreateTxUtxos = serde_json::from_str(json_str).unwrap();
     }
+
+    #[test]
+    fn test_unblinded_url_fragment() {
+        let output = |pt_idx, satoshi| UnblindedOutput {
+            pt_idx,
+            asset_id: "aa".repeat(32),
+            satoshi,
+            asset_blinder: "bb".repeat(32),
+            amount_blinder: "cc".repeat(32),
+        };
+        let tx = UnblindedTransaction::new("00".repeat(32), vec![]);
+        assert_eq!(tx.url_fragment, "");
+
+        let tx = UnblindedTransaction::new("00".repeat(32), vec![output(0, 1000), output(2, 42)]);
+        let expected = format!(
+            "#blinded=1000,{a},{c},{b},42,{a},{c},{b}",
+            a = "aa".repeat(32),
+            b = "bb".repeat(32),
+            c = "cc".repeat(32)
+        );
+        assert_eq!(tx.url_fragment, expected);
+    }
+
+    #[test]
+    fn test_sync_status() {
+        let mut status = SyncStatus::default();
+        status.subaccount_mut(1).txs_discovered = 3;
+        status.subaccount_mut(0).external_scripts = 20;
+        status.subaccount_mut(1).txs_downloaded = 2;
+        assert_eq!(status.subaccounts.len(), 2);
+        assert_eq!(status.subaccounts[0].subaccount, 1);
+        assert_eq!(status.subaccounts[0].txs_downloaded, 2);
+        assert!(!status.is_complete());
+
+        status.completed = true;
+        status.tip_height = 120;
+        assert!(status.is_complete());
+        status.headers_height = Some(100);
+        assert!(!status.is_complete());
+        status.headers_height = Some(120);
+        assert!(status.is_complete());
+    }
 }
@@ -41,6 +41,11 @@ pub struct NetworkParameters {
     pub use_tor: Option<bool>,
     pub max_reorg_blocks: Option<u32>,
 
+    /// Height and hex serialized header of a difficulty retarget block, when set a new SPV
+    /// headers chain starts from this block instead of the genesis block
+    pub spv_checkpoint_height: Option<u32>,
+    pub spv_checkpoint_header: Option<String>,
+
     /// For electrum sessions is used as root directory for the db cache and for
     /// the headers chain files
     ///
@@ -1,5 +1,5 @@
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::str::FromStr;
 
@@ -16,10 +16,10 @@ use bitcoin::{PublicKey, SigHashType};
 use elements::confidential::Value;
 
 use gdk_common::be::{
-    BEAddress, BEOutPoint, BEScript, BEScriptConvert, BETransaction, BETxid, ScriptBatch,
+    BEAddress, BEOutPoint, BEScript, BEScriptConvert, BETransaction, BETxid, CtParams, ScriptBatch,
     DUST_VALUE,
 };
-use gdk_common::error::fn_err;
+use gdk_common::error::{fn_err, Error as CommonError};
 use gdk_common::model::{
     parse_path, AccountInfo, AddressAmount, AddressPointer, CreateTransaction,
     GetPreviousAddressesOpt, GetTransactionsOpt, GetTxInOut, PreviousAddress, PreviousAddresses,
@@ -286,9 +286,16 @@ impl Account {
         let num_confs = opt.num_confs.unwrap_or(0);
 
         let mut txs = vec![];
+        // double spent transactions are listed as unconfirmed
+        let conflicted = acc_store
+            .conflicts
+            .keys()
+            .filter(|txid| !acc_store.heights.contains_key(*txid))
+            .map(|txid| (txid, &None));
         let mut my_txids: Vec<(&BETxid, &Option<u32>)> = acc_store
             .heights
             .iter()
+            .chain(conflicted)
             .filter(|(_, height)| {
                 num_confs <= height.map_or(0, |height| (tip_height + 1).saturating_sub(height))
             })
@@ -347,8 +354,9 @@ impl Account {
                 SPVVerifyTxResult::Disabled
             };
 
+            let conflict = acc_store.conflicts.get(*tx_id).cloned();
             let rbf_optin = tx.rbf_optin();
-            let can_rbf = height.is_none() && rbf_optin && user_signed;
+            let can_rbf = height.is_none() && rbf_optin && user_signed && conflict.is_none();
 
             let inputs = tx
                 .previous_outputs()
@@ -499,6 +507,7 @@ impl Account {
                 transaction_size: txe.size,
                 transaction_vsize: weight_to_vsize(txe.weight),
                 transaction_weight: txe.weight,
+                conflict,
             });
         }
         info!("list_tx {:?}", txs.iter().map(|e| &e.txhash).collect::<Vec<&String>>());
@@ -739,12 +748,25 @@ impl Account {
                 let fee: u64 =
                     tx.output.iter().filter(|o| o.is_fee()).map(|o| o.minimum_value()).sum();
                 let tx = BETransaction::Elements(tx);
+                let vsize = weight_to_vsize(tx.get_weight());
                 info!(
                     "transaction final size is {} bytes and {} vbytes and fee is {}",
                     tx.serialize().len(),
-                    tx.get_weight() / 4,
+                    vsize,
                     fee
                 );
+                // the fee is computed on the estimated size of the blinded transaction, warn if
+                // the actual one is paying less than the requested fee rate
+                if let Some(fee_rate) = request.create_transaction.as_ref().and_then(|c| c.fee_rate)
+                {
+                    let actual_fee_rate = fee * 1000 / vsize as u64;
+                    if actual_fee_rate < fee_rate {
+                        warn!(
+                            "fee rate of the signed transaction {}sat/kb is below the requested {}sat/kb",
+                            actual_fee_rate, fee_rate
+                        );
+                    }
+                }
                 info!("FINALTX inputs:{} outputs:{}", tx.input_len(), tx.output_len());
                 tx.into()
             }
@@ -1070,7 +1092,7 @@ pub fn create_tx(
                     &addressee.address,
                     network.address_params(),
                 ) {
-                    if !address.is_blinded() {
+                    if !address.is_blinded() && !addressee.allow_unconfidential {
                         return Err(Error::NonConfidentialAddress);
                     }
                     if let elements::address::Payload::WitnessProgram {
@@ -1143,6 +1165,7 @@ pub fn create_tx(
                         address: bitcoin::Address::from_script(&o.script_pubkey, net)?.to_string(),
                         satoshi: o.value,
                         asset_id: None,
+                        allow_unconfidential: false,
                     })
                 })
                 .collect();
@@ -1209,29 +1232,43 @@ pub fn create_tx(
         if request.addressees.len() != 1 {
             return Err(Error::SendAll);
         }
-        let asset = request.addressees[0].asset_id();
-        let all_utxos: Vec<&Txo> = utxos.iter().filter(|u| u.asset_id() == asset).collect();
-        let total_amount_utxos: u64 = all_utxos.iter().map(|u| u.satoshi).sum();
-
-        let to_send = if asset == network.policy_asset_id().ok() {
-            let mut dummy_tx = BETransaction::new(network.id());
-            for utxo in all_utxos.iter() {
-                dummy_tx.add_input(utxo.outpoint.clone());
-            }
-            let out = &request.addressees[0]; // safe because we checked we have exactly one recipient
-            dummy_tx
-                .add_output(&out.address, out.satoshi, out.asset_id(), network.id())
-                .map_err(|_| Error::InvalidAddress)?;
-            // estimating 2 satoshi more as estimating less would later result in InsufficientFunds
-            let estimated_fee = dummy_tx.estimated_fee(fee_rate, 0, account.script_type) + 2;
-            total_amount_utxos.checked_sub(estimated_fee).ok_or_else(|| Error::InsufficientFunds)?
+        if request.all_assets {
+            sweep_all_assets(account, request, &utxos, fee_rate)?;
         } else {
-            total_amount_utxos
-        };
+            let asset = request.addressees[0].asset_id();
+            let all_utxos: Vec<&Txo> = utxos.iter().filter(|u| u.asset_id() == asset).collect();
+            let total_amount_utxos: u64 = all_utxos.iter().map(|u| u.satoshi).sum();
+
+            let to_send = if asset == network.policy_asset_id().ok() {
+                let mut dummy_tx = BETransaction::new(network.id());
+                for utxo in all_utxos.iter() {
+                    dummy_tx.add_input(utxo.outpoint.clone());
+                }
+                let out = &request.addressees[0]; // safe because we checked we have exactly one recipient
+                dummy_tx
+                    .add_output(
+                        &out.address,
+                        out.satoshi,
+                        out.asset_id(),
+                        network.id(),
+                        out.allow_unconfidential,
+                    )
+                    .map_err(|_| Error::InvalidAddress)?;
+                // estimating 2 satoshi more as estimating less would later result in InsufficientFunds
+                let estimated_fee =
+                    dummy_tx.estimated_fee(fee_rate, 0, account.script_type, CtParams::default())
+                        + 2;
+                total_amount_utxos
+                    .checked_sub(estimated_fee)
+                    .ok_or_else(|| Error::InsufficientFunds)?
+            } else {
+                total_amount_utxos
+            };
 
-        info!("send_all asset: {:?} to_send:{}", asset, to_send);
+            info!("send_all asset: {:?} to_send:{}", asset, to_send);
 
-        request.addressees[0].satoshi = to_send;
+            request.addressees[0].satoshi = to_send;
+        }
     }
 
     // transaction is created in 3 steps:
@@ -1246,7 +1283,13 @@ pub fn create_tx(
             let mut new_tx = BETransaction::new(network.id());
             for out in request.addressees.iter() {
                 new_tx
-                    .add_output(&out.address, out.satoshi, out.asset_id(), network.id())
+                    .add_output(
+                        &out.address,
+                        out.satoshi,
+                        out.asset_id(),
+                        network.id(),
+                        out.allow_unconfidential,
+                    )
                     .map_err(|_| Error::InvalidAddress)?;
             }
             Ok(new_tx)
@@ -1268,6 +1311,7 @@ pub fn create_tx(
                     &acc_store.all_txs,
                     &acc_store.unblinded,
                     account.script_type,
+                    CtParams::default(),
                 ); // "policy asset" is last, in bitcoin max 1 element
                 info!("needs: {:?}", needs);
                 if needs.is_empty() {
@@ -1321,6 +1365,7 @@ pub fn create_tx(
                 &acc_store.all_txs,
                 &acc_store.unblinded,
                 account.script_type,
+                CtParams::default(),
             );
             if !needs.is_empty() {
                 return Err(Error::InsufficientFunds);
@@ -1333,13 +1378,19 @@ pub fn create_tx(
         fee_rate,
         tx.estimated_changes(send_all, &acc_store.all_txs, &acc_store.unblinded),
         account.script_type,
+        CtParams::default(),
     );
-    let changes = tx.changes(
-        estimated_fee,
-        network.policy_asset_id().ok(),
-        &acc_store.all_txs,
-        &acc_store.unblinded,
-    ); // Vec<Change> asset, value
+    let changes = tx
+        .changes(
+            estimated_fee,
+            network.policy_asset_id().ok(),
+            &acc_store.all_txs,
+            &acc_store.unblinded,
+        )
+        .map_err(|e| match e {
+            CommonError::InsufficientFunds => Error::InsufficientFunds,
+            e => e.into(),
+        })?; // Vec<Change> asset, value
     for (i, change) in changes.iter().enumerate() {
         let change_address = change_addresses.pop().map_or_else(
             || -> Result<_, Error> {
@@ -1352,7 +1403,7 @@ pub fn create_tx(
             "adding change to {} of {} asset {:?}",
             &change_address, change.satoshi, change.asset
         );
-        tx.add_output(&change_address, change.satoshi, change.asset, network.id())?;
+        tx.add_output(&change_address, change.satoshi, change.asset, network.id(), false)?;
     }
 
     // randomize inputs and outputs, BIP69 has been rejected because lacks wallets adoption
@@ -1390,11 +1441,84 @@ pub fn create_tx(
     created_tx.transaction_outputs = tx_outputs;
     created_tx.changes_used = Some(changes.len() as u32);
     created_tx.addressees_read_only = request.previous_transaction.is_some();
+    if let NetworkId::Elements(net) = network.id() {
+        for out in request.addressees.iter().filter(|a| a.allow_unconfidential) {
+            let address = elements::Address::parse_with_params(&out.address, net.address_params());
+            if address.map_or(false, |a| !a.is_blinded()) {
+                created_tx.warnings.push(format!(
+                    "output to non-confidential address {} reveals its amount and asset",
+                    out.address
+                ));
+            }
+        }
+    }
     info!("returning: {:?}", created_tx);
 
     Ok(created_tx)
 }
 
+/// Replace the only addressee of a `send_all` request with one addressee for every asset in
+/// `utxos`, all sending to the same address.
+///
+/// The policy asset addressee is added last and the fee, estimated with every other output in
+/// place, is deducted from it.
+fn sweep_all_assets(
+    account: &Account,
+    request: &mut CreateTransaction,
+    utxos: &[Txo],
+    fee_rate: f64,
+) -> Result<(), Error> {
+    let network = &account.network;
+    let policy_asset = match network.id() {
+        NetworkId::Elements(_) => network.policy_asset_id()?,
+        NetworkId::Bitcoin(_) => return Err(Error::SendAll),
+    };
+    let addressee = request.addressees.pop().expect("checked exactly one addressee");
+    if addressee.asset_id() != Some(policy_asset) {
+        return Err(Error::SendAll);
+    }
+
+    let mut totals: HashMap<elements::issuance::AssetId, u64> = HashMap::new();
+    for utxo in utxos.iter() {
+        let asset = utxo.asset_id().expect("liquid utxos have an asset");
+        *totals.entry(asset).or_insert(0) += utxo.satoshi;
+    }
+    let policy_total = totals.remove(&policy_asset).ok_or(Error::InsufficientFunds)?;
+
+    for (asset, satoshi) in totals {
+        request.addressees.push(AddressAmount {
+            asset_id: Some(asset.to_hex()),
+            satoshi,
+            ..addressee.clone()
+        });
+    }
+    request.addressees.push(addressee);
+
+    let mut dummy_tx = BETransaction::new(network.id());
+    for utxo in utxos.iter() {
+        dummy_tx.add_input(utxo.outpoint.clone());
+    }
+    for out in request.addressees.iter() {
+        dummy_tx
+            .add_output(
+                &out.address,
+                out.satoshi,
+                out.asset_id(),
+                network.id(),
+                out.allow_unconfidential,
+            )
+            .map_err(|_| Error::InvalidAddress)?;
+    }
+    // estimating 2 satoshi more as estimating less would later result in InsufficientFunds
+    let estimated_fee =
+        dummy_tx.estimated_fee(fee_rate, 0, account.script_type, CtParams::default()) + 2;
+    let to_send = policy_total.checked_sub(estimated_fee).ok_or(Error::InsufficientFunds)?;
+    info!("send_all all assets: {} to_send:{}", request.addressees.len(), to_send);
+
+    request.addressees.last_mut().expect("policy asset addressee pushed").satoshi = to_send;
+    Ok(())
+}
+
 fn internal_sign_bitcoin(
     tx: &bitcoin::Transaction,
     input_index: usize,
@@ -1509,6 +1633,8 @@ fn blind_tx(account: &Account, tx: &elements::Transaction) -> Result<elements::T
     }
 
     let inp_txout_sec: Vec<_> = inp_txout_sec.iter().map(|e| e.as_ref()).collect();
+    // `blind_last` does not allow to choose the rangeproof parameters, it uses the Elements
+    // defaults so the fees are estimated with `CtParams::default()`
     pset.blind_last(&mut rand::thread_rng(), &crate::EC, &inp_txout_sec[..])?;
     pset.extract_tx().map_err(Into::into)
 }
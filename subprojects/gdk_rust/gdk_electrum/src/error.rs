@@ -74,6 +74,9 @@ pub enum Error {
     #[error("invalid asset id")]
     InvalidAssetId,
 
+    #[error("commitments of output {0} do not match the unblinded values")]
+    InvalidCommitments(u32),
+
     #[error("Invalid Electrum URL: {0}")]
     InvalidElectrumUrl(String),
 
@@ -95,6 +98,9 @@ pub enum Error {
     #[error("invalid replacement request fields")]
     InvalidReplacementRequest,
 
+    #[error("the store encryption key is missing or does not match")]
+    InvalidStoreKey,
+
     #[error(transparent)]
     InvalidStringUtf8(#[from] std::string::FromUtf8Error),
 
@@ -104,6 +110,9 @@ pub enum Error {
     #[error("invalid subaccount {0}")]
     InvalidSubaccount(u32),
 
+    #[error("invalid wallet backup: {0}")]
+    InvalidWalletBackup(String),
+
     #[error("Xpubs mismatch ({0} vs {1})")]
     MismatchingXpubs(ExtendedPubKey, ExtendedPubKey),
 
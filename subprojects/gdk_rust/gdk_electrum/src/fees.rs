@@ -0,0 +1,201 @@
+//! Fee estimation blending the `estimatefee` values of the server with the current content of
+//! its mempool, as returned by `mempool.get_fee_histogram`.
+
+use crate::error::Error;
+use crate::store::Store;
+use electrum_client::{Batch, Client, ElectrumApi};
+use gdk_common::model::FeeEstimate;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// Maximum virtual size of the transactions confirmed in a block
+pub const BLOCK_VSIZE: u64 = 1_000_000;
+
+/// The number of confirmation targets estimated, from 1 block up to this value
+pub const MAX_TARGET: usize = 24;
+
+/// The mempool fee histogram, pairs of fee rate in satoshi per virtual byte and the virtual size
+/// of the mempool transactions paying about that fee rate, sorted by decreasing fee rate
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct FeeHistogram(pub Vec<(f64, u64)>);
+
+impl FeeHistogram {
+    /// The fee rate, in satoshi per 1000 bytes, a transaction must pay to be confirmed within
+    /// `target` blocks if no other transaction enters the mempool.
+    ///
+    /// Returns 0 if the whole mempool is expected to be confirmed within `target` blocks.
+    pub fn fee_rate(&self, target: usize) -> u64 {
+        let limit = target as u64 * BLOCK_VSIZE;
+        let mut cumulative = 0u64;
+        for (rate, vsize) in self.0.iter() {
+            cumulative += vsize;
+            if cumulative >= limit {
+                return (rate * 1000.0) as u64;
+            }
+        }
+        0
+    }
+
+    /// The number of blocks expected for a transaction paying `fee_rate`, in satoshi per 1000
+    /// bytes, to be confirmed, considering the mempool transactions paying at least as much
+    pub fn confirmation_blocks(&self, fee_rate: u64) -> u32 {
+        let ahead: u64 = self
+            .0
+            .iter()
+            .filter(|(rate, _)| (rate * 1000.0) as u64 >= fee_rate)
+            .map(|(_, vsize)| vsize)
+            .sum();
+        (ahead / BLOCK_VSIZE) as u32 + 1
+    }
+
+    /// Whether the estimates of the server should be used alone
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Blend the `estimatefee` values of the server, in satoshi per 1000 bytes for the targets from
+/// 1 block, with the fee rates needed according to the mempool `histogram`.
+///
+/// The returned estimates are indexed by the target in blocks, the first element being the
+/// `relay_fee`. They are never lower than the `relay_fee` and never increase with the target.
+pub fn blend(
+    relay_fee: u64,
+    server_estimates: &[u64],
+    histogram: &FeeHistogram,
+) -> Vec<FeeEstimate> {
+    // The server returns a negative estimate, mapped to 0, when it doesn't have enough data, while
+    // the histogram rate is 0 when it doesn't constrain the target
+    let blended: Vec<Option<u64>> = server_estimates
+        .iter()
+        .enumerate()
+        .map(|(i, server_estimate)| match (*server_estimate, histogram.fee_rate(i + 1)) {
+            (0, 0) => None,
+            (0, histogram_rate) => Some(histogram_rate),
+            (server_estimate, 0) => Some(server_estimate),
+            (server_estimate, histogram_rate) => Some((server_estimate + histogram_rate) / 2),
+        })
+        .collect();
+    let mut estimates = vec![FeeEstimate(relay_fee)];
+    let mut previous: Option<u64> = None;
+    for (i, estimate) in blended.iter().enumerate() {
+        // A missing estimate is skipped, taking the one of the previous target or else of the
+        // first following target having one
+        let estimate = estimate
+            .or(previous)
+            .or_else(|| blended[i..].iter().flatten().next().copied())
+            .unwrap_or(relay_fee);
+        let estimate = estimate.max(relay_fee).min(previous.unwrap_or(u64::MAX));
+        previous = Some(estimate);
+        estimates.push(FeeEstimate(estimate));
+    }
+    estimates
+}
+
+/// The number of blocks expected for a transaction paying `fee_rate`, in satoshi per 1000 bytes,
+/// to be confirmed according to the fee `estimates` returned by [`blend`]
+///
+/// Returns None if `fee_rate` is lower than all the estimates.
+pub fn estimates_confirmation_blocks(estimates: &[FeeEstimate], fee_rate: u64) -> Option<u32> {
+    estimates.iter().skip(1).position(|e| e.0 <= fee_rate).map(|i| i as u32 + 1)
+}
+
+/// Download the mempool fee histogram, an empty one is returned if the server doesn't support it
+fn get_fee_histogram(client: &Client) -> FeeHistogram {
+    let mut batch = Batch::default();
+    batch.raw("mempool.get_fee_histogram".into(), vec![]);
+    let histogram = client
+        .batch_call(&batch)
+        .map_err(Error::from)
+        .and_then(|mut values| Ok(serde_json::from_value(values.pop().unwrap_or_default())?));
+    match histogram {
+        Ok(histogram) => histogram,
+        Err(e) => {
+            warn!("can't get the mempool fee histogram {:?}", e);
+            FeeHistogram::default()
+        }
+    }
+}
+
+/// Returns the fee estimates and the mempool fee histogram they have been computed with
+pub fn try_get_fee_estimates(client: &Client) -> Result<(Vec<FeeEstimate>, FeeHistogram), Error> {
+    let relay_fee = (client.relay_fee()? * 100_000_000.0) as u64;
+    let blocks: Vec<usize> = (1..=MAX_TARGET).collect();
+    // negative estimates are mapped to 0
+    let server_estimates: Vec<u64> = client
+        .batch_estimate_fee(blocks)?
+        .iter()
+        .map(|e| (*e * 100_000_000.0).max(0.0) as u64)
+        .collect();
+    let histogram = get_fee_histogram(client);
+    Ok((blend(relay_fee, &server_estimates, &histogram), histogram))
+}
+
+/// Update the fee estimates and the mempool fee histogram in the cache of `store`
+pub fn update_fee_estimates(store: &Store, client: &Client) -> Result<Vec<FeeEstimate>, Error> {
+    let (fee_estimates, histogram) = try_get_fee_estimates(client)?;
+    info!("updated fee estimates {:?}", fee_estimates);
+    let mut store_write = store.write()?;
+    store_write.cache.fee_estimates = fee_estimates.clone();
+    store_write.cache.fee_histogram = histogram;
+    Ok(fee_estimates)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rates(estimates: Vec<FeeEstimate>) -> Vec<u64> {
+        estimates.into_iter().map(|e| e.0).collect()
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = FeeHistogram(vec![(50.0, 600_000), (20.0, 600_000), (5.0, 1_000_000)]);
+        assert_eq!(histogram.fee_rate(1), 20_000);
+        assert_eq!(histogram.fee_rate(2), 5_000);
+        assert_eq!(histogram.fee_rate(3), 0);
+        assert_eq!(FeeHistogram::default().fee_rate(1), 0);
+
+        assert_eq!(histogram.confirmation_blocks(60_000), 1);
+        assert_eq!(histogram.confirmation_blocks(20_000), 2);
+        assert_eq!(histogram.confirmation_blocks(1_000), 3);
+    }
+
+    #[test]
+    fn test_blend() {
+        let histogram = FeeHistogram(vec![(50.0, 600_000), (20.0, 600_000), (5.0, 1_000_000)]);
+        let server = vec![30_000, 10_000, 0, 12_000];
+        assert_eq!(
+            rates(blend(1_000, &server, &histogram)),
+            vec![1_000, 25_000, 7_500, 7_500, 7_500]
+        );
+        assert_eq!(
+            rates(blend(1_000, &server, &FeeHistogram::default())),
+            vec![1_000, 30_000, 10_000, 10_000, 10_000]
+        );
+
+        // a histogram rate of 0 doesn't constrain the server estimates
+        let small = FeeHistogram(vec![(50.0, 1_000)]);
+        assert_eq!(
+            rates(blend(1_000, &server, &small)),
+            vec![1_000, 30_000, 10_000, 10_000, 10_000]
+        );
+
+        // missing estimates take the one of the following target if there is no previous one
+        assert_eq!(
+            rates(blend(1_000, &[0, 8_000, 0], &FeeHistogram::default())),
+            vec![1_000, 8_000, 8_000, 8_000]
+        );
+        assert_eq!(rates(blend(1_000, &[0, 0], &FeeHistogram::default())), vec![1_000; 3]);
+    }
+
+    #[test]
+    fn test_estimates_confirmation_blocks() {
+        let estimates = blend(1_000, &[30_000, 10_000, 5_000], &FeeHistogram::default());
+        assert_eq!(estimates_confirmation_blocks(&estimates, 40_000), Some(1));
+        assert_eq!(estimates_confirmation_blocks(&estimates, 10_000), Some(2));
+        assert_eq!(estimates_confirmation_blocks(&estimates, 6_000), Some(3));
+        assert_eq!(estimates_confirmation_blocks(&estimates, 1_000), None);
+    }
+}
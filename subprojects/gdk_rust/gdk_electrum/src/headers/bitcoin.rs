@@ -6,7 +6,8 @@ use bitcoin::consensus::{deserialize, serialize};
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::{BlockHash, Txid};
 use bitcoin::{BlockHeader, Network};
-use electrum_client::GetMerkleRes;
+use electrum_client::{Client, ElectrumApi, GetMerkleRes};
+use gdk_common::{NetworkId, NetworkParameters};
 use log::{info, warn};
 use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
@@ -25,9 +26,75 @@ lazy_static! {
     };
 }
 
+/// A trusted block header at a difficulty retarget height, used to start a chain of headers
+/// without downloading all the headers from the genesis block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    height: u32,
+    header: BlockHeader,
+}
+
+impl Checkpoint {
+    pub fn new(height: u32, header: BlockHeader) -> Result<Checkpoint, Error> {
+        if height % DIFFCHANGE_INTERVAL != 0 {
+            return Err(Error::Generic(format!(
+                "checkpoint height {} is not a difficulty retarget height",
+                height
+            )));
+        }
+        Ok(Checkpoint {
+            height,
+            header,
+        })
+    }
+
+    /// Returns the checkpoint configured in the network parameters, if any
+    ///
+    /// The checkpoint must have a valid proof of work and must not contradict the headers compiled
+    /// in this library, see [`Checkpoint::validate`].
+    pub fn from_params(network: &NetworkParameters) -> Result<Option<Checkpoint>, Error> {
+        match (network.spv_checkpoint_height, network.spv_checkpoint_header.as_ref()) {
+            (Some(height), Some(header)) => {
+                let header = deserialize(&Vec::<u8>::from_hex(header)?)?;
+                let checkpoint = Checkpoint::new(height, header)?;
+                match network.id() {
+                    NetworkId::Bitcoin(network) => checkpoint.validate(network)?,
+                    NetworkId::Elements(_) => {
+                        return Err(Error::Generic("spv checkpoints are only for bitcoin".into()))
+                    }
+                }
+                Ok(Some(checkpoint))
+            }
+            (None, None) => Ok(None),
+            _ => Err(Error::Generic("incomplete spv checkpoint parameters".into())),
+        }
+    }
+
+    /// Check the checkpoint against the headers at retarget heights compiled in this library
+    fn validate(&self, network: Network) -> Result<(), Error> {
+        if self.header.validate_pow(&self.header.target()).is_err() {
+            return Err(Error::Generic(format!(
+                "checkpoint header {} has an invalid proof of work",
+                self.header.block_hash()
+            )));
+        }
+        match retarget_checkpoints(network).get(&self.height) {
+            Some(hash) if *hash != self.header.block_hash() => Err(Error::Generic(format!(
+                "checkpoint {} {} contradicts the compiled in {}",
+                self.height,
+                self.header.block_hash(),
+                hash
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct HeadersChain {
     path: PathBuf,
+    /// height of the first header persisted in the file, 0 unless started from a checkpoint
+    base: u32,
     height: u32,
     last: BlockHeader,
     checkpoints: HashMap<u32, BlockHash>,
@@ -40,21 +107,40 @@ impl HeadersChain {
     ///
     /// if the file doesn't exist, a chain with only the genesis block (relative to `network`) is returned
     pub fn new<P: AsRef<Path>>(path: P, network: Network) -> Result<HeadersChain, Error> {
+        HeadersChain::with_checkpoint(path, network, None)
+    }
+
+    /// Like [`HeadersChain::new`], but if no chain starting from the genesis block has been
+    /// persisted and a `checkpoint` is given, the chain starts from the checkpoint header.
+    ///
+    /// Headers below the checkpoint are not available, so transactions confirmed before it can't
+    /// be verified.
+    pub fn with_checkpoint<P: AsRef<Path>>(
+        path: P,
+        network: Network,
+        checkpoint: Option<Checkpoint>,
+    ) -> Result<HeadersChain, Error> {
         std::fs::create_dir_all(path.as_ref())?;
         let mut filepath: PathBuf = path.as_ref().into();
         filepath.push(format!("headers_chain_{}", network));
+        let (base, first) = match checkpoint {
+            Some(checkpoint) if !filepath.exists() => {
+                filepath.set_file_name(format!("headers_chain_{}_{}", network, checkpoint.height));
+                (checkpoint.height, checkpoint.header)
+            }
+            _ => (0, genesis_block(network).header),
+        };
         let checkpoints = get_checkpoints(network);
         if !filepath.exists() {
             info!("{:?} chain file doesn't exist, creating", filepath);
-            let last = genesis_block(network).header;
             let mut file = File::create(&filepath)?;
-            file.write_all(&serialize(&last))?;
-            let height = 0;
+            file.write_all(&serialize(&first))?;
 
             Ok(HeadersChain {
                 path: filepath,
-                height,
-                last,
+                base,
+                height: base,
+                last: first,
                 checkpoints,
                 network,
             })
@@ -65,19 +151,24 @@ impl HeadersChain {
             if file_size % 80 != 0 || file_size < 80 {
                 return Err(Error::InvalidHeaders);
             }
+            let mut buf = [0u8; 80];
+            file.read_exact(&mut buf)?;
+            if deserialize::<BlockHeader>(&buf)? != first {
+                return Err(Error::InvalidHeaders);
+            }
             let wanted_seek = file_size - 80;
             let effective_seek = file.seek(SeekFrom::Start(wanted_seek))?;
             if wanted_seek != effective_seek {
                 warn!("Seek failed wanted:{} effective:{}", wanted_seek, effective_seek);
                 return Err(Error::Generic("failed seek".into()));
             }
-            let mut buf = [0u8; 80];
             file.read_exact(&mut buf)?;
-            let height = (file_size as u32 / 80) - 1;
+            let height = base + (file_size as u32 / 80) - 1;
             let last: BlockHeader = deserialize(&buf)?;
 
             Ok(HeadersChain {
                 path: filepath,
+                base,
                 height,
                 last,
                 checkpoints,
@@ -90,6 +181,11 @@ impl HeadersChain {
         self.height
     }
 
+    /// The height of the first header available, 0 unless the chain started from a checkpoint
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
     fn pow_allow_min_difficulty_blocks(&self) -> bool {
         // Special difficulty rule for testnet and regtest:
         // If the next block's timestamp is more than 2* 10 minutes
@@ -107,7 +203,7 @@ impl HeadersChain {
             // loop at most DIFFCHANGE_INTERVAL times
             let bits = loop {
                 let header = self.get(height)?;
-                if height == 0
+                if height == self.base
                     || height % DIFFCHANGE_INTERVAL == 0
                     || header.difficulty(self.network) != 1
                 {
@@ -122,8 +218,14 @@ impl HeadersChain {
     }
 
     pub fn get(&self, height: u32) -> Result<BlockHeader, Error> {
+        if height < self.base {
+            return Err(Error::Generic(format!(
+                "header {} is below the checkpoint {}",
+                height, self.base
+            )));
+        }
         let mut file = File::open(&self.path)?;
-        let wanted_seek = height as u64 * 80;
+        let wanted_seek = (height - self.base) as u64 * 80;
         let effective_seek = file.seek(SeekFrom::Start(wanted_seek))?;
         if wanted_seek != effective_seek {
             warn!("Seek failed wanted:{} effective:{}", wanted_seek, effective_seek);
@@ -137,9 +239,9 @@ impl HeadersChain {
 
     /// to handle reorgs, it's necessary to remove some of the last headers
     pub fn remove(&mut self, headers_to_remove: u32) -> Result<(), Error> {
-        let headers_to_remove = headers_to_remove.min(self.height);
+        let headers_to_remove = headers_to_remove.min(self.height - self.base);
         let new_height = self.height - headers_to_remove;
-        let new_size = (new_height + 1) as u64 * 80;
+        let new_size = (new_height - self.base + 1) as u64 * 80;
         let file = OpenOptions::new().write(true).open(&self.path)?;
         self.last = self.get(new_height)?;
         self.height = new_height;
@@ -151,6 +253,48 @@ impl HeadersChain {
         self.last
     }
 
+    /// Find the height of the last header we have in common with the chain of the server,
+    /// looking back at most `max_depth` headers from our tip.
+    ///
+    /// Returns `None` if the fork is deeper than `max_depth`.
+    pub fn find_fork(&self, client: &Client, max_depth: u32) -> Result<Option<u32>, Error> {
+        self.find_fork_with(max_depth, |start, count| {
+            Ok(client.block_headers(start as usize, count as usize)?.headers)
+        })
+    }
+
+    /// Like [`HeadersChain::find_fork`], with `get_headers(start, count)` returning the headers of
+    /// the server from height `start`
+    pub fn find_fork_with<F>(
+        &self,
+        max_depth: u32,
+        mut get_headers: F,
+    ) -> Result<Option<u32>, Error>
+    where
+        F: FnMut(u32, u32) -> Result<Vec<BlockHeader>, Error>,
+    {
+        let lowest = self.lowest_fork(max_depth);
+        let mut end = self.height + 1;
+        while end > lowest {
+            let start = end.saturating_sub(DIFFCHANGE_INTERVAL).max(lowest);
+            let remote = get_headers(start, end - start)?;
+            for height in (start..end).rev() {
+                if let Some(header) = remote.get((height - start) as usize) {
+                    if header.block_hash() == self.get(height)?.block_hash() {
+                        return Ok(Some(height));
+                    }
+                }
+            }
+            end = start;
+        }
+        Ok(None)
+    }
+
+    /// The height to roll back to when the fork is deeper than `max_depth`
+    pub fn lowest_fork(&self, max_depth: u32) -> u32 {
+        self.height.saturating_sub(max_depth).max(self.base)
+    }
+
     /// write new headers to the file if checks are passed
     pub fn push(&mut self, new_headers: Vec<BlockHeader>) -> Result<(), Error> {
         let mut curr_bits = self.curr_bits()?;
@@ -161,7 +305,7 @@ impl HeadersChain {
             if self.last.block_hash() != new_header.prev_blockhash
                 || new_header.validate_pow(&new_header.target()).is_err()
             {
-                return Err(Error::InvalidHeaders);
+                return self.invalid(&mut serialized);
             }
 
             if new_height % DIFFCHANGE_INTERVAL == 0 {
@@ -175,7 +319,7 @@ impl HeadersChain {
                     };
                     let new_target = calc_difficulty_retarget(&first, &self.last);
                     if new_header.bits != BlockHeader::compact_target_from_u256(&new_target) {
-                        return Err(Error::InvalidHeaders);
+                        return self.invalid(&mut serialized);
                     }
                     curr_bits = new_header.bits;
                 }
@@ -186,13 +330,13 @@ impl HeadersChain {
                         || new_header.time.checked_sub(self.last.time).unwrap_or(0)
                             <= 2 * TARGET_BLOCK_SPACING
                     {
-                        return Err(Error::InvalidHeaders);
+                        return self.invalid(&mut serialized);
                     }
                 }
             }
             if let Some(hash) = self.checkpoints.get(&new_height) {
                 if hash != &new_header.block_hash() {
-                    return Err(Error::InvalidHeaders);
+                    return self.invalid(&mut serialized);
                 }
                 info!("checkpoint {} {} is ok", new_height, hash);
             }
@@ -229,6 +373,13 @@ impl HeadersChain {
         }
     }
 
+    /// write the valid headers preceding an invalid one, so that the file stays consistent with
+    /// the tip in memory
+    fn invalid(&mut self, serialized: &mut Vec<u8>) -> Result<(), Error> {
+        self.flush(serialized)?;
+        Err(Error::InvalidHeaders)
+    }
+
     /// write `serialized` bytes to the file, forcing flush so we are sure next `get()` will have
     /// also this data if requested
     fn flush(&mut self, serialized: &mut Vec<u8>) -> Result<(), Error> {
@@ -242,6 +393,16 @@ impl HeadersChain {
     }
 }
 
+/// Hashes of the compiled in headers at difficulty retarget heights, which a configured checkpoint
+/// at the same height must match: the genesis block and the ones of `get_checkpoints` at a retarget
+/// height
+fn retarget_checkpoints(network: Network) -> HashMap<u32, BlockHash> {
+    let mut checkpoints = get_checkpoints(network);
+    checkpoints.retain(|height, _| height % DIFFCHANGE_INTERVAL == 0);
+    checkpoints.insert(0, genesis_block(network).block_hash());
+    checkpoints
+}
+
 fn get_checkpoints(network: Network) -> HashMap<u32, BlockHash> {
     let mut checkpoints = HashMap::new();
     let mut i = |n, s| checkpoints.insert(n, BlockHash::from_hex(s).unwrap());
@@ -267,8 +428,9 @@ fn get_checkpoints(network: Network) -> HashMap<u32, BlockHash> {
 }
 
 #[cfg(test)]
-mod test {
-    use crate::headers::bitcoin::HeadersChain;
+pub(crate) mod test {
+    use crate::headers::bitcoin::{Checkpoint, HeadersChain};
+    use bitcoin::blockdata::constants::{genesis_block, DIFFCHANGE_INTERVAL};
     use bitcoin::consensus::encode::Decodable;
     use bitcoin::hash_types::BlockHash;
     use bitcoin::hashes::hex::FromHex;
@@ -378,4 +540,169 @@ mod test {
         );
         assert!(chain.get(200).is_err());
     }
+
+    /// mine a regtest header on top of `prev`
+    pub(crate) fn mine(prev: &BlockHeader) -> BlockHeader {
+        mine_at(prev, prev.time + 600)
+    }
+
+    /// mine a regtest header on top of `prev`, different from the one of [`mine`]
+    pub(crate) fn mine_fork(prev: &BlockHeader) -> BlockHeader {
+        mine_at(prev, prev.time + 601)
+    }
+
+    fn mine_at(prev: &BlockHeader, time: u32) -> BlockHeader {
+        let mut header = BlockHeader {
+            prev_blockhash: prev.block_hash(),
+            time,
+            nonce: 0,
+            ..*prev
+        };
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    /// mine `count` regtest headers on top of `prev` with `mine`
+    pub(crate) fn mine_chain(
+        prev: &BlockHeader,
+        count: usize,
+        mine: fn(&BlockHeader) -> BlockHeader,
+    ) -> Vec<BlockHeader> {
+        let mut headers: Vec<BlockHeader> = vec![];
+        for _ in 0..count {
+            let header = mine(headers.last().unwrap_or(prev));
+            headers.push(header);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_headers_checkpoint() {
+        let genesis = genesis_block(Network::Regtest).header;
+        assert!(Checkpoint::new(DIFFCHANGE_INTERVAL + 1, genesis).is_err());
+        let checkpoint_header = mine(&genesis);
+        let base = 2 * DIFFCHANGE_INTERVAL;
+        let checkpoint = Checkpoint::new(base, checkpoint_header).unwrap();
+
+        let temp = TempDir::new().unwrap();
+        let mut chain =
+            HeadersChain::with_checkpoint(&temp, Network::Regtest, Some(checkpoint)).unwrap();
+        assert_eq!(chain.base(), base);
+        assert_eq!(chain.height(), base);
+        assert_eq!(chain.tip(), checkpoint_header);
+
+        let mut headers = vec![mine(&checkpoint_header)];
+        for _ in 0..9 {
+            headers.push(mine(headers.last().unwrap()));
+        }
+        chain.push(headers.clone()).unwrap();
+        assert_eq!(chain.height(), base + 10);
+        assert_eq!(chain.get(base).unwrap(), checkpoint_header);
+        assert_eq!(chain.get(base + 10).unwrap(), headers[9]);
+        assert!(chain.get(base - 1).is_err(), "headers below the checkpoint are not available");
+
+        let chain =
+            HeadersChain::with_checkpoint(&temp, Network::Regtest, Some(checkpoint)).unwrap();
+        assert_eq!(chain.height(), base + 10);
+        assert_eq!(chain.tip(), headers[9]);
+
+        let mut chain =
+            HeadersChain::with_checkpoint(&temp, Network::Regtest, Some(checkpoint)).unwrap();
+        chain.remove(100).unwrap();
+        assert_eq!(chain.height(), base, "can't remove the checkpoint");
+        chain.push(headers).unwrap();
+
+        // the valid headers preceding an invalid one are persisted
+        let next = mine(&chain.tip());
+        assert!(chain.push(vec![next, checkpoint_header]).is_err());
+        assert_eq!(chain.height(), base + 11);
+        let chain =
+            HeadersChain::with_checkpoint(&temp, Network::Regtest, Some(checkpoint)).unwrap();
+        assert_eq!(chain.height(), base + 11);
+        assert_eq!(chain.tip(), next);
+
+        // checkpoints must have a valid proof of work and match the compiled in headers
+        let mut invalid_pow = checkpoint_header;
+        while invalid_pow.validate_pow(&invalid_pow.target()).is_ok() {
+            invalid_pow.nonce += 1;
+        }
+        assert!(Checkpoint::new(base, invalid_pow).unwrap().validate(Network::Regtest).is_err());
+        assert!(checkpoint.validate(Network::Regtest).is_ok());
+        assert!(Checkpoint::new(0, genesis).unwrap().validate(Network::Regtest).is_ok());
+        let wrong_genesis = Checkpoint::new(0, genesis_block(Network::Testnet).header).unwrap();
+        assert!(wrong_genesis.validate(Network::Regtest).is_err());
+
+        let other = Checkpoint::new(base, mine(&checkpoint_header)).unwrap();
+        assert!(HeadersChain::with_checkpoint(&temp, Network::Regtest, Some(other)).is_err());
+
+        // a chain from the genesis block is preferred over a checkpoint
+        let chain = HeadersChain::new(&temp, Network::Regtest).unwrap();
+        assert_eq!(chain.base(), 0);
+        let chain =
+            HeadersChain::with_checkpoint(&temp, Network::Regtest, Some(checkpoint)).unwrap();
+        assert_eq!(chain.base(), 0);
+        assert_eq!(chain.height(), 0);
+    }
+
+    #[test]
+    fn test_find_fork() {
+        let genesis = genesis_block(Network::Regtest).header;
+        let temp = TempDir::new().unwrap();
+        let mut chain = HeadersChain::new(&temp, Network::Regtest).unwrap();
+        let tip = DIFFCHANGE_INTERVAL + 100;
+        let ours = mine_chain(&genesis, tip as usize, mine);
+        chain.push(ours.clone()).unwrap();
+        assert_eq!(chain.height(), tip);
+
+        // the server chain, indexed by height, forking after `fork`
+        let server = |fork: u32, len: u32| {
+            let mut headers = vec![genesis];
+            headers.extend_from_slice(&ours[..fork as usize]);
+            let forked = mine_chain(headers.last().unwrap(), (len - fork) as usize, mine_fork);
+            headers.extend(forked);
+            headers
+        };
+        let find_fork = |server: &[BlockHeader], max_depth: u32| {
+            let mut calls = 0;
+            let fork = chain
+                .find_fork_with(max_depth, |start, count| {
+                    calls += 1;
+                    let end = (start + count) as usize;
+                    Ok(server[(start as usize).min(server.len())..end.min(server.len())].to_vec())
+                })
+                .unwrap();
+            (fork, calls)
+        };
+
+        let same = server(tip, tip);
+        assert_eq!(find_fork(&same, 144), (Some(tip), 1));
+
+        let shorter = server(tip - 10, tip - 10);
+        assert_eq!(find_fork(&shorter, 144), (Some(tip - 10), 1));
+
+        // the search continues in the previous chunk
+        let deep = server(50, tip + 1);
+        assert_eq!(find_fork(&deep, tip), (Some(50), 2));
+        assert_eq!(find_fork(&deep, tip - 100), (None, 2));
+        assert_eq!(find_fork(&deep, 144), (None, 1));
+        assert_eq!(chain.lowest_fork(144), tip - 144);
+        assert_eq!(chain.lowest_fork(tip + 1), 0);
+
+        // the search doesn't go below a checkpoint
+        let checkpoint_header = mine(&genesis);
+        let base = 2 * DIFFCHANGE_INTERVAL;
+        let checkpoint = Checkpoint::new(base, checkpoint_header).unwrap();
+        let temp = TempDir::new().unwrap();
+        let mut chain =
+            HeadersChain::with_checkpoint(&temp, Network::Regtest, Some(checkpoint)).unwrap();
+        chain.push(mine_chain(&checkpoint_header, 10, mine)).unwrap();
+        assert_eq!(chain.lowest_fork(144), base);
+        let fork = chain.find_fork_with(144, |start, count| {
+            assert!(start >= base);
+            Ok(mine_chain(&genesis, count as usize, mine_fork))
+        });
+        assert_eq!(fork.unwrap(), None);
+    }
 }
@@ -2,27 +2,42 @@ use elements::{self, BlockExtData};
 
 use crate::error::*;
 use crate::headers::compute_merkle_root;
+use bitcoin::consensus::encode::VarInt;
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::{Message, Signature};
 use bitcoin::PublicKey;
-use electrum_client::GetMerkleRes;
+use electrum_client::{Client, ElectrumApi, GetMerkleRes};
 use elements::opcodes::{self, Class};
 use elements::script::{self, Instruction};
 use elements::{BlockHash, BlockHeader, Script, TxMerkleNode, Txid};
 use gdk_common::ElementsNetwork;
-use log::info;
+use log::{info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
 
-/// liquid v1 block header verifier, not suitable for dynafed
-/// checks the challenge is exactly equal to the one present in block 1
-/// checks the solution script against the challenge, verifying signatures
+/// liquid block header verifier
+///
+/// for pre-dynafed headers checks the challenge is the script of a trusted federation (the one
+/// present in block 1 on Liquid) and the solution script against the challenge, verifying
+/// signatures.
+/// for dynafed headers checks the signblockscript of the current params is the script of a
+/// trusted federation and that the signblock witness satisfies it within the witness size limit.
+///
+/// the federations of every network are pinned, a new one is trusted only after checking with
+/// `follow_federation` that it has been activated following the dynafed rules. Federations
+/// trusted this way are persisted if the verifier is created with `load`.
 pub struct Verifier {
-    challenge: Script,
     genesis: BlockHash,
-    is_regtest: bool,
+    epoch_length: u32,
+    federations: Mutex<Vec<Script>>,
+    path: Option<PathBuf>,
 }
 
 const CHALLENGE: &'static str = "5b21026a2a106ec32c8a1e8052e5d02a7b0a150423dbd9b116fc48d46630ff6e6a05b92102791646a8b49c2740352b4495c118d876347bf47d0551c01c4332fdc2df526f1a2102888bda53a424466b0451627df22090143bbf7c060e9eacb1e38426f6b07f2ae12102aee8967150dee220f613de3b239320355a498808084a93eaf39a34dcd62024852102d46e9259d0a0bb2bcbc461a3e68f34adca27b8d08fbe985853992b4b104e27412102e9944e35e5750ab621e098145b8e6cf373c273b7c04747d1aa020be0af40ccd62102f9a9d4b10a6d6c56d8c955c547330c589bb45e774551d46d415e51cd9ad5116321033b421566c124dfde4db9defe4084b7aa4e7f36744758d92806b8f72c2e943309210353dcc6b4cf6ad28aceb7f7b2db92a4bf07ac42d357adf756f3eca790664314b621037f55980af0455e4fb55aad9b85a55068bb6dc4740ea87276dc693f4598db45fa210384001daa88dabd23db878dbb1ce5b4c2a5fa72c3113e3514bf602325d0c37b8e21039056d089f2fe72dbc0a14780b4635b0dc8a1b40b7a59106325dd1bc45cc70493210397ab8ea7b0bf85bc7fc56bb27bf85e75502e94e76a6781c409f3f2ec3d1122192103b00e3b5b77884bf3cae204c4b4eac003601da75f96982ffcb3dcb29c5ee419b92103c1f3c0874cfe34b8131af34699589aacec4093399739ae352e8a46f80a6f68375fae";
+const LIQUID_TESTNET_SIGNBLOCKSCRIPT: &'static str =
+    "51210217e403ddb181872c32a0cd468c710040b2f53d8cac69f18dad07985ee37e9a7151ae";
+const ELEMENTS_REGTEST_SIGNBLOCKSCRIPT: &'static str = "51"; // OP_TRUE
 const LIQUID_GENESIS_HASH: &'static str =
     "1466275836220db2944ca059a3a10ef6fd2ea684b0688d2c379296888a206003";
 const LIQUID_TESTNET_GENESIS_HASH: &'static str =
@@ -30,18 +45,48 @@ const LIQUID_TESTNET_GENESIS_HASH: &'static str =
 const ELEMENTS_REGTEST_GENESIS_HASH: &'static str =
     "209577bda6bf4b5804bd46f8621580dd6d4e8bfa2d190e1c50e932492baca07d";
 
+// Number of headers downloaded at once when counting the votes for a federation
+const VOTES_HEADERS_CHUNK: usize = 500;
+
 impl Verifier {
     pub fn new(network: ElementsNetwork) -> Self {
-        let (is_regtest, genesis_hash) = match network {
-            ElementsNetwork::Liquid => (false, LIQUID_GENESIS_HASH),
-            ElementsNetwork::LiquidTestnet => (false, LIQUID_TESTNET_GENESIS_HASH),
-            ElementsNetwork::ElementsRegtest => (true, ELEMENTS_REGTEST_GENESIS_HASH),
+        let (script, genesis_hash, epoch_length) = match network {
+            ElementsNetwork::Liquid => (CHALLENGE, LIQUID_GENESIS_HASH, 20160),
+            ElementsNetwork::LiquidTestnet => {
+                (LIQUID_TESTNET_SIGNBLOCKSCRIPT, LIQUID_TESTNET_GENESIS_HASH, 1000)
+            }
+            ElementsNetwork::ElementsRegtest => {
+                (ELEMENTS_REGTEST_SIGNBLOCKSCRIPT, ELEMENTS_REGTEST_GENESIS_HASH, 10)
+            }
         };
+        // pre-dynafed headers use the script as challenge, dynafed ones its p2wsh
+        let script = Script::from(Vec::<u8>::from_hex(script).unwrap());
         Verifier {
-            challenge: Script::from(Vec::<u8>::from_hex(CHALLENGE).unwrap()),
             genesis: BlockHash::from_hex(genesis_hash).unwrap(),
-            is_regtest,
+            epoch_length,
+            federations: Mutex::new(vec![script.to_v0_p2wsh(), script]),
+            path: None,
+        }
+    }
+
+    /// like `new` but also trusting the federations followed by previous verifiers with the same
+    /// `path`, and persisting the ones followed by this one
+    pub fn load<P: AsRef<Path>>(path: P, network: ElementsNetwork) -> Result<Self, Error> {
+        std::fs::create_dir_all(path.as_ref())?;
+        let mut filepath: PathBuf = path.as_ref().into();
+        filepath.push(format!("liquid_federations_{:?}", network));
+        let mut verifier = Verifier::new(network);
+        if filepath.exists() {
+            let followed: Vec<Script> = serde_cbor::from_slice(&std::fs::read(&filepath)?)?;
+            let mut federations = verifier.federations()?;
+            for script in followed {
+                if !federations.contains(&script) {
+                    federations.push(script);
+                }
+            }
         }
+        verifier.path = Some(filepath);
+        Ok(verifier)
     }
 
     /// verify the given txid and the proof against a given block header (verify header validity also)
@@ -61,12 +106,86 @@ impl Verifier {
         }
     }
 
+    /// trust the federation signing the dynafed `header` if it has been activated following the
+    /// dynafed rules: its signblockscript has been proposed by more than 4/5 of the blocks of the
+    /// epoch preceding the one in which it became current, blocks signed by a trusted federation.
+    ///
+    /// `get_headers` returns the headers at the given heights, the ones of a whole epoch are
+    /// downloaded once for every federation change.
+    pub fn follow_federation<F>(&self, header: &BlockHeader, get_headers: &F) -> Result<(), Error>
+    where
+        F: Fn(&[u32]) -> Result<Vec<BlockHeader>, Error>,
+    {
+        let script = match current_signblockscript(header) {
+            Some(script) => script,
+            // the federation of pre-dynafed headers can't change
+            None => return Ok(()),
+        };
+        if self.federations()?.contains(script) {
+            return Ok(());
+        }
+
+        // params change only at the start of an epoch, find the one in which it became current
+        let length = self.epoch_length;
+        let mut epoch_start = header.height - header.height % length;
+        let previous_start = loop {
+            let previous_start = epoch_start.checked_sub(length).ok_or(Error::InvalidHeaders)?;
+            let previous = get_header(get_headers, previous_start)?;
+            if current_signblockscript(&previous) != Some(script) {
+                // the previous epoch may be signed by a federation not trusted yet too
+                self.follow_federation(&previous, get_headers)?;
+                break previous_start;
+            }
+            epoch_start = previous_start;
+        };
+
+        let heights: Vec<u32> = (previous_start..epoch_start).collect();
+        let mut votes = 0;
+        for chunk in heights.chunks(VOTES_HEADERS_CHUNK) {
+            let headers = get_headers(chunk)?;
+            if headers.len() != chunk.len() {
+                return Err(Error::InvalidHeaders);
+            }
+            for (height, voter) in chunk.iter().zip(headers.iter()) {
+                if voter.height != *height {
+                    return Err(Error::InvalidHeaders);
+                }
+                self.verify_header(voter)?;
+                if let BlockExtData::Dynafed {
+                    proposed,
+                    ..
+                } = &voter.ext
+                {
+                    if proposed.signblockscript() == Some(script) {
+                        votes += 1;
+                    }
+                }
+            }
+        }
+        if votes <= length * 4 / 5 {
+            warn!("federation {:?} has only {} votes in epoch {}", script, votes, previous_start);
+            return Err(Error::InvalidHeaders);
+        }
+
+        info!("federation {:?} activated at height {}", script, epoch_start);
+        let mut federations = self.federations()?;
+        federations.push(script.clone());
+        if let Some(path) = &self.path {
+            // the first two federations are the pinned ones
+            let followed = serde_cbor::to_vec(&federations[2..])?;
+            let mut tmp_path = path.clone();
+            tmp_path.set_extension("tmp");
+            std::fs::write(&tmp_path, &followed)?;
+            std::fs::rename(&tmp_path, path)?;
+        }
+        Ok(())
+    }
+
     /// verify the given liquid header
     fn verify_header(&self, header: &BlockHeader) -> Result<(), Error> {
         let mut stack = vec![];
         let hash = header.block_hash();
-        if hash == self.genesis || self.is_regtest {
-            // TODO add regtest verification
+        if hash == self.genesis {
             return Ok(());
         }
 
@@ -75,23 +194,57 @@ impl Verifier {
                 challenge,
                 solution,
             } => {
-                if challenge != &self.challenge {
-                    return Err(Error::InvalidHeaders);
-                }
+                self.check_federation(challenge, header.height)?;
                 for instr in solution.instructions_minimal().chain(challenge.instructions_minimal())
                 {
                     self.process_instr(&instr, &hash, &mut stack)?;
                 }
-                if stack.is_empty() {
-                    Ok(())
-                } else {
-                    Err(Error::InvalidHeaders)
+                check_stack(&stack)
+            }
+            BlockExtData::Dynafed {
+                current,
+                signblock_witness,
+                ..
+            } => {
+                let signblockscript = current.signblockscript().ok_or(Error::InvalidHeaders)?;
+                let witness_limit =
+                    current.signblock_witness_limit().ok_or(Error::InvalidHeaders)?;
+                self.check_federation(signblockscript, header.height)?;
+                if witness_size(signblock_witness) > witness_limit as usize {
+                    return Err(Error::InvalidHeaders);
+                }
+
+                // the signblockscript is a v0 p2wsh, the last element of the witness is the
+                // witness script which is executed with the other elements as initial stack
+                let (witness_script, initial_stack) =
+                    signblock_witness.split_last().ok_or(Error::InvalidHeaders)?;
+                let witness_script = Script::from(witness_script.clone());
+                if signblockscript != &witness_script.to_v0_p2wsh() {
+                    return Err(Error::InvalidHeaders);
                 }
+                stack.extend(initial_stack.iter().cloned());
+                for instr in witness_script.instructions_minimal() {
+                    self.process_instr(&instr, &hash, &mut stack)?;
+                }
+                check_stack(&stack)
             }
-            _ => Err(Error::InvalidHeaders),
         }
     }
 
+    /// check `script` is the script of a trusted federation
+    fn check_federation(&self, script: &Script, height: u32) -> Result<(), Error> {
+        if self.federations()?.contains(script) {
+            Ok(())
+        } else {
+            warn!("block {} is signed by the unknown federation {:?}", height, script);
+            Err(Error::InvalidHeaders)
+        }
+    }
+
+    fn federations(&self) -> Result<MutexGuard<Vec<Script>>, Error> {
+        self.federations.lock().map_err(|e| Error::MutexPoisonError(e.to_string()))
+    }
+
     fn process_instr(
         &self,
         instr: &Result<Instruction, script::Error>,
@@ -143,7 +296,9 @@ impl Verifier {
             }
             if verified == required_sig {
                 info!("proof for block {} found {} valid signatures", hash, verified);
+                // pop the dummy element consumed by OP_CHECKMULTISIG and push the result
                 stack.pop().ok_or_else(|| Error::InvalidHeaders)?;
+                stack.push(vec![1]);
                 return Ok(());
             }
         }
@@ -151,18 +306,69 @@ impl Verifier {
     }
 }
 
+/// the signblockscript of the current params of a dynafed header
+fn current_signblockscript(header: &BlockHeader) -> Option<&Script> {
+    match &header.ext {
+        BlockExtData::Dynafed {
+            current,
+            ..
+        } => current.signblockscript(),
+        BlockExtData::Proof {
+            ..
+        } => None,
+    }
+}
+
+fn get_header<F>(get_headers: &F, height: u32) -> Result<BlockHeader, Error>
+where
+    F: Fn(&[u32]) -> Result<Vec<BlockHeader>, Error>,
+{
+    match get_headers(&[height])?.pop() {
+        Some(header) if header.height == height => Ok(header),
+        _ => Err(Error::InvalidHeaders),
+    }
+}
+
+/// download the headers at `heights` for `Verifier::follow_federation`
+pub fn download_headers(client: &Client, heights: &[u32]) -> Result<Vec<BlockHeader>, Error> {
+    client
+        .batch_block_header_raw(heights.iter().cloned())?
+        .iter()
+        .map(|bytes| Ok(elements::encode::deserialize(bytes)?))
+        .collect()
+}
+
+/// a successful script execution leaves a single true element on the stack
+fn check_stack(stack: &[Vec<u8>]) -> Result<(), Error> {
+    match stack {
+        [top] if top.iter().any(|b| *b != 0) => Ok(()),
+        _ => Err(Error::InvalidHeaders),
+    }
+}
+
+/// serialized size of the signblock witness, which is limited by the current params
+fn witness_size(witness: &[Vec<u8>]) -> usize {
+    let elements_size: usize = witness.iter().map(|e| VarInt(e.len() as u64).len() + e.len()).sum();
+    VarInt(witness.len() as u64).len() + elements_size
+}
+
 #[cfg(test)]
 mod test {
+    use crate::error::Error;
     use crate::headers::liquid::Verifier;
-    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::hashes::hex::{FromHex, ToHex};
+    use bitcoin::hashes::{sha256, Hash};
     use elements::encode::deserialize;
     use elements::{BlockExtData, BlockHeader, Script};
     use gdk_common::ElementsNetwork;
     use rand::seq::SliceRandom;
 
+    const REGTEST_HEADER: &str = "000000a07da0ac2b4932e9501c0e192dfa8b4e6ddd801562f846bd04584bbfa6bd779520a297a6b54050bd32f46e7b738931f2bfc0f9ebc2663e2057dbdf26c5472c73439ee3ec5e01000000022200204ae81572f06e1b88fd5ced7a1a000945432e83e1551e6f721ee9c00b8cc332604a00000017a91472c44f957fc011d97e3406667dca5b1c930c4026870151014202fcba7ecf41bc7e1be4ee122d9d22e3333671eb0a3a87b5cdf099d59874e1940f02fcba7ecf41bc7e1be4ee122d9d22e3333671eb0a3a87b5cdf099d59874e1940f00010151";
+
     #[test]
     fn test_regtest() {
-        let regtest_header : BlockHeader = deserialize(&Vec::<u8>::from_hex("000000a07da0ac2b4932e9501c0e192dfa8b4e6ddd801562f846bd04584bbfa6bd779520a297a6b54050bd32f46e7b738931f2bfc0f9ebc2663e2057dbdf26c5472c73439ee3ec5e01000000022200204ae81572f06e1b88fd5ced7a1a000945432e83e1551e6f721ee9c00b8cc332604a00000017a91472c44f957fc011d97e3406667dca5b1c930c4026870151014202fcba7ecf41bc7e1be4ee122d9d22e3333671eb0a3a87b5cdf099d59874e1940f02fcba7ecf41bc7e1be4ee122d9d22e3333671eb0a3a87b5cdf099d59874e1940f00010151").unwrap()).unwrap();
+        let regtest_header: BlockHeader =
+            deserialize(&Vec::<u8>::from_hex(REGTEST_HEADER).unwrap()).unwrap();
 
         match regtest_header.ext {
             BlockExtData::Proof {
@@ -175,6 +381,98 @@ mod test {
                 signblock_witness: _,
             } => assert!(true),
         }
+
+        let verifier = Verifier::new(ElementsNetwork::ElementsRegtest);
+        assert!(verifier.verify_header(&regtest_header).is_ok());
+
+        // the witness script must match the signblockscript
+        let mut wrong_header = regtest_header.clone();
+        if let BlockExtData::Dynafed {
+            signblock_witness,
+            ..
+        } = &mut wrong_header.ext
+        {
+            *signblock_witness = vec![vec![0x52]];
+        }
+        assert!(verifier.verify_header(&wrong_header).is_err());
+
+        // the witness script must leave true on the stack
+        let mut wrong_header = regtest_header.clone();
+        if let BlockExtData::Dynafed {
+            signblock_witness,
+            ..
+        } = &mut wrong_header.ext
+        {
+            *signblock_witness = vec![vec![0x00], vec![0x51]];
+        }
+        assert!(verifier.verify_header(&wrong_header).is_err());
+    }
+
+    /// sets the height of a header in hex
+    fn at_height(header_hex: &str, height: u32) -> BlockHeader {
+        let mut bytes = Vec::<u8>::from_hex(header_hex).unwrap();
+        bytes[72..76].copy_from_slice(&height.to_le_bytes());
+        deserialize(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_dynafed_transition() {
+        // regtest header with signblockscript `wsh(OP_TRUE)` and null proposed params
+        let header_hex = REGTEST_HEADER;
+        let op_true_wsh = sha256::Hash::hash(&[0x51]).to_hex();
+        let op_2_wsh = sha256::Hash::hash(&[0x52]).to_hex();
+        let proposing_op_2 = header_hex.strip_suffix("00010151").unwrap().to_string()
+            + "0222"
+            + "0020"
+            + &op_2_wsh
+            + "4a00000017a91472c44f957fc011d97e3406667dca5b1c930c4026870151"
+            + "00"
+            + "010151";
+        let signed_by_op_2 =
+            header_hex.replace(&op_true_wsh, &op_2_wsh).strip_suffix("010151").unwrap().to_string()
+                + "010152";
+
+        // the epoch length on regtest is 10, the federation proposed by enough blocks of the
+        // epoch starting at 10 is current from height 20
+        let (proposing, signing) = (&proposing_op_2, &signed_by_op_2);
+        let headers = |votes: u32| {
+            move |heights: &[u32]| -> Result<Vec<BlockHeader>, Error> {
+                Ok(heights
+                    .iter()
+                    .map(|h| match h {
+                        0..=9 => at_height(header_hex, *h),
+                        10..=19 if *h < 10 + votes => at_height(proposing, *h),
+                        10..=19 => at_height(header_hex, *h),
+                        _ => at_height(signing, *h),
+                    })
+                    .collect())
+            }
+        };
+        let signed = at_height(&signed_by_op_2, 35);
+
+        let verifier = Verifier::new(ElementsNetwork::ElementsRegtest);
+        assert!(verifier.verify_header(&at_height(&proposing_op_2, 10)).is_ok());
+        assert!(
+            verifier.verify_header(&signed).is_err(),
+            "proposed federation should not be accepted before activation"
+        );
+        assert!(
+            verifier.follow_federation(&signed, &headers(8)).is_err(),
+            "4/5 of the votes are not enough"
+        );
+        assert!(verifier.verify_header(&signed).is_err());
+        assert!(verifier.follow_federation(&signed, &headers(9)).is_ok());
+        assert!(verifier.verify_header(&signed).is_ok(), "activated federation is trusted");
+        assert!(verifier.verify_header(&at_height(header_hex, 1)).is_ok());
+
+        // followed federations are persisted
+        let dir = tempfile::TempDir::new().unwrap();
+        let verifier = Verifier::load(&dir, ElementsNetwork::ElementsRegtest).unwrap();
+        assert!(verifier.follow_federation(&signed, &headers(10)).is_ok());
+        let verifier = Verifier::load(&dir, ElementsNetwork::ElementsRegtest).unwrap();
+        assert!(verifier.verify_header(&signed).is_ok());
+        let verifier = Verifier::new(ElementsNetwork::ElementsRegtest);
+        assert!(verifier.verify_header(&signed).is_err());
     }
 
     #[test]
@@ -1,21 +1,21 @@
 use crate::determine_electrum_url;
 use crate::error::Error;
-use crate::headers::bitcoin::{HeadersChain, HEADERS_FILE_MUTEX};
-use crate::headers::liquid::Verifier;
+use crate::headers::bitcoin::{Checkpoint, HeadersChain, HEADERS_FILE_MUTEX};
+use crate::headers::liquid::{download_headers, Verifier};
 use ::bitcoin::hashes::hex::ToHex;
 use ::bitcoin::hashes::{sha256, sha256d, Hash};
 use aes_gcm_siv::aead::{Aead, NewAead};
 use aes_gcm_siv::{Aes256GcmSiv, Key, Nonce};
-use electrum_client::{Client, ElectrumApi, GetMerkleRes};
+use electrum_client::{Batch, Client, ElectrumApi, GetMerkleRes, Param};
 use gdk_common::be::{BETxid, BETxidConvert};
 use gdk_common::model::{
     SPVCommonParams, SPVDownloadHeadersParams, SPVDownloadHeadersResult, SPVVerifyTxParams,
-    SPVVerifyTxResult,
+    SPVVerifyTxResult, SPVVerifyTxsItem, SPVVerifyTxsParams, SPVVerifyTxsResult,
 };
 use gdk_common::NetworkId;
 use log::{debug, info, warn};
 use rand::{thread_rng, Rng};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
@@ -71,7 +71,8 @@ impl ParamsMethods for SPVCommonParams {
     }
     fn headers_chain(&self) -> Result<HeadersChain, Error> {
         let network = self.bitcoin_network().expect("headers_chain available only on bitcoin");
-        Ok(HeadersChain::new(&self.network.state_dir, network)?)
+        let checkpoint = Checkpoint::from_params(&self.network)?;
+        Ok(HeadersChain::with_checkpoint(&self.network.state_dir, network, checkpoint)?)
     }
     fn verified_cache(&self) -> Result<VerifiedCache, Error> {
         Ok(VerifiedCache::new(&self.network.state_dir, self.network.id(), &self.encryption_key))
@@ -102,12 +103,20 @@ pub fn download_headers(
     let mut reorg_happened = false;
     if let Err(Error::InvalidHeaders) = chain.push(headers) {
         warn!(
-            "invalid headers, possible reorg, invalidating latest headers and latest verified tx"
+            "invalid headers, possible reorg, invalidating headers and verified tx after the fork"
         );
-        let mut cache = input.params.verified_cache()?;
-        chain.remove(input.params.network.max_reorg_blocks.unwrap_or(144))?;
-        cache.remove(input.params.network.max_reorg_blocks.unwrap_or(144))?;
-        reorg_happened = true;
+        let max_reorg_blocks = input.params.network.max_reorg_blocks.unwrap_or(144);
+        let tip = chain.height();
+        let fork = match chain.find_fork(&client, max_reorg_blocks)? {
+            Some(fork) => fork,
+            None => chain.lowest_fork(max_reorg_blocks),
+        };
+        if fork < tip {
+            let mut cache = input.params.verified_cache()?;
+            chain.remove(tip - fork)?;
+            cache.remove(fork + 1)?;
+            reorg_happened = true;
+        }
     }
     info!("downloaded {:?}", chain.height());
 
@@ -125,75 +134,170 @@ pub fn download_headers(
 ///
 /// used to expose SPV functionality through C interface
 pub fn spv_verify_tx(input: &SPVVerifyTxParams) -> Result<SPVVerifyTxResult, Error> {
+    debug!("spv_verify_tx {:?}", input);
+    let txid = BETxid::from_hex(&input.txid, input.params.network.id())?;
+    let mut results = verify_txs(&input.params, &[(txid, input.height)])?;
+    Ok(results.remove(0))
+}
+
+/// Like `spv_verify_tx` but for many transactions, using a single connection and opening the
+/// headers chain and the cache once.
+///
+/// Transactions requested more than once are verified once, and for Liquid the header of every
+/// block is downloaded once.
+pub fn spv_verify_txs(input: &SPVVerifyTxsParams) -> Result<SPVVerifyTxsResult, Error> {
+    debug!("spv_verify_txs {} txs", input.txs.len());
+    let network = input.params.network.id();
+    let txs = input
+        .txs
+        .iter()
+        .map(|tx| Ok((BETxid::from_hex(&tx.txid, network)?, tx.height)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let results = verify_txs(&input.params, &txs)?;
+    Ok(SPVVerifyTxsResult {
+        txs: input
+            .txs
+            .iter()
+            .zip(results)
+            .map(|(tx, result)| SPVVerifyTxsItem {
+                tx: tx.clone(),
+                result,
+            })
+            .collect(),
+    })
+}
+
+/// Returns the verification result of every `(txid, height)` in `txs`, in the same order
+fn verify_txs(
+    params: &SPVCommonParams,
+    txs: &[(BETxid, u32)],
+) -> Result<Vec<SPVVerifyTxResult>, Error> {
     let mut _lock;
-    if let NetworkId::Bitcoin(network) = input.params.network.id() {
+    if let NetworkId::Bitcoin(network) = params.network.id() {
         // Liquid hasn't a shared headers chain file
         _lock = HEADERS_FILE_MUTEX
             .get(&network)
             .expect("unreachable because map populate with every enum variants")
             .lock()?;
     }
-    debug!("spv_verify_tx {:?}", input);
-    let txid = BETxid::from_hex(&input.txid, input.params.network.id())?;
 
-    let mut cache = input.params.verified_cache()?;
-    if cache.contains(&txid, input.height)? {
-        info!("verified cache hit for {}", txid);
-        return Ok(SPVVerifyTxResult::Verified);
+    let mut cache = params.verified_cache()?;
+    let mut results = HashMap::new();
+    let mut to_verify = vec![];
+    for (txid, height) in txs {
+        if results.contains_key(&(txid, *height)) {
+            continue;
+        }
+        if cache.contains(txid, *height)? {
+            info!("verified cache hit for {}", txid);
+            results.insert((txid, *height), SPVVerifyTxResult::Verified);
+        } else {
+            results.insert((txid, *height), SPVVerifyTxResult::NotVerified);
+            to_verify.push((txid, *height));
+        }
+    }
+    if to_verify.is_empty() {
+        return Ok(txs.iter().map(|(t, h)| results[&(t, *h)].clone()).collect());
     }
 
-    let client = input.params.build_client()?;
+    let client = params.build_client()?;
+    let get_proof = |txid: &BETxid, height: u32| match client
+        .transaction_get_merkle(&txid.into_bitcoin(), height as usize)
+    {
+        Ok(proof) => Some(proof),
+        Err(e) => {
+            warn!("failed fetching merkle inclusion proof for {}: {:?}", txid, e);
+            None
+        }
+    };
+    // the proofs of the transactions of a block are requested in a single batch, falling back to
+    // one request per transaction if the batch fails
+    let get_proofs = |height: u32, txids: &[&BETxid]| -> Vec<Option<GetMerkleRes>> {
+        let mut batch = Batch::default();
+        for txid in txids {
+            let params =
+                vec![Param::String(txid.into_bitcoin().to_hex()), Param::Usize(height as usize)];
+            batch.raw("blockchain.transaction.get_merkle".into(), params);
+        }
+        match client.batch_call(&batch) {
+            Ok(values) if values.len() == txids.len() => {
+                values.into_iter().map(|v| serde_json::from_value(v).ok()).collect()
+            }
+            result => {
+                warn!("failed fetching merkle inclusion proofs of block {}: {:?}", height, result);
+                txids.iter().map(|txid| get_proof(txid, height)).collect()
+            }
+        }
+    };
+    let mut blocks: BTreeMap<u32, Vec<&BETxid>> = BTreeMap::new();
+    for (txid, height) in to_verify {
+        blocks.entry(height).or_default().push(txid);
+    }
 
-    match input.params.network.id() {
+    match params.network.id() {
         NetworkId::Bitcoin(_bitcoin_network) => {
-            let chain = input.params.headers_chain().expect("match verified we are bitcoin type");
-
-            if input.height <= chain.height() {
-                let btxid = txid.ref_bitcoin().unwrap();
-                info!("chain height ({}) enough to verify, downloading proof", chain.height());
-                let proof = match client.transaction_get_merkle(btxid, input.height as usize) {
-                    Ok(proof) => proof,
-                    Err(e) => {
-                        warn!("failed fetching merkle inclusion proof for {}: {:?}", txid, e);
-                        return Ok(SPVVerifyTxResult::NotVerified);
+            let chain = params.headers_chain().expect("match verified we are bitcoin type");
+            for (height, txids) in blocks {
+                if height > chain.height() {
+                    info!(
+                        "chain height ({}) not enough to verify txs at height {}",
+                        chain.height(),
+                        height
+                    );
+                    for txid in txids {
+                        results.insert((txid, height), SPVVerifyTxResult::InProgress);
+                    }
+                    continue;
+                }
+                info!("chain height ({}) enough to verify, downloading proofs", chain.height());
+                for (txid, proof) in txids.iter().copied().zip(get_proofs(height, &txids)) {
+                    let btxid = txid.ref_bitcoin().unwrap();
+                    match proof {
+                        Some(proof) if chain.verify_tx_proof(btxid, height, proof).is_ok() => {
+                            cache.insert(txid, height);
+                            results.insert((txid, height), SPVVerifyTxResult::Verified);
+                        }
+                        _ => (),
                     }
-                };
-                if chain.verify_tx_proof(btxid, input.height, proof).is_ok() {
-                    cache.write(&txid, input.height)?;
-                    Ok(SPVVerifyTxResult::Verified)
-                } else {
-                    Ok(SPVVerifyTxResult::NotVerified)
                 }
-            } else {
-                info!(
-                    "chain height ({}) not enough to verify tx at height {}",
-                    chain.height(),
-                    input.height
-                );
-
-                Ok(SPVVerifyTxResult::InProgress)
             }
         }
         NetworkId::Elements(elements_network) => {
-            let proof =
-                match client.transaction_get_merkle(&txid.into_bitcoin(), input.height as usize) {
-                    Ok(proof) => proof,
+            let verifier = Verifier::load(&params.network.state_dir, elements_network)?;
+            let get_headers = |heights: &[u32]| download_headers(&client, heights);
+            for (height, txids) in blocks {
+                let header = client
+                    .block_header_raw(height as usize)
+                    .map_err(Error::from)
+                    .and_then(|bytes| Ok(elements::encode::deserialize(&bytes)?));
+                let header: elements::BlockHeader = match header {
+                    Ok(header) => header,
                     Err(e) => {
-                        warn!("failed fetching merkle inclusion proof for {}: {:?}", txid, e);
-                        return Ok(SPVVerifyTxResult::NotVerified);
+                        // the transactions of this block are left not verified
+                        warn!("failed fetching header {}: {:?}", height, e);
+                        continue;
                     }
                 };
-            let verifier = Verifier::new(elements_network);
-            let header_bytes = client.block_header_raw(input.height as usize)?;
-            let header: elements::BlockHeader = elements::encode::deserialize(&header_bytes)?;
-            if verifier.verify_tx_proof(txid.ref_elements().unwrap(), proof, &header).is_ok() {
-                cache.write(&txid, input.height)?;
-                Ok(SPVVerifyTxResult::Verified)
-            } else {
-                Ok(SPVVerifyTxResult::NotVerified)
+                if let Err(e) = verifier.follow_federation(&header, &get_headers) {
+                    warn!("cannot follow the federation of block {}: {:?}", height, e);
+                }
+                for (txid, proof) in txids.iter().copied().zip(get_proofs(height, &txids)) {
+                    let proof = match proof {
+                        Some(proof) => proof,
+                        None => continue,
+                    };
+                    let etxid = txid.ref_elements().unwrap();
+                    if verifier.verify_tx_proof(etxid, proof, &header).is_ok() {
+                        cache.insert(txid, height);
+                        results.insert((txid, height), SPVVerifyTxResult::Verified);
+                    }
+                }
             }
         }
     }
+    cache.flush()?;
+
+    Ok(txs.iter().map(|(t, h)| results[&(t, *h)].clone()).collect())
 }
 
 struct VerifiedCache {
@@ -257,12 +361,12 @@ impl VerifiedCache {
         Ok(self.set.contains(&(txid.clone(), height)))
     }
 
-    fn write(&mut self, txid: &BETxid, height: u32) -> Result<(), Error> {
+    /// add a verified txid, persisted on the next `flush()`
+    fn insert(&mut self, txid: &BETxid, height: u32) {
         self.set.insert((txid.clone(), height));
-        self.flush()
     }
 
-    /// remove all verified txid with height greater than given height
+    /// remove all verified txid with height greater or equal than given height
     fn remove(&mut self, height: u32) -> Result<(), Error> {
         self.set = self.set.iter().filter(|e| e.1 < height).cloned().collect();
         self.flush()
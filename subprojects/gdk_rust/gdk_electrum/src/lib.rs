@@ -15,12 +15,14 @@ use serde_json::Value;
 
 pub mod account;
 pub mod error;
+pub mod fees;
 pub mod headers;
 pub mod interface;
 mod notification;
 pub mod pin;
 pub mod pset;
 pub mod spv;
+pub mod unblind;
 
 use crate::account::{
     discover_account, get_account_derivation, get_account_script_purpose,
@@ -49,15 +51,19 @@ use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::path::PathBuf;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{iter, thread};
 
-use crate::headers::bitcoin::HeadersChain;
-use crate::headers::liquid::Verifier;
+use crate::headers::bitcoin::{Checkpoint, HeadersChain};
+use crate::headers::liquid::{download_headers, Verifier};
 use crate::headers::ChainOrVerifier;
-pub use crate::notification::{NativeNotif, Notification, TransactionNotification};
+pub use crate::notification::{
+    CacheRebuildNotification, ConfirmationNotification, ConflictNotification, Event,
+    MinorityForkNotification, NativeNotif, Notification, ReorgNotification, SpvStatusNotification,
+    TransactionNotification, TxRemovedNotification, SUBSCRIPTION_CAPACITY,
+};
 use crate::pin::PinManager;
-use crate::spv::SpvCrossValidator;
+use crate::spv::{work_to_hex, CrossValidationResult, ServersStatus, SpvCrossValidator};
 use aes::Aes256;
 use bitcoin::blockdata::constants::DIFFCHANGE_INTERVAL;
 use block_modes::block_padding::Pkcs7;
@@ -71,10 +77,13 @@ use std::collections::hash_map::DefaultHasher;
 use std::fmt;
 use std::hash::Hasher;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, RwLock};
 use std::thread::JoinHandle;
 
 const CROSS_VALIDATION_RATE: u8 = 4; // Once every 4 thread loop runs, or roughly 28 seconds
+const DOWNLOAD_TXS_CHUNK: usize = 100; // Transactions downloaded before updating the sync status
+const PENDING_TX_REBROADCAST_SECS: u64 = 60; // Minimum time between broadcasts of a pending tx
 
 lazy_static! {
     static ref EC: secp256k1::Secp256k1<secp256k1::All> = {
@@ -92,7 +101,13 @@ struct Syncer {
     store: Store,
     master_blinding: Option<MasterBlindingKey>,
     network: NetworkParameters,
-    recent_spent_utxos: Arc<RwLock<HashSet<BEOutPoint>>>,
+    notify: NativeNotif,
+    rebuilding_cache: Arc<AtomicBool>,
+    progress: SyncProgress,
+
+    /// The wallet transactions with at least `required_num_blocks` confirmations at the last
+    /// sync, None before the first sync
+    confirmed_txs: Option<HashSet<BETxid>>,
 }
 
 pub struct Tipper {
@@ -128,20 +143,24 @@ pub struct ElectrumSession {
     // True if the last call (to the Electrum server) succeeded
     pub last_network_call_succeeded: Arc<AtomicBool>,
 
+    // True if the cache has been reset and the next sync rebuilds it from scratch
+    pub rebuilding_cache: Arc<AtomicBool>,
+
+    // The outcome of the last SPV cross-validation against every server
+    pub spv_servers_status: ServersStatus,
+
+    // The progress of the sync started by the last call to start_threads
+    pub sync_status: Arc<RwLock<SyncStatus>>,
+
+    // True once the sync started by the last call to start_threads has been completed
+    pub initial_sync_done: Arc<AtomicBool>,
+
     pub store: Option<Store>,
 
     /// Master xprv of the signer associated to the session
     ///
     /// FIXME: remove this once we have fully migrated to the hw signer interface
     pub master_xprv: Option<ExtendedPrivKey>,
-
-    /// Spent utxos
-    ///
-    /// Remember the spent utxos to avoid using them in transaction that are created after
-    /// the previous send/broadcast tx, but before the next sync.
-    ///
-    /// This set it emptied after every sync.
-    pub recent_spent_utxos: Arc<RwLock<HashSet<BEOutPoint>>>,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
@@ -198,6 +217,37 @@ impl StateUpdater {
     }
 }
 
+/// Shares the sync status with `get_sync_status` and notifies its updates until the initial sync
+/// is complete
+///
+/// Once complete, the status keeps being updated but a new tip, moving it back to incomplete
+/// until the headers catch up, doesn't restart the notifications.
+#[derive(Clone)]
+pub struct SyncProgress {
+    status: Arc<RwLock<SyncStatus>>,
+    initial_sync_done: Arc<AtomicBool>,
+    notify: NativeNotif,
+}
+
+impl SyncProgress {
+    fn update(&self, f: impl FnOnce(&mut SyncStatus)) {
+        let status = {
+            let mut status = self.status.write().unwrap();
+            f(&mut status);
+            // decided under the lock so that the completing status is notified exactly once
+            if self.initial_sync_done.load(Ordering::Relaxed) {
+                return;
+            }
+            if status.is_complete() {
+                self.initial_sync_done.store(true, Ordering::Relaxed);
+            }
+            status.clone()
+        };
+        // notified without holding the lock, the handler may call `get_sync_status`
+        self.notify.sync_progress(&status);
+    }
+}
+
 pub fn determine_electrum_url(network: &NetworkParameters) -> Result<ElectrumUrl, Error> {
     if let Some(true) = network.use_tor {
         if let Some(electrum_onion_url) = network.electrum_onion_url.as_ref() {
@@ -237,20 +287,6 @@ fn socksify(proxy: Option<&str>) -> Option<String> {
     }
 }
 
-fn try_get_fee_estimates(client: &Client) -> Result<Vec<FeeEstimate>, Error> {
-    let relay_fee = (client.relay_fee()? * 100_000_000.0) as u64;
-    let blocks: Vec<usize> = (1..25).collect();
-    // max is covering a rounding errors in production electrs which sometimes cause a fee
-    // estimates lower than relay fee
-    let mut estimates: Vec<FeeEstimate> = client
-        .batch_estimate_fee(blocks)?
-        .iter()
-        .map(|e| FeeEstimate(relay_fee.max((*e * 100_000_000.0) as u64)))
-        .collect();
-    estimates.insert(0, FeeEstimate(relay_fee));
-    Ok(estimates)
-}
-
 impl ElectrumSession {
     pub fn create_session(
         network: NetworkParameters,
@@ -266,11 +302,14 @@ impl ElectrumSession {
             handles: vec![],
             user_wants_to_sync: Arc::new(AtomicBool::new(false)),
             last_network_call_succeeded: Arc::new(AtomicBool::new(false)),
+            rebuilding_cache: Arc::new(AtomicBool::new(false)),
+            spv_servers_status: Arc::new(RwLock::new(HashMap::new())),
+            sync_status: Arc::new(RwLock::new(SyncStatus::default())),
+            initial_sync_done: Arc::new(AtomicBool::new(false)),
             timeout: None,
             store: None,
             master_xpub: None,
             master_xprv: None,
-            recent_spent_utxos: Arc::new(RwLock::new(HashSet::<BEOutPoint>::new())),
         }
     }
 
@@ -394,6 +433,7 @@ impl ElectrumSession {
             Ok(Credentials {
                 mnemonic: std::str::from_utf8(&decrypted)?.to_string(),
                 bip39_passphrase: "".to_string(),
+                encryption_key: None,
             })
         }
     }
@@ -407,7 +447,9 @@ impl ElectrumSession {
             path.push(wallet_hash_id);
 
             info!("Store root path: {:?}", path);
-            let store = StoreMeta::new(&path, &opt.master_xpub, self.network.id())?;
+            let user_key = opt.encryption_key.as_deref().map(parse_store_key).transpose()?;
+            let store =
+                StoreMeta::open(&path, &opt.master_xpub, user_key.as_deref(), self.network.id())?;
             let store = Arc::new(RwLock::new(store));
             self.store = Some(store);
         }
@@ -442,6 +484,102 @@ impl ElectrumSession {
         })
     }
 
+    /// Export settings, memos and subaccount settings in an encrypted backup, which can be
+    /// imported by the same wallet on another device
+    pub fn export_wallet_backup(&self) -> Result<ExportWalletBackupResult, Error> {
+        let backup = self.store()?.read()?.export_backup()?;
+        Ok(ExportWalletBackupResult {
+            backup: backup.to_hex(),
+        })
+    }
+
+    /// Merge a backup returned by `export_wallet_backup` in the store, it needs to be called after
+    /// `load_store`
+    pub fn import_wallet_backup(
+        &mut self,
+        opt: &ImportWalletBackupOpt,
+    ) -> Result<ImportWalletBackupResult, Error> {
+        let backup = Vec::<u8>::from_hex(&opt.backup)?;
+        let result = self.store()?.write()?.import_backup(&backup, opt.overwrite)?;
+        if result.settings {
+            self.notify.settings(&self.get_settings()?);
+        }
+        Ok(result)
+    }
+
+    /// Encrypt the store with a new key, it needs to be called after `load_store`
+    pub fn rotate_store_encryption_key(&mut self, opt: &RotateStoreKeyOpt) -> Result<(), Error> {
+        let master_xpub = self.master_xpub.ok_or_else(|| Error::WalletNotInitialized)?;
+        let user_key = opt.encryption_key.as_deref().map(parse_store_key).transpose()?;
+        self.store()?.write()?.rotate_key(&master_xpub, user_key.as_deref())
+    }
+
+    /// Check the internal consistency of the cache, it needs to be called after `load_store`
+    pub fn verify_store(&self) -> Result<VerifyStoreResult, Error> {
+        Ok(self.store()?.read()?.verify())
+    }
+
+    /// Reset the cache, keeping the store, so that the syncer populates it again from scratch.
+    ///
+    /// The progress is reported with `cache_rebuild` notifications.
+    pub fn rebuild_cache(&mut self) -> Result<(), Error> {
+        let total_subaccounts = {
+            let store = self.store()?;
+            let mut store_write = store.write()?;
+            store_write.rebuild_cache()?;
+            store_write.cache.accounts.len()
+        };
+        self.rebuilding_cache.store(true, Ordering::Relaxed);
+        self.notify.cache_rebuild(&CacheRebuildNotification {
+            synced_subaccounts: 0,
+            total_subaccounts,
+            completed: false,
+        });
+        Ok(())
+    }
+
+    /// Returns the tip of the local headers chain and the outcome of the last SPV cross-validation
+    /// against every server, available only on bitcoin networks
+    pub fn get_spv_status(&self) -> Result<SPVStatus, Error> {
+        let network = match self.network.id() {
+            NetworkId::Bitcoin(network) => network,
+            NetworkId::Elements(_) => {
+                return Err(Error::Generic("SPV status is available only on bitcoin".into()))
+            }
+        };
+        let (tip_height, tip_hash) = {
+            let _lock = HEADERS_FILE_MUTEX
+                .get(&network)
+                .expect("unreachable because map populate with every enum variants")
+                .lock()?;
+            let checkpoint = Checkpoint::from_params(&self.network)?;
+            let chain =
+                HeadersChain::with_checkpoint(&self.network.state_dir, network, checkpoint)?;
+            (chain.height(), chain.tip().block_hash())
+        };
+        let minority_fork = match &self.store()?.read()?.cache.cross_validation_result {
+            Some(CrossValidationResult::Invalid(inv)) => inv.is_minority_fork(),
+            _ => false,
+        };
+        let mut servers: Vec<_> = self.spv_servers_status.read()?.values().cloned().collect();
+        servers.sort_by(|a, b| a.server.cmp(&b.server));
+
+        Ok(SPVStatus {
+            tip_height,
+            tip_hash: tip_hash.to_hex(),
+            minority_fork,
+            servers,
+        })
+    }
+
+    pub fn get_sync_status(&self) -> Result<SyncStatus, Error> {
+        let mut status = self.sync_status.read()?.clone();
+        if let Ok(store) = self.store() {
+            status.tip_height = status.tip_height.max(store.read()?.cache.tip_height());
+        }
+        Ok(status)
+    }
+
     pub fn store(&self) -> Result<Store, Error> {
         Ok(self.store.as_ref().ok_or_else(|| Error::StoreNotLoaded)?.clone())
     }
@@ -461,8 +599,10 @@ impl ElectrumSession {
         let (master_xprv, master_xpub, master_blinding_key) =
             keys_from_credentials(&credentials, self.network.bip32_network())?;
 
+        // A store encrypted with a user key needs the key, unless loaded with `load_store` before
         self.load_store(&LoadStoreOpt {
             master_xpub: master_xpub.clone(),
+            encryption_key: credentials.encryption_key.clone(),
         })?;
 
         if self.network.liquid {
@@ -502,6 +642,22 @@ impl ElectrumSession {
         }
     }
 
+    /// Returns a receiver of every notification emitted by the session from now on, as [`Event`]
+    ///
+    /// The receiver holds at most [`SUBSCRIPTION_CAPACITY`] pending events, the
+    /// ones exceeding it are dropped.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.notify.subscribe()
+    }
+
+    pub fn sync_progress(&self) -> SyncProgress {
+        SyncProgress {
+            status: self.sync_status.clone(),
+            initial_sync_done: self.initial_sync_done.clone(),
+            notify: self.notify.clone(),
+        }
+    }
+
     pub fn state_updater(&self) -> Result<StateUpdater, Error> {
         Ok(StateUpdater {
             current: self.last_network_call_succeeded.clone(),
@@ -529,6 +685,9 @@ impl ElectrumSession {
             None
         };
 
+        *self.sync_status.write()? = SyncStatus::default();
+        self.initial_sync_done.store(false, Ordering::Relaxed);
+
         {
             let store = self.store()?;
             let store_read = store.read()?;
@@ -548,14 +707,9 @@ impl ElectrumSession {
             info!("building built end");
             let fee_store = self.store()?;
             thread::spawn(move || {
-                match try_get_fee_estimates(&fee_client) {
-                    Ok(fee_estimates) => {
-                        fee_store.write().unwrap().cache.fee_estimates = fee_estimates
-                    }
-                    Err(e) => {
-                        warn!("can't update fee estimates {:?}", e)
-                    }
-                };
+                if let Err(e) = fees::update_fee_estimates(&fee_store, &fee_client) {
+                    warn!("can't update fee estimates {:?}", e)
+                }
             });
         }
 
@@ -564,16 +718,26 @@ impl ElectrumSession {
         if self.network.spv_enabled.unwrap_or(false) {
             let checker = match self.network.id() {
                 NetworkId::Bitcoin(network) => {
-                    ChainOrVerifier::Chain(HeadersChain::new(&self.network.state_dir, network)?)
+                    let checkpoint = Checkpoint::from_params(&self.network)?;
+                    let chain = HeadersChain::with_checkpoint(
+                        &self.network.state_dir,
+                        network,
+                        checkpoint,
+                    )?;
+                    ChainOrVerifier::Chain(chain)
                 }
                 NetworkId::Elements(network) => {
-                    let verifier = Verifier::new(network);
+                    let verifier = Verifier::load(&self.network.state_dir, network)?;
                     ChainOrVerifier::Verifier(verifier)
                 }
             };
 
-            let cross_validator =
-                SpvCrossValidator::from_network(&self.network, &self.proxy, self.timeout)?;
+            let cross_validator = SpvCrossValidator::from_network(
+                &self.network,
+                &self.proxy,
+                self.timeout,
+                self.spv_servers_status.clone(),
+            )?;
 
             let mut headers = Headers {
                 store: self.store()?,
@@ -584,6 +748,7 @@ impl ElectrumSession {
             let headers_url = self.url.clone();
             let proxy = self.proxy.clone();
             let notify_blocks = self.notify.clone();
+            let headers_progress = self.sync_progress();
             let chunk_size = DIFFCHANGE_INTERVAL as usize;
             let user_wants_to_sync = self.user_wants_to_sync.clone();
             let max_reorg_blocks = self.network.max_reorg_blocks.unwrap_or(144);
@@ -614,6 +779,14 @@ impl ElectrumSession {
                             }
                             match headers.ask(chunk_size, &client) {
                                 Ok(headers_found) => {
+                                    if let ChainOrVerifier::Chain(chain) = &headers.checker {
+                                        let tip_height =
+                                            headers.store.read().unwrap().cache.tip_height();
+                                        headers_progress.update(|s| {
+                                            s.headers_height = Some(chain.height());
+                                            s.tip_height = s.tip_height.max(tip_height);
+                                        });
+                                    }
                                     if headers_found < chunk_size {
                                         break;
                                     } else {
@@ -621,14 +794,18 @@ impl ElectrumSession {
                                     }
                                 }
                                 Err(Error::InvalidHeaders) => {
-                                    warn!("invalid headers");
-                                    // this should handle reorgs and also broke IO writes update
-                                    headers.store.write().unwrap().cache.txs_verif.clear();
-                                    if let Err(e) = headers.remove(max_reorg_blocks) {
-                                        warn!("failed removing headers: {:?}", e);
-                                        break;
+                                    warn!("invalid headers, looking for a reorg");
+                                    match headers.handle_reorg(&client, max_reorg_blocks) {
+                                        Ok(Some(reorg)) => notify_blocks.reorg(&reorg),
+                                        Ok(None) => {
+                                            warn!("headers not connecting to a known header");
+                                            break;
+                                        }
+                                        Err(e) => {
+                                            warn!("failed handling reorg: {:?}", e);
+                                            break;
+                                        }
                                     }
-                                    // XXX clear affected blocks/txs more surgically?
                                 }
                                 Err(e) => {
                                     warn!("error while asking headers {}", e);
@@ -659,6 +836,30 @@ impl ElectrumSession {
                                         &tip_hash,
                                         &tip_prev_hash,
                                     );
+                                    let result = &store_read.cache.cross_validation_result;
+                                    notify_blocks.spv_status(&SpvStatusNotification {
+                                        tip_height,
+                                        valid: result.as_ref().map_or(true, |r| r.is_valid()),
+                                        minority_fork: match result {
+                                            Some(CrossValidationResult::Invalid(inv)) => {
+                                                inv.is_minority_fork()
+                                            }
+                                            _ => false,
+                                        },
+                                    });
+                                    if let Some(CrossValidationResult::Invalid(inv)) = result {
+                                        if inv.is_minority_fork() {
+                                            notify_blocks.spv_minority_fork(
+                                                &MinorityForkNotification {
+                                                    our_height: inv.our_height,
+                                                    common_ancestor: inv.common_ancestor,
+                                                    longest_height: inv.longest_height,
+                                                    work_diff: work_to_hex(&inv.work_diff),
+                                                    server: inv.origin_server.url().to_string(),
+                                                },
+                                            );
+                                        }
+                                    }
                                 }
                             }
                         }
@@ -670,12 +871,15 @@ impl ElectrumSession {
             self.handles.push(headers_handle);
         }
 
-        let syncer = Syncer {
+        let mut syncer = Syncer {
             accounts: self.accounts.clone(),
             store: self.store()?,
             master_blinding: master_blinding.clone(),
             network: self.network.clone(),
-            recent_spent_utxos: self.recent_spent_utxos.clone(),
+            notify: self.notify.clone(),
+            rebuilding_cache: self.rebuilding_cache.clone(),
+            progress: self.sync_progress(),
+            confirmed_txs: None,
         };
 
         let tipper = Tipper {
@@ -700,6 +904,9 @@ impl ElectrumSession {
                         Ok(Some((height, header))) => {
                             // This is a new block
                             notify_blocks.block_from_header(height, &header);
+                            if let Err(e) = fees::update_fee_estimates(&tipper.store, &client) {
+                                warn!("can't update fee estimates {:?}", e)
+                            }
                         }
                         Err(e) => {
                             warn!("exception in tipper {:?}", e);
@@ -955,6 +1162,27 @@ impl ElectrumSession {
         store.get_tx_entry(&txid).map(|e| e.into())
     }
 
+    /// Return the unblinded values of the wallet outputs of the given transaction, so that they
+    /// can be shared with a third party without disclosing the master blinding key.
+    pub fn get_unblinded_transaction(&self, txid: &str) -> Result<UnblindedTransaction, Error> {
+        if !self.network.liquid {
+            return Err(Error::Generic("get_unblinded_transaction is only for Liquid".into()));
+        }
+        let txid = BETxid::from_hex(txid, self.network.id())?;
+        let store = self.store()?;
+        let store = store.read()?;
+        let tx = &store.get_tx_entry(&txid)?.tx;
+        let elements_txid = txid.ref_elements().expect("checked network is liquid");
+        let mut outputs = vec![];
+        for vout in 0..tx.output_len() as u32 {
+            let outpoint = elements::OutPoint::new(*elements_txid, vout);
+            if let Some(secrets) = store.get_unblinded(&outpoint) {
+                outputs.push(UnblindedOutput::new(vout, secrets));
+            }
+        }
+        Ok(UnblindedTransaction::new(txid.to_hex(), outputs))
+    }
+
     pub fn get_balance(&self, opt: &GetBalanceOpt) -> Result<Balances, Error> {
         let mut result = HashMap::new();
         // bitcoin balance is always set even if 0
@@ -991,12 +1219,14 @@ impl ElectrumSession {
         Ok(())
     }
 
-    fn remove_recent_spent_utxos(&self, tx_req: &mut CreateTransaction) -> Result<(), Error> {
+    /// Remove from `utxos` the outputs spent by the pending transactions, the server might not
+    /// have indexed them yet
+    fn remove_pending_spent_utxos(&self, utxos: &mut CreateTxUtxos) -> Result<(), Error> {
         let id = self.network.id();
-        let recent_spent_utxos = self.recent_spent_utxos.read()?;
-        for asset_utxos in tx_req.utxos.values_mut() {
+        let pending_spent = self.store()?.read()?.pending_spent_outpoints();
+        for asset_utxos in utxos.values_mut() {
             asset_utxos.retain(|u| {
-                u.outpoint(id).ok().map(|o| !(*recent_spent_utxos).contains(&o)).unwrap_or(false)
+                u.outpoint(id).ok().map(|o| !pending_spent.contains(&o)).unwrap_or(false)
             });
         }
         Ok(())
@@ -1008,10 +1238,114 @@ impl ElectrumSession {
     ) -> Result<TransactionMeta, Error> {
         info!("electrum create_transaction {:?}", tx_req);
 
-        self.remove_recent_spent_utxos(tx_req)?;
+        self.remove_pending_spent_utxos(&mut tx_req.utxos)?;
         self.get_account(tx_req.subaccount)?.create_tx(tx_req)
     }
 
+    /// The unspent outputs of `subaccount` in the form expected by `CreateTransaction::utxos`,
+    /// without the ones spent by pending transactions
+    fn create_tx_utxos(
+        &self,
+        subaccount: u32,
+        num_confs: u32,
+        confidential_utxos_only: bool,
+    ) -> Result<CreateTxUtxos, Error> {
+        let opt = GetUnspentOpt {
+            subaccount,
+            num_confs: Some(num_confs),
+            confidential_utxos_only: Some(confidential_utxos_only),
+            all_coins: None,
+        };
+        let unspent_outputs = self.get_unspent_outputs(&opt)?;
+        let mut utxos = unspent_outputs
+            .0
+            .into_iter()
+            .map(|(asset, utxos)| {
+                let utxos = utxos
+                    .into_iter()
+                    .map(|u| CreateTxUtxo {
+                        txid: u.txhash,
+                        vout: u.pt_idx,
+                    })
+                    .collect();
+                (asset, utxos)
+            })
+            .collect();
+        self.remove_pending_spent_utxos(&mut utxos)?;
+        Ok(utxos)
+    }
+
+    /// The greatest amount of an asset that can be sent to the given address, built with the same
+    /// coin selection as a `send_all` transaction.
+    ///
+    /// Coins spent by pending transactions are excluded, like they are by `create_transaction`.
+    pub fn get_max_sendable(&self, opt: &GetMaxSendableOpt) -> Result<GetMaxSendableResult, Error> {
+        info!("electrum get_max_sendable {:?}", opt);
+        let asset_id = match self.network.id() {
+            NetworkId::Bitcoin(_) if opt.asset_id.is_some() => return Err(Error::InvalidAssetId),
+            NetworkId::Bitcoin(_) => None,
+            NetworkId::Elements(_) => {
+                Some(opt.asset_id.clone().unwrap_or(self.network.policy_asset_id()?.to_hex()))
+            }
+        };
+        let mut request = CreateTransaction {
+            addressees: vec![AddressAmount {
+                address: opt.address.clone(),
+                satoshi: 0,
+                asset_id: asset_id.clone(),
+                allow_unconfidential: opt.allow_unconfidential,
+            }],
+            fee_rate: opt.fee_rate,
+            subaccount: opt.subaccount,
+            send_all: true,
+            utxos: self.create_tx_utxos(
+                opt.subaccount,
+                opt.num_confs,
+                opt.confidential_utxos_only,
+            )?,
+            num_confs: opt.num_confs,
+            confidential_utxos_only: opt.confidential_utxos_only,
+            ..Default::default()
+        };
+        match self.get_account(opt.subaccount)?.create_tx(&mut request) {
+            Ok(tx) => Ok(GetMaxSendableResult {
+                satoshi: request.addressees[0].satoshi,
+                fee: tx.fee,
+                asset_id,
+            }),
+            Err(Error::InsufficientFunds) => Ok(GetMaxSendableResult {
+                satoshi: 0,
+                fee: 0,
+                asset_id,
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The fee a transaction would pay if created with `tx_req`, without marking its coins as
+    /// spent. If `tx_req` has no `utxos`, every unspent output of the subaccount is considered.
+    /// Coins spent by pending transactions are excluded, like they are by `create_transaction`.
+    pub fn preview_fee(&self, tx_req: &CreateTransaction) -> Result<PreviewFeeResult, Error> {
+        info!("electrum preview_fee {:?}", tx_req);
+        let mut request = tx_req.clone();
+        if request.utxos.is_empty() {
+            request.utxos = self.create_tx_utxos(
+                request.subaccount,
+                request.num_confs,
+                request.confidential_utxos_only,
+            )?;
+        } else {
+            self.remove_pending_spent_utxos(&mut request.utxos)?;
+        }
+        let tx = self.get_account(request.subaccount)?.create_tx(&mut request)?;
+        Ok(PreviewFeeResult {
+            fee: tx.fee,
+            fee_rate: request.fee_rate.unwrap_or_default(),
+            satoshi: tx.satoshi,
+            changes_used: tx.changes_used.unwrap_or(0),
+        })
+    }
+
     pub fn sign_transaction(&self, create_tx: &TransactionMeta) -> Result<TransactionMeta, Error> {
         info!("electrum sign_transaction {:?}", create_tx);
         let account_num = create_tx
@@ -1022,10 +1356,10 @@ impl ElectrumSession {
         self.get_account(account_num)?.sign(create_tx)
     }
 
-    fn set_recent_spent_utxos(&self, tx: &BETransaction) -> Result<(), Error> {
-        let mut recent_spent_utxos = self.recent_spent_utxos.write()?;
-        (*recent_spent_utxos).extend(tx.previous_outputs());
-        Ok(())
+    /// Persist a just broadcast transaction until it confirms, so that its inputs are not
+    /// selected again before the server indexes it and it's rebroadcast if it gets lost
+    fn insert_pending_tx(&self, tx: &BETransaction) -> Result<(), Error> {
+        self.store()?.write()?.insert_pending_tx(tx, now_secs())
     }
 
     pub fn send_transaction(&mut self, tx: &TransactionMeta) -> Result<TransactionMeta, Error> {
@@ -1040,7 +1374,7 @@ impl ElectrumSession {
         // If sign transaction happens externally txid might not have been updated
         tx.txid = txid.to_string();
         let betx = BETransaction::deserialize(&tx_bytes[..], self.network.id())?;
-        self.set_recent_spent_utxos(&betx)?;
+        self.insert_pending_tx(&betx)?;
         Ok(tx)
     }
 
@@ -1051,7 +1385,7 @@ impl ElectrumSession {
         let client = self.url.build_client(self.proxy.as_deref(), None)?;
         let hex = Vec::<u8>::from_hex(tx_hex)?;
         let txid = client.transaction_broadcast_raw(&hex)?;
-        self.set_recent_spent_utxos(&transaction)?;
+        self.insert_pending_tx(&transaction)?;
         Ok(format!("{}", txid))
     }
 
@@ -1061,16 +1395,67 @@ impl ElectrumSession {
     /// network, while the remaining elements are the current estimates to use
     /// for a transaction to confirm from 1 to 24 blocks.
     pub fn get_fee_estimates(&mut self) -> Result<Vec<FeeEstimate>, Error> {
-        let min_fee = match self.network.id() {
-            NetworkId::Bitcoin(_) => 1000,
-            NetworkId::Elements(_) => 100,
-        };
-        let fee_estimates =
-            try_get_fee_estimates(&self.url.build_client(self.proxy.as_deref(), None)?)
-                .unwrap_or_else(|_| vec![FeeEstimate(min_fee); 25]);
-        self.store()?.write()?.cache.fee_estimates = fee_estimates.clone();
-        Ok(fee_estimates)
-        //TODO better implement default
+        let store = self.store()?;
+        let client = self.url.build_client(self.proxy.as_deref(), None)?;
+        Ok(fees::update_fee_estimates(&store, &client)
+            .unwrap_or_else(|_| store.read().unwrap().fee_estimates()))
+    }
+
+    /// The cached fee estimates keyed by the number of blocks within which a transaction paying
+    /// them is expected to confirm, updated on every new block
+    pub fn get_fee_estimates_by_target(&self) -> Result<FeeEstimatesByTarget, Error> {
+        let estimates = self.store()?.read()?.fee_estimates();
+        Ok(FeeEstimatesByTarget {
+            relay_fee: estimates[0].0,
+            estimates: estimates.iter().enumerate().skip(1).map(|(i, e)| (i as u32, e.0)).collect(),
+        })
+    }
+
+    /// Estimate in how many blocks a wallet transaction in the mempool is going to be confirmed,
+    /// according to the mempool fee histogram of the last fee estimates update, or to the fee
+    /// estimates if the histogram is not available
+    pub fn estimate_confirmation_time(
+        &self,
+        opt: &EstimateConfirmationTimeOpt,
+    ) -> Result<EstimateConfirmationTimeResult, Error> {
+        let txid = BETxid::from_hex(&opt.txid, self.network.id())?;
+        let store = self.store()?;
+        let store_read = store.read()?;
+        for account_num in store_read.account_nums() {
+            let acc_store = store_read.account_cache(account_num)?;
+            let txe = match acc_store.all_txs.get(&txid) {
+                Some(txe) if acc_store.heights.get(&txid) == Some(&None) => txe,
+                Some(_) => return Err(Error::Generic("transaction already confirmed".into())),
+                None => continue,
+            };
+            let fee = txe.tx.fee(
+                &acc_store.all_txs,
+                &acc_store.unblinded,
+                &self.network.policy_asset_id().ok(),
+            )?;
+            let fee_rate = txe.fee_rate(fee);
+            // The histogram is not persisted, until the next fee estimates update the persisted
+            // estimates are used
+            let histogram = &store_read.cache.fee_histogram;
+            let blocks = if histogram.is_empty() {
+                let estimates = store_read.fee_estimates();
+                fees::estimates_confirmation_blocks(&estimates, fee_rate).ok_or_else(|| {
+                    Error::Generic("fee rate too low to estimate the confirmation time".into())
+                })?
+            } else {
+                histogram.confirmation_blocks(fee_rate)
+            };
+            let block_minutes = match self.network.id() {
+                NetworkId::Bitcoin(_) => 10,
+                NetworkId::Elements(_) => 1,
+            };
+            return Ok(EstimateConfirmationTimeResult {
+                fee_rate,
+                blocks,
+                minutes: blocks * block_minutes,
+            });
+        }
+        Err(Error::Generic("transaction not found".into()))
     }
 
     pub fn get_settings(&self) -> Result<Settings, Error> {
@@ -1143,6 +1528,15 @@ impl ElectrumSession {
     }
 }
 
+/// Parse the hex of a 32 bytes secret used to encrypt the store
+fn parse_store_key(hex: &str) -> Result<Vec<u8>, Error> {
+    let key = Vec::<u8>::from_hex(hex)?;
+    if key.len() != 32 {
+        return Err(Error::Generic("store encryption key must be 32 bytes".into()));
+    }
+    Ok(key)
+}
+
 pub fn keys_from_credentials(
     credentials: &Credentials,
     network: bitcoin::Network,
@@ -1228,9 +1622,16 @@ impl Headers {
                             .verify_tx_proof(txid.ref_bitcoin().unwrap(), height, proof)
                             .is_ok(),
                         ChainOrVerifier::Verifier(verifier) => {
-                            if let Some(BEBlockHeader::Elements(header)) =
-                                self.store.read()?.cache.headers.get(&height)
-                            {
+                            let header = self.store.read()?.cache.headers.get(&height).cloned();
+                            if let Some(BEBlockHeader::Elements(header)) = header {
+                                let get_headers =
+                                    |heights: &[u32]| download_headers(client, heights);
+                                if let Err(e) = verifier.follow_federation(&header, &get_headers) {
+                                    warn!(
+                                        "cannot follow the federation of block {}: {:?}",
+                                        height, e
+                                    );
+                                }
                                 verifier
                                     .verify_tx_proof(txid.ref_elements().unwrap(), proof, &header)
                                     .is_ok()
@@ -1266,11 +1667,69 @@ impl Headers {
         Ok(proofs_done)
     }
 
-    pub fn remove(&mut self, headers: u32) -> Result<(), Error> {
-        if let ChainOrVerifier::Chain(chain) = &mut self.checker {
-            chain.remove(headers)?;
+    /// Roll back the headers that are no longer in the chain of the server and invalidate the SPV
+    /// validation of the transactions confirmed in them.
+    ///
+    /// If the fork is deeper than `max_depth`, the last `max_depth` headers are rolled back and
+    /// the fork is searched again when the next headers fail to connect. Returns `None` if no
+    /// header has been rolled back, meaning the server sent headers not connecting to our tip.
+    pub fn handle_reorg(
+        &mut self,
+        client: &Client,
+        max_depth: u32,
+    ) -> Result<Option<ReorgNotification>, Error> {
+        self.handle_reorg_with(max_depth, |start, count| {
+            Ok(client.block_headers(start as usize, count as usize)?.headers)
+        })
+    }
+
+    /// Like [`Headers::handle_reorg`], see [`HeadersChain::find_fork_with`] for `get_headers`
+    fn handle_reorg_with<F>(
+        &mut self,
+        max_depth: u32,
+        get_headers: F,
+    ) -> Result<Option<ReorgNotification>, Error>
+    where
+        F: FnMut(u32, u32) -> Result<Vec<bitcoin::BlockHeader>, Error>,
+    {
+        let chain = match &mut self.checker {
+            ChainOrVerifier::Chain(chain) => chain,
+            ChainOrVerifier::Verifier(_) => return Ok(None),
+        };
+        let tip = chain.height();
+        let fork = match chain.find_fork_with(max_depth, get_headers)? {
+            Some(fork) => fork,
+            None => {
+                warn!("no common ancestor in the last {} headers", max_depth);
+                chain.lowest_fork(max_depth)
+            }
+        };
+        if fork == tip {
+            return Ok(None);
         }
-        Ok(())
+        chain.remove(tip - fork)?;
+        info!("reorg, rolled back headers from height {} to {}", tip, fork);
+
+        let mut store_write = self.store.write()?;
+        let mut txids = HashSet::new();
+        for account_num in store_write.account_nums() {
+            let acc_store = store_write.account_cache(account_num)?;
+            txids.extend(
+                acc_store
+                    .heights
+                    .iter()
+                    .filter(|(_, h)| h.map_or(false, |h| h > fork))
+                    .map(|(t, _)| t.clone()),
+            );
+        }
+        for txid in txids.iter() {
+            store_write.cache.txs_verif.remove(txid);
+        }
+
+        Ok(Some(ReorgNotification {
+            disconnected_heights: (fork + 1..=tip).collect(),
+            txids: txids.into_iter().map(BETxidConvert::into_bitcoin).collect(),
+        }))
     }
 
     pub fn cross_validate(&mut self) -> bool {
@@ -1305,14 +1764,19 @@ struct DownloadTxResult {
 
 impl Syncer {
     /// Sync the wallet, return the set of updated accounts
-    pub fn sync(&self, client: &Client) -> Result<Vec<TransactionNotification>, Error> {
+    pub fn sync(&mut self, client: &Client) -> Result<Vec<TransactionNotification>, Error> {
         debug!("start sync");
         let start = Instant::now();
 
-        let accounts = self.accounts.read().unwrap();
+        let accounts_lock = self.accounts.clone();
+        let accounts = accounts_lock.read().unwrap();
         let mut updated_txs: HashMap<BETxid, TransactionNotification> = HashMap::new();
+        let mut removed_txs: HashMap<BETxid, TxRemovedNotification> = HashMap::new();
+        let mut conflict_ntfs: HashMap<BETxid, ConflictNotification> = HashMap::new();
+        self.progress.update(|s| s.total_subaccounts = accounts.len());
+        let rebuilding_cache = self.rebuilding_cache.load(Ordering::Relaxed);
 
-        for account in accounts.values() {
+        for (i, account) in accounts.values().enumerate() {
             let mut history_txs_id = HashSet::<BETxid>::new();
             let mut heights_set = HashSet::new();
             let mut txid_height = HashMap::<BETxid, _>::new();
@@ -1350,6 +1814,15 @@ impl Syncer {
 
                     let flattened: Vec<GetHistoryRes> = result.into_iter().flatten().collect();
                     trace!("{}/batch({}) {:?}", i, batch_count, flattened.len());
+                    self.progress.update(|s| {
+                        let status = s.subaccount_mut(account.num());
+                        let scanned = (batch_count + 1) * BATCH_SIZE;
+                        if is_internal {
+                            status.internal_scripts = scanned;
+                        } else {
+                            status.external_scripts = scanned;
+                        }
+                    });
 
                     if flattened.is_empty() {
                         break;
@@ -1386,14 +1859,117 @@ impl Syncer {
                 .iter()
                 .any(|(txid, height)| acc_store.heights.get(txid) != Some(height))
                 || acc_store.heights.keys().any(|txid| txid_height.get(txid).is_none());
+            for txid in acc_store.heights.keys().filter(|txid| !txid_height.contains_key(*txid)) {
+                let ntf = removed_txs.entry(*txid).or_insert_with(|| TxRemovedNotification {
+                    subaccounts: vec![],
+                    txid: txid.into_bitcoin(),
+                });
+                if let Err(pos) = ntf.subaccounts.binary_search(&account.num()) {
+                    ntf.subaccounts.insert(pos, account.num());
+                }
+            }
+
+            // Unconfirmed transactions no longer returned by the server may have been double
+            // spent, the conflicting transaction is searched among the wallet ones first
+            let mut conflicting = vec![];
+            let mut to_lookup = vec![];
+            let new_txs_map: HashMap<&BETxid, &BETransaction> =
+                new_txs.txs.iter().map(|(txid, tx)| (txid, tx)).collect();
+            let prevout_scripts = |tx: &BETransaction| -> HashMap<BEOutPoint, BEScript> {
+                tx.previous_outputs()
+                    .into_iter()
+                    .filter_map(|o| {
+                        let script = acc_store.all_txs.get_previous_output_script_pubkey(&o)?;
+                        Some((o, script))
+                    })
+                    .collect()
+            };
+            for (txid, _) in acc_store
+                .heights
+                .iter()
+                .filter(|(txid, height)| height.is_none() && !txid_height.contains_key(*txid))
+            {
+                let tx = match acc_store.all_txs.get(txid) {
+                    Some(txe) => &txe.tx,
+                    None => continue,
+                };
+                let in_wallet = txid_height.keys().find(|other_txid| {
+                    acc_store
+                        .all_txs
+                        .get(*other_txid)
+                        .map(|txe| &txe.tx)
+                        .or_else(|| new_txs_map.get(*other_txid).copied())
+                        .map_or(false, |other| spends_same_outputs(tx, other))
+                });
+                match in_wallet {
+                    Some(other_txid) => conflicting.push((*txid, tx.clone(), *other_txid)),
+                    None => to_lookup.push((*txid, tx.clone(), prevout_scripts(tx))),
+                }
+            }
+
+            // The conflicts are forgotten once the conflicting transaction has
+            // `required_num_blocks` confirmations, the double spent one can't confirm anymore
+            let tip_height = store_read.cache.tip_height();
+            let required = store_read.get_settings().unwrap_or_default().required_num_blocks.max(1);
+            let buried =
+                |height: Option<u32>| height.map_or(false, |h| tip_height + 1 >= h + required);
+            let mut settled = vec![];
+            let mut to_recheck = vec![];
+            for (txid, conflict) in acc_store.conflicts.iter() {
+                let other_txid = BETxid::from_hex(&conflict.txhash, self.network.id())?;
+                match txid_height.get(&other_txid) {
+                    Some(height) if buried(*height) => settled.push(*txid),
+                    Some(_) => (),
+                    None => {
+                        // not a wallet transaction, its height is in the history of the outputs
+                        // spent by the double spent one
+                        if let Some(txe) = acc_store.all_txs.get(txid) {
+                            to_recheck.push((*txid, txe.tx.clone(), prevout_scripts(&txe.tx)));
+                        }
+                    }
+                }
+            }
             drop(acc_store);
             drop(store_read);
 
+            for (txid, tx, scripts) in to_lookup {
+                match self.find_conflict(client, &txid, &tx, scripts) {
+                    Ok(Some((other_txid, _))) => conflicting.push((txid, tx, other_txid)),
+                    Ok(None) => info!("transaction {} evicted from the mempool", txid),
+                    Err(e) => {
+                        warn!("can't look for transactions conflicting with {}: {:?}", txid, e)
+                    }
+                }
+            }
+            for (txid, tx, scripts) in to_recheck {
+                match self.find_conflict(client, &txid, &tx, scripts) {
+                    Ok(Some((_, height))) if buried(height) => settled.push(txid),
+                    Ok(_) => (),
+                    Err(e) => warn!("can't look for the conflict of {}: {:?}", txid, e),
+                }
+            }
+            let mut conflicts = HashMap::new();
+            for (txid, tx, other_txid) in conflicting {
+                let conflict = TxConflict::new(&tx, other_txid.to_string());
+                info!("transaction {} double spent: {:?}", txid, conflict);
+                let ntf = conflict_ntfs.entry(txid).or_insert_with(|| ConflictNotification {
+                    subaccounts: vec![],
+                    txid: txid.into_bitcoin(),
+                    conflicting_txid: other_txid.into_bitcoin(),
+                    type_: conflict.type_,
+                });
+                if let Err(pos) = ntf.subaccounts.binary_search(&account.num()) {
+                    ntf.subaccounts.insert(pos, account.num());
+                }
+                conflicts.insert(txid, conflict);
+            }
+
             let changed = if !new_txs.txs.is_empty()
                 || !headers.is_empty()
                 || store_indexes != last_used
                 || !scripts.is_empty()
                 || txs_heights_changed
+                || !settled.is_empty()
             {
                 info!(
                     "There are changes in the store new_txs:{:?} headers:{:?} txid_height:{:?}",
@@ -1415,6 +1991,17 @@ impl Syncer {
                 // could disappear from the list, we clear the list and keep only the last values returned by the server
                 acc_store.heights.clear();
                 acc_store.heights.extend(txid_height.into_iter());
+                acc_store.conflicts.extend(conflicts);
+                let reappeared: Vec<BETxid> = acc_store
+                    .conflicts
+                    .keys()
+                    .filter(|txid| acc_store.heights.contains_key(*txid))
+                    .cloned()
+                    .collect();
+                for txid in reappeared.iter().chain(settled.iter()) {
+                    info!("forgetting the conflict of transaction {}", txid);
+                    acc_store.conflicts.remove(txid);
+                }
                 acc_store.scripts.extend(scripts.clone().into_iter().map(|(a, b)| (b, a)));
                 acc_store.paths.extend(scripts.into_iter());
 
@@ -1464,15 +2051,181 @@ impl Syncer {
                 changed,
                 start.elapsed().as_millis()
             );
+            if rebuilding_cache {
+                self.notify.cache_rebuild(&CacheRebuildNotification {
+                    synced_subaccounts: i + 1,
+                    total_subaccounts: accounts.len(),
+                    completed: false,
+                });
+            }
+            self.progress.update(|s| {
+                s.subaccount_mut(account.num()).synced = true;
+                if !s.completed {
+                    s.synced_subaccounts = i + 1;
+                }
+            });
         }
 
-        self.empty_recent_spent_utxos()?;
+        if let Err(e) = self.update_pending_txs(client) {
+            warn!("updating pending transactions failed: {:?}", e);
+        }
+        for ntf in removed_txs.values() {
+            info!("transaction {} removed", ntf.txid);
+            self.notify.tx_removed(ntf);
+        }
+        for ntf in conflict_ntfs.values() {
+            self.notify.conflict(ntf);
+        }
+        for ntf in self.update_confirmed_txs()? {
+            self.notify.confirmation(&ntf);
+        }
+        let tip_height = self.store.read()?.cache.tip_height();
+        self.progress.update(|s| {
+            if !s.completed {
+                info!("initial sync completed in {}ms", start.elapsed().as_millis());
+            }
+            s.completed = true;
+            s.synced_subaccounts = accounts.len();
+            s.tip_height = s.tip_height.max(tip_height);
+        });
+        if rebuilding_cache {
+            info!("cache rebuilt in {}ms", start.elapsed().as_millis());
+            self.rebuilding_cache.store(false, Ordering::Relaxed);
+            self.notify.cache_rebuild(&CacheRebuildNotification {
+                synced_subaccounts: accounts.len(),
+                total_subaccounts: accounts.len(),
+                completed: true,
+            });
+            // Every transaction is new to the rebuilt cache, do not notify them
+            return Ok(vec![]);
+        }
         Ok(updated_txs.into_values().collect())
     }
 
-    fn empty_recent_spent_utxos(&self) -> Result<(), Error> {
-        let mut recent_spent_utxos = self.recent_spent_utxos.write()?;
-        *recent_spent_utxos = HashSet::new();
+    /// Returns the transactions reaching `required_num_blocks` confirmations since the last call.
+    ///
+    /// Nothing is returned by the first call, which only records the transactions already
+    /// confirmed enough at login.
+    fn update_confirmed_txs(&mut self) -> Result<Vec<ConfirmationNotification>, Error> {
+        let store_read = self.store.read()?;
+        let tip_height = store_read.cache.tip_height();
+        let required = store_read.get_settings().unwrap_or_default().required_num_blocks.max(1);
+
+        let mut confirmed: HashMap<BETxid, ConfirmationNotification> = HashMap::new();
+        for account_num in store_read.account_nums() {
+            let acc_store = store_read.account_cache(account_num)?;
+            for (txid, height) in acc_store.heights.iter() {
+                let height = match height {
+                    Some(height) => *height,
+                    None => continue,
+                };
+                let confirmations = (tip_height + 1).saturating_sub(height);
+                if confirmations < required {
+                    continue;
+                }
+                let ntf = confirmed.entry(*txid).or_insert_with(|| ConfirmationNotification {
+                    subaccounts: vec![],
+                    txid: txid.into_bitcoin(),
+                    block_height: height,
+                    confirmations,
+                });
+                if let Err(pos) = ntf.subaccounts.binary_search(&account_num) {
+                    ntf.subaccounts.insert(pos, account_num);
+                }
+            }
+        }
+
+        let previous = self.confirmed_txs.replace(confirmed.keys().cloned().collect());
+        Ok(match previous {
+            None => vec![],
+            Some(previous) => confirmed
+                .into_iter()
+                .filter(|(txid, _)| !previous.contains(txid))
+                .map(|(_, ntf)| ntf)
+                .collect(),
+        })
+    }
+
+    /// Look for a transaction spending some of the outputs spent by `tx` in the history of their
+    /// scripts, `scripts` contains the ones already known
+    ///
+    /// Returns the conflicting transaction with its height, if confirmed.
+    fn find_conflict(
+        &self,
+        client: &Client,
+        txid: &BETxid,
+        tx: &BETransaction,
+        mut scripts: HashMap<BEOutPoint, BEScript>,
+    ) -> Result<Option<(BETxid, Option<u32>)>, Error> {
+        let net = self.network.id();
+        let previous_outputs = tx.previous_outputs();
+        let missing: HashSet<bitcoin::Txid> = previous_outputs
+            .iter()
+            .filter(|o| !scripts.contains_key(o))
+            .map(|o| o.txid().into_bitcoin())
+            .collect();
+        if !missing.is_empty() {
+            let mut previous_txs = HashMap::new();
+            for vec in client.batch_transaction_get_raw(missing.iter())? {
+                let previous_tx = BETransaction::deserialize(&vec, net)?;
+                previous_txs.insert(previous_tx.txid(), previous_tx);
+            }
+            for o in previous_outputs.iter().filter(|o| !scripts.contains_key(o)) {
+                if let Some(previous_tx) = previous_txs.get(&o.txid()) {
+                    scripts.insert(o.clone(), previous_tx.output_script(o.vout()));
+                }
+            }
+        }
+
+        let b_scripts: Vec<bitcoin::Script> =
+            scripts.into_iter().map(|(_, s)| s.into_bitcoin()).collect();
+        let candidates: HashMap<bitcoin::Txid, Option<u32>> = client
+            .batch_script_get_history(b_scripts.iter())?
+            .into_iter()
+            .flatten()
+            .filter(|h| h.tx_hash.into_net(net) != *txid)
+            .map(|h| (h.tx_hash, Some(h.height as u32).filter(|_| h.height > 0)))
+            .collect();
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        for vec in client.batch_transaction_get_raw(candidates.keys())? {
+            let candidate = BETransaction::deserialize(&vec, net)?;
+            if spends_same_outputs(tx, &candidate) {
+                let height = candidates.get(&candidate.txid().into_bitcoin()).copied().flatten();
+                return Ok(Some((candidate.txid(), height)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Forget the pending transactions that confirmed or were double spent, and rebroadcast the
+    /// ones the server does not know about
+    fn update_pending_txs(&self, client: &Client) -> Result<(), Error> {
+        let pending_txs = self.store.read()?.get_pending_txs();
+        let now = now_secs();
+        for (txid, pending_tx) in pending_txs {
+            let action = {
+                let store_read = self.store.read()?;
+                pending_tx_action(&txid, &pending_tx, store_read.cache.accounts.values(), now)
+            };
+            match action {
+                PendingTxAction::Forget => {
+                    info!("pending transaction {} confirmed or double spent", txid);
+                    self.store.write()?.remove_pending_tx(&txid)?;
+                }
+                PendingTxAction::Wait => (),
+                PendingTxAction::Rebroadcast => {
+                    info!("rebroadcasting pending transaction {}", txid);
+                    if let Err(e) = client.transaction_broadcast_raw(&pending_tx.tx.serialize()) {
+                        // A rejection doesn't prove that the inputs have been spent by another
+                        // transaction, it stays pending until a conflicting spend is observed
+                        warn!("rebroadcasting pending transaction {} failed: {:?}", txid, e);
+                    }
+                    self.store.write()?.insert_pending_tx(&pending_tx.tx, now)?;
+                }
+            }
+        }
         Ok(())
     }
 
@@ -1543,12 +2296,20 @@ impl Syncer {
         // BETxid has to be converted into bitcoin::Txid for rust-electrum-client
         let txs_to_download: Vec<bitcoin::Txid> =
             history_txs_id.difference(&txs_in_db).map(BETxidConvert::into_bitcoin).collect();
+        self.progress.update(|s| {
+            let status = s.subaccount_mut(account_num);
+            status.txs_discovered = history_txs_id.len();
+            status.txs_downloaded = history_txs_id.len() - txs_to_download.len();
+        });
         if !txs_to_download.is_empty() {
-            let txs_bytes_downloaded = client.batch_transaction_get_raw(txs_to_download.iter())?;
             let mut txs_downloaded: Vec<BETransaction> = vec![];
-            for vec in txs_bytes_downloaded {
-                let tx = BETransaction::deserialize(&vec, self.network.id())?;
-                txs_downloaded.push(tx);
+            for chunk in txs_to_download.chunks(DOWNLOAD_TXS_CHUNK) {
+                for vec in client.batch_transaction_get_raw(chunk.iter())? {
+                    let tx = BETransaction::deserialize(&vec, self.network.id())?;
+                    txs_downloaded.push(tx);
+                }
+                self.progress
+                    .update(|s| s.subaccount_mut(account_num).txs_downloaded += chunk.len());
             }
             info!("txs_downloaded {:?}", txs_downloaded.len());
             let mut previous_txs_to_download = HashSet::new();
@@ -1648,6 +2409,64 @@ impl Syncer {
     }
 }
 
+/// Whether `tx` and `other` spend some of the same outputs
+fn spends_same_outputs(tx: &BETransaction, other: &BETransaction) -> bool {
+    let previous_outputs = tx.previous_outputs();
+    tx.txid() != other.txid()
+        && other.previous_outputs().iter().any(|o| previous_outputs.contains(o))
+}
+
+#[derive(Debug, PartialEq)]
+enum PendingTxAction {
+    /// It confirmed or a conflicting spend has been observed
+    Forget,
+    /// It's known to the server or it has been broadcast recently
+    Wait,
+    Rebroadcast,
+}
+
+/// What to do with the pending transaction `txid` given the wallet transactions in `accounts`
+///
+/// A conflicting spend is either a conflict detected by the sync or a confirmed wallet
+/// transaction spending some of the same outputs.
+fn pending_tx_action<'a>(
+    txid: &BETxid,
+    pending_tx: &PendingTx,
+    accounts: impl Iterator<Item = &'a RawAccountCache>,
+    now: u64,
+) -> PendingTxAction {
+    let mut in_history = false;
+    for acc_store in accounts {
+        match acc_store.heights.get(txid) {
+            Some(Some(_)) => return PendingTxAction::Forget,
+            Some(None) => in_history = true,
+            None => (),
+        }
+        if acc_store.conflicts.contains_key(txid) {
+            return PendingTxAction::Forget;
+        }
+        let spent_by_confirmed = acc_store
+            .heights
+            .iter()
+            .filter(|(_, height)| height.is_some())
+            .filter_map(|(other_txid, _)| acc_store.all_txs.get(other_txid))
+            .any(|txe| spends_same_outputs(&pending_tx.tx, &txe.tx));
+        if spent_by_confirmed {
+            return PendingTxAction::Forget;
+        }
+    }
+    if in_history || now < pending_tx.last_broadcast + PENDING_TX_REBROADCAST_SECS {
+        PendingTxAction::Wait
+    } else {
+        PendingTxAction::Rebroadcast
+    }
+}
+
+/// The current unix time in seconds
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
 fn wait_or_close(user_wants_to_sync: &Arc<AtomicBool>, interval: u32) -> bool {
     for _ in 0..(interval * 2) {
         if !user_wants_to_sync.load(Ordering::Relaxed) {
@@ -1676,9 +2495,159 @@ mod test {
         let credentials = Credentials {
             mnemonic: "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".to_string(),
             bip39_passphrase: "TREZOR".to_string(),
+            encryption_key: None,
         };
         let (master_xprv, _, _) =
             keys_from_credentials(&credentials, bitcoin::Network::Bitcoin).unwrap();
         assert_eq!(master_xprv.to_string(), "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF");
     }
+
+    #[test]
+    fn test_handle_reorg() {
+        use crate::headers::bitcoin::test::{mine, mine_chain, mine_fork};
+        use crate::headers::bitcoin::HeadersChain;
+        use bitcoin::hashes::Hash;
+        use std::str::FromStr;
+
+        let network = bitcoin::Network::Regtest;
+        let dir = tempfile::TempDir::new().unwrap();
+        let xpub = bitcoin::util::bip32::ExtendedPubKey::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let mut store = StoreMeta::new(dir.path(), &xpub, NetworkId::Bitcoin(network)).unwrap();
+        store.make_account(0, xpub, true).unwrap();
+        let txid = |i: u8| BETxid::from(bitcoin::Txid::from_inner([i; 32]));
+        let acc_store = store.account_cache_mut(0).unwrap();
+        acc_store.heights.insert(txid(1), Some(5));
+        acc_store.heights.insert(txid(2), Some(8));
+        acc_store.heights.insert(txid(3), None);
+        for i in 1..=2 {
+            store.cache.txs_verif.insert(txid(i), SPVVerifyTxResult::Verified);
+        }
+
+        let genesis = bitcoin::blockdata::constants::genesis_block(network).header;
+        let ours = mine_chain(&genesis, 10, mine);
+        let mut chain = HeadersChain::new(dir.path(), network).unwrap();
+        chain.push(ours.clone()).unwrap();
+        let mut headers = Headers {
+            store: Arc::new(RwLock::new(store)),
+            checker: ChainOrVerifier::Chain(chain),
+            cross_validator: None,
+        };
+
+        // the server chain forks after height 6
+        let mut server = vec![genesis];
+        server.extend_from_slice(&ours[..6]);
+        server.extend(mine_chain(&ours[5], 6, mine_fork));
+        let get_headers = |start: u32, count: u32| -> Result<Vec<bitcoin::BlockHeader>, Error> {
+            let end = ((start + count) as usize).min(server.len());
+            Ok(server[(start as usize).min(end)..end].to_vec())
+        };
+        let verified = |headers: &Headers, i: u8| {
+            headers.store.read().unwrap().cache.txs_verif.contains_key(&txid(i))
+        };
+
+        // deeper than `max_depth`, the last `max_depth` headers are rolled back
+        let ntf = headers.handle_reorg_with(2, get_headers).unwrap().unwrap();
+        assert_eq!(ntf.disconnected_heights, vec![9, 10]);
+        assert!(ntf.txids.is_empty());
+        assert!(verified(&headers, 1) && verified(&headers, 2));
+
+        let ntf = headers.handle_reorg_with(144, get_headers).unwrap().unwrap();
+        assert_eq!(ntf.disconnected_heights, vec![7, 8]);
+        assert_eq!(ntf.txids, vec![txid(2).into_bitcoin()]);
+        assert!(verified(&headers, 1));
+        assert!(!verified(&headers, 2));
+        match &headers.checker {
+            ChainOrVerifier::Chain(chain) => assert_eq!(chain.tip(), ours[5]),
+            _ => unreachable!(),
+        }
+
+        // the tip is in the chain of the server
+        assert!(headers.handle_reorg_with(144, get_headers).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pending_tx_action() {
+        let spending = |vout: u32, lock_time: u32| {
+            let prev_txid = bitcoin::Txid::default();
+            BETransaction::Bitcoin(bitcoin::Transaction {
+                version: 2,
+                lock_time,
+                input: vec![bitcoin::TxIn {
+                    previous_output: bitcoin::OutPoint::new(prev_txid, vout),
+                    ..Default::default()
+                }],
+                output: vec![],
+            })
+        };
+        let tx = spending(0, 0);
+        let txid = tx.txid();
+        let pending_tx = PendingTx {
+            tx: tx.clone(),
+            last_broadcast: 100,
+        };
+        let action = |acc_store: &RawAccountCache, now: u64| {
+            pending_tx_action(&txid, &pending_tx, std::iter::once(acc_store), now)
+        };
+
+        // not known to the server, e.g. rejected at the last broadcast: rebroadcast it periodically
+        let mut acc_store = RawAccountCache::default();
+        assert_eq!(action(&acc_store, 100), PendingTxAction::Wait);
+        assert_eq!(
+            action(&acc_store, 100 + PENDING_TX_REBROADCAST_SECS),
+            PendingTxAction::Rebroadcast
+        );
+
+        acc_store.heights.insert(txid, None);
+        assert_eq!(action(&acc_store, 1000), PendingTxAction::Wait);
+        acc_store.heights.insert(txid, Some(10));
+        assert_eq!(action(&acc_store, 1000), PendingTxAction::Forget);
+
+        let mut acc_store = RawAccountCache::default();
+        acc_store.conflicts.insert(txid, TxConflict::new(&tx, spending(0, 1).txid().to_string()));
+        assert_eq!(action(&acc_store, 1000), PendingTxAction::Forget);
+
+        // only a confirmed spend of the same outputs makes it forgotten
+        let mut acc_store = RawAccountCache::default();
+        for other in [spending(1, 0), spending(0, 1)] {
+            acc_store.heights.insert(other.txid(), None);
+            acc_store.all_txs.insert(other.txid(), other.into());
+        }
+        assert_eq!(action(&acc_store, 1000), PendingTxAction::Rebroadcast);
+        acc_store.heights.insert(spending(1, 0).txid(), Some(10));
+        assert_eq!(action(&acc_store, 1000), PendingTxAction::Rebroadcast);
+        acc_store.heights.insert(spending(0, 1).txid(), Some(10));
+        assert_eq!(action(&acc_store, 1000), PendingTxAction::Forget);
+    }
+
+    #[test]
+    fn test_sync_progress() {
+        let progress = SyncProgress {
+            status: Arc::new(RwLock::new(SyncStatus::default())),
+            initial_sync_done: Arc::new(AtomicBool::new(false)),
+            notify: NativeNotif::new(),
+        };
+        let receiver = progress.notify.subscribe();
+        let notified = || {
+            receiver
+                .try_iter()
+                .filter_map(|e| match e {
+                    Event::SyncProgress(status) => Some(status.is_complete()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        progress.update(|s| s.tip_height = 100);
+        progress.update(|s| s.completed = true);
+        assert_eq!(notified(), vec![false, true]);
+
+        // a new tip after the initial sync doesn't restart the notifications
+        progress.update(|s| {
+            s.tip_height = 101;
+            s.headers_height = Some(100);
+        });
+        progress.update(|s| s.headers_height = Some(101));
+        assert!(notified().is_empty());
+        assert_eq!(progress.status.read().unwrap().headers_height, Some(101));
+    }
 }
@@ -1,16 +1,26 @@
 use crate::State;
+use gdk_common::be::BEBlockHash;
 use gdk_common::be::BEBlockHeader;
+use gdk_common::model::{ConflictType, Settings, SyncStatus, TransactionType};
 use gdk_common::wally::make_str;
-use gdk_common::{be::BEBlockHash, model::Settings, model::TransactionType};
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+
+/// The number of events a subscriber can leave unreceived, following ones are dropped
+pub const SUBSCRIPTION_CAPACITY: usize = 1024;
 
 type NativeType = (extern "C" fn(*const libc::c_void, *const libc::c_char), *const libc::c_void);
 #[derive(Clone)]
 pub struct NativeNotif {
     pub native: Option<NativeType>,
 
+    /// The in-process consumers receiving every notification as an [`Event`], alongside the
+    /// `native` handler
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+
     /// With testing feature notifications are simply pushed in the following vec so assertions
     /// could check over it, it's a mutex so that methods signatures doesn't need to be mut
     #[cfg(feature = "testing")]
@@ -18,7 +28,38 @@ pub struct NativeNotif {
 }
 unsafe impl Send for NativeNotif {}
 
-#[derive(Serialize, Deserialize)]
+struct Subscriber {
+    sender: SyncSender<Event>,
+
+    /// The number of events dropped since its queue became full
+    dropped: usize,
+}
+
+impl Subscriber {
+    /// Returns false if the subscription ended, an event not fitting the queue is dropped
+    fn send(&mut self, event: Event) -> bool {
+        match self.sender.try_send(event) {
+            Ok(()) => {
+                if self.dropped > 0 {
+                    info!("subscriber receiving again, {} events were dropped", self.dropped);
+                    self.dropped = 0;
+                }
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    warn!("subscriber queue full, dropping the following events");
+                }
+                self.dropped += 1;
+                true
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// Constructed by the `new_*` functions, each setting `event` and the field of its payload
+#[derive(Serialize, Deserialize, Default)]
 pub struct Notification {
     #[serde(skip_serializing_if = "Option::is_none")]
     network: Option<NetworkNotification>,
@@ -29,15 +70,73 @@ pub struct Notification {
     #[serde(skip_serializing_if = "Option::is_none")]
     block: Option<BlockNotification>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_rebuild: Option<CacheRebuildNotification>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reorg: Option<ReorgNotification>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spv_minority_fork: Option<MinorityForkNotification>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confirmation: Option<ConfirmationNotification>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tx_removed: Option<TxRemovedNotification>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spv_status: Option<SpvStatusNotification>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sync_progress: Option<SyncStatus>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    conflict: Option<ConflictNotification>,
+
     event: Kind,
 }
 
+/// A notification as received by the subscribers of [`NativeNotif::subscribe`]
+#[derive(Debug, Clone)]
+pub enum Event {
+    Network {
+        current: State,
+        next: State,
+    },
+    Transaction(TransactionNotification),
+    Block(BlockNotification),
+    Settings(Settings),
+    CacheRebuild(CacheRebuildNotification),
+    Reorg(ReorgNotification),
+    SpvMinorityFork(MinorityForkNotification),
+    Confirmation(ConfirmationNotification),
+    TxRemoved(TxRemovedNotification),
+    SpvStatus(SpvStatusNotification),
+    SyncProgress(SyncStatus),
+    Conflict(ConflictNotification),
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 enum Kind {
     Network,
     Transaction,
     Block,
+    CacheRebuild,
+    Reorg,
+    SpvMinorityFork,
+    Confirmation,
+    TxRemoved,
+    SpvStatus,
+    SyncProgress,
+    Conflict,
+}
+
+impl Default for Kind {
+    fn default() -> Self {
+        Kind::Network
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -47,7 +146,7 @@ struct NetworkNotification {
     wait_ms: u32,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TransactionNotification {
     /// The wallet subaccounts the transaction affects.
     pub subaccounts: Vec<u32>,
@@ -70,7 +169,7 @@ pub struct TransactionNotification {
     pub type_: Option<TransactionType>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct BlockNotification {
     /// The height of the block.
     pub block_height: u32,
@@ -82,6 +181,103 @@ pub struct BlockNotification {
     pub previous_hash: bitcoin::BlockHash,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CacheRebuildNotification {
+    /// The number of subaccounts synced from scratch so far.
+    pub synced_subaccounts: usize,
+
+    /// The number of subaccounts to sync.
+    pub total_subaccounts: usize,
+
+    /// Whether the cache rebuild is completed.
+    pub completed: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReorgNotification {
+    /// The heights of the blocks no longer in the best chain.
+    pub disconnected_heights: Vec<u32>,
+
+    /// The txids of the wallet transactions that were confirmed in the disconnected blocks.
+    #[serde(rename = "txhashes")]
+    pub txids: Vec<bitcoin::Txid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MinorityForkNotification {
+    /// The height of our local tip.
+    pub our_height: u32,
+
+    /// The height of the last block in common with the longest chain.
+    pub common_ancestor: u32,
+
+    /// The height of the longest chain.
+    pub longest_height: u32,
+
+    /// The extra work in the longest chain compared to our local one, in hex.
+    pub work_diff: String,
+
+    /// The server that we learned about the longest chain from.
+    pub server: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ConfirmationNotification {
+    /// The wallet subaccounts the transaction affects.
+    pub subaccounts: Vec<u32>,
+
+    /// The txid of the transaction.
+    #[serde(rename = "txhash")]
+    pub txid: bitcoin::Txid,
+
+    /// The height of the block confirming the transaction.
+    pub block_height: u32,
+
+    /// The number of confirmations, at least the `required_num_blocks` setting.
+    pub confirmations: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TxRemovedNotification {
+    /// The wallet subaccounts the transaction affected.
+    pub subaccounts: Vec<u32>,
+
+    /// The txid of the transaction no longer returned by the server, because replaced or
+    /// evicted from the mempool.
+    #[serde(rename = "txhash")]
+    pub txid: bitcoin::Txid,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConflictNotification {
+    /// The wallet subaccounts the double spent transaction affected.
+    pub subaccounts: Vec<u32>,
+
+    /// The txid of the double spent transaction.
+    #[serde(rename = "txhash")]
+    pub txid: bitcoin::Txid,
+
+    /// The txid of the transaction spending some of the same outputs.
+    #[serde(rename = "conflicting_txhash")]
+    pub conflicting_txid: bitcoin::Txid,
+
+    /// Whether the double spent transaction signaled replaceability.
+    #[serde(rename = "type")]
+    pub type_: ConflictType,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SpvStatusNotification {
+    /// The height of our local tip.
+    pub tip_height: u32,
+
+    /// Whether our chain matches the one of the other servers.
+    pub valid: bool,
+
+    /// Whether we are on a fork with less work than the one of another server.
+    pub minority_fork: bool,
+}
+
 impl Notification {
     pub fn new_network(current: State, next: State) -> Self {
         Notification {
@@ -90,44 +286,104 @@ impl Notification {
                 next_state: next,
                 wait_ms: 0,
             }),
-            transaction: None,
-            block: None,
             event: Kind::Network,
+            ..Default::default()
         }
     }
 
     pub fn new_transaction(ntf: &TransactionNotification) -> Self {
         Notification {
-            network: None,
             transaction: Some(ntf.clone()),
-            block: None,
             event: Kind::Transaction,
+            ..Default::default()
         }
     }
 
     pub fn new_block_from_hashes(height: u32, hash: &BEBlockHash, prev_hash: &BEBlockHash) -> Self {
         Notification {
-            network: None,
-            transaction: None,
             block: Some(BlockNotification {
                 block_height: height,
                 block_hash: hash.into_bitcoin(),
                 previous_hash: prev_hash.into_bitcoin(),
             }),
             event: Kind::Block,
+            ..Default::default()
         }
     }
 
     pub fn new_block_from_header(height: u32, header: &BEBlockHeader) -> Self {
         Notification {
-            network: None,
-            transaction: None,
             block: Some(BlockNotification {
                 block_height: height,
                 block_hash: header.block_hash().into_bitcoin(),
                 previous_hash: header.prev_block_hash().into_bitcoin(),
             }),
             event: Kind::Block,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_cache_rebuild(ntf: &CacheRebuildNotification) -> Self {
+        Notification {
+            cache_rebuild: Some(ntf.clone()),
+            event: Kind::CacheRebuild,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_reorg(ntf: &ReorgNotification) -> Self {
+        Notification {
+            reorg: Some(ntf.clone()),
+            event: Kind::Reorg,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_spv_minority_fork(ntf: &MinorityForkNotification) -> Self {
+        Notification {
+            spv_minority_fork: Some(ntf.clone()),
+            event: Kind::SpvMinorityFork,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_confirmation(ntf: &ConfirmationNotification) -> Self {
+        Notification {
+            confirmation: Some(ntf.clone()),
+            event: Kind::Confirmation,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_tx_removed(ntf: &TxRemovedNotification) -> Self {
+        Notification {
+            tx_removed: Some(ntf.clone()),
+            event: Kind::TxRemoved,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_spv_status(ntf: &SpvStatusNotification) -> Self {
+        Notification {
+            spv_status: Some(ntf.clone()),
+            event: Kind::SpvStatus,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_sync_progress(ntf: &SyncStatus) -> Self {
+        Notification {
+            sync_progress: Some(ntf.clone()),
+            event: Kind::SyncProgress,
+            ..Default::default()
+        }
+    }
+
+    pub fn new_conflict(ntf: &ConflictNotification) -> Self {
+        Notification {
+            conflict: Some(ntf.clone()),
+            event: Kind::Conflict,
+            ..Default::default()
         }
     }
 }
@@ -137,6 +393,7 @@ impl NativeNotif {
     pub fn new() -> Self {
         NativeNotif {
             native: None,
+            subscribers: Arc::new(Mutex::new(vec![])),
         }
     }
 
@@ -159,33 +416,111 @@ impl NativeNotif {
         self.native = Some(native_type);
     }
 
+    /// Returns a receiver of every notification emitted from now on, the subscription ends when
+    /// the receiver is dropped
+    ///
+    /// The channel is bounded to [`SUBSCRIPTION_CAPACITY`] events, so that a subscriber not
+    /// receiving doesn't grow the memory without limits: when it's full the following events are
+    /// dropped for that subscriber, without blocking the notifying thread.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = sync_channel(SUBSCRIPTION_CAPACITY);
+        self.subscribers.lock().unwrap().push(Subscriber {
+            sender,
+            dropped: 0,
+        });
+        receiver
+    }
+
+    fn send(&self, event: Event) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let current = std::mem::take(&mut *subscribers);
+        *subscribers =
+            current.into_iter().filter_map(|mut s| s.send(event.clone()).then(|| s)).collect();
+    }
+
+    /// Notify a block whose previous hash is unknown, it's left zeroed in the [`Event::Block`]
     pub fn block(&self, height: u32, hash: BEBlockHash) {
+        self.send(Event::Block(BlockNotification {
+            block_height: height,
+            block_hash: hash.into_bitcoin(),
+            previous_hash: Default::default(),
+        }));
         let data =
             json!({"block":{"block_height":height,"block_hash": hash.to_hex()},"event":"block"});
         self.notify(data);
     }
 
     pub fn block_from_hashes(&self, height: u32, hash: &BEBlockHash, prev_hash: &BEBlockHash) {
-        self.notify(Notification::new_block_from_hashes(height, hash, prev_hash));
+        let ntf = Notification::new_block_from_hashes(height, hash, prev_hash);
+        self.send(Event::Block(ntf.block.clone().expect("block notification")));
+        self.notify(ntf);
     }
 
     pub fn block_from_header(&self, height: u32, header: &BEBlockHeader) {
-        self.notify(Notification::new_block_from_header(height, &header));
+        let ntf = Notification::new_block_from_header(height, &header);
+        self.send(Event::Block(ntf.block.clone().expect("block notification")));
+        self.notify(ntf);
     }
 
     pub fn settings(&self, settings: &Settings) {
+        self.send(Event::Settings(settings.clone()));
         let data = json!({"settings":settings,"event":"settings"});
         self.notify(data);
     }
 
     pub fn updated_txs(&self, ntf: &TransactionNotification) {
+        self.send(Event::Transaction(ntf.clone()));
         self.notify(Notification::new_transaction(ntf));
     }
 
     pub fn network(&self, current: State, desired: State) {
+        self.send(Event::Network {
+            current,
+            next: desired,
+        });
         self.notify(Notification::new_network(current, desired));
     }
 
+    pub fn cache_rebuild(&self, ntf: &CacheRebuildNotification) {
+        self.send(Event::CacheRebuild(ntf.clone()));
+        self.notify(Notification::new_cache_rebuild(ntf));
+    }
+
+    pub fn reorg(&self, ntf: &ReorgNotification) {
+        self.send(Event::Reorg(ntf.clone()));
+        self.notify(Notification::new_reorg(ntf));
+    }
+
+    pub fn spv_minority_fork(&self, ntf: &MinorityForkNotification) {
+        self.send(Event::SpvMinorityFork(ntf.clone()));
+        self.notify(Notification::new_spv_minority_fork(ntf));
+    }
+
+    pub fn confirmation(&self, ntf: &ConfirmationNotification) {
+        self.send(Event::Confirmation(ntf.clone()));
+        self.notify(Notification::new_confirmation(ntf));
+    }
+
+    pub fn tx_removed(&self, ntf: &TxRemovedNotification) {
+        self.send(Event::TxRemoved(ntf.clone()));
+        self.notify(Notification::new_tx_removed(ntf));
+    }
+
+    pub fn spv_status(&self, ntf: &SpvStatusNotification) {
+        self.send(Event::SpvStatus(ntf.clone()));
+        self.notify(Notification::new_spv_status(ntf));
+    }
+
+    pub fn sync_progress(&self, ntf: &SyncStatus) {
+        self.send(Event::SyncProgress(ntf.clone()));
+        self.notify(Notification::new_sync_progress(ntf));
+    }
+
+    pub fn conflict(&self, ntf: &ConflictNotification) {
+        self.send(Event::Conflict(ntf.clone()));
+        self.notify(Notification::new_conflict(ntf));
+    }
+
     #[cfg(not(feature = "testing"))]
     pub fn push(&self, _value: Value) {
         //does nothing in non testing mode
@@ -197,6 +532,7 @@ impl NativeNotif {
     pub fn new() -> Self {
         NativeNotif {
             native: None,
+            subscribers: Arc::new(Mutex::new(vec![])),
             testing: std::sync::Arc::new(std::sync::Mutex::new(vec![])),
         }
     }
@@ -220,6 +556,7 @@ impl NativeNotif {
 mod test {
     use super::*;
     use crate::State;
+    use gdk_common::model::SubaccountSyncStatus;
 
     #[test]
     fn test_network_json() {
@@ -241,6 +578,148 @@ mod test {
         assert_eq!(expected, serde_json::to_value(&obj).unwrap());
     }
 
+    #[test]
+    fn test_cache_rebuild_json() {
+        let expected = json!({"event":"cache_rebuild","cache_rebuild":{"synced_subaccounts":1,"total_subaccounts":2,"completed":false}});
+        let obj = Notification::new_cache_rebuild(&CacheRebuildNotification {
+            synced_subaccounts: 1,
+            total_subaccounts: 2,
+            completed: false,
+        });
+        assert_eq!(expected, serde_json::to_value(&obj).unwrap());
+    }
+
+    #[test]
+    fn test_reorg_json() {
+        let expected = json!({"event":"reorg","reorg":{"disconnected_heights":[101,102],"txhashes":["0000000000000000000000000000000000000000000000000000000000000000"]}});
+        let obj = Notification::new_reorg(&ReorgNotification {
+            disconnected_heights: vec![101, 102],
+            txids: vec![bitcoin::Txid::default()],
+        });
+        assert_eq!(expected, serde_json::to_value(&obj).unwrap());
+    }
+
+    #[test]
+    fn test_spv_minority_fork_json() {
+        let expected = json!({"event":"spv_minority_fork","spv_minority_fork":{"our_height":105,"common_ancestor":100,"longest_height":107,"work_diff":"04","server":"electrum.example.com:50002"}});
+        let obj = Notification::new_spv_minority_fork(&MinorityForkNotification {
+            our_height: 105,
+            common_ancestor: 100,
+            longest_height: 107,
+            work_diff: "04".into(),
+            server: "electrum.example.com:50002".into(),
+        });
+        assert_eq!(expected, serde_json::to_value(&obj).unwrap());
+    }
+
+    #[test]
+    fn test_confirmation_json() {
+        let expected = json!({"event":"confirmation","confirmation":{"subaccounts":[0],"txhash":"0000000000000000000000000000000000000000000000000000000000000000","block_height":100,"confirmations":6}});
+        let obj = Notification::new_confirmation(&ConfirmationNotification {
+            subaccounts: vec![0],
+            txid: bitcoin::Txid::default(),
+            block_height: 100,
+            confirmations: 6,
+        });
+        assert_eq!(expected, serde_json::to_value(&obj).unwrap());
+    }
+
+    #[test]
+    fn test_tx_removed_json() {
+        let expected = json!({"event":"tx_removed","tx_removed":{"subaccounts":[0,1],"txhash":"0000000000000000000000000000000000000000000000000000000000000000"}});
+        let obj = Notification::new_tx_removed(&TxRemovedNotification {
+            subaccounts: vec![0, 1],
+            txid: bitcoin::Txid::default(),
+        });
+        assert_eq!(expected, serde_json::to_value(&obj).unwrap());
+    }
+
+    #[test]
+    fn test_spv_status_json() {
+        let expected = json!({"event":"spv_status","spv_status":{"tip_height":105,"valid":false,"minority_fork":true}});
+        let obj = Notification::new_spv_status(&SpvStatusNotification {
+            tip_height: 105,
+            valid: false,
+            minority_fork: true,
+        });
+        assert_eq!(expected, serde_json::to_value(&obj).unwrap());
+    }
+
+    #[test]
+    fn test_sync_progress_json() {
+        let expected = json!({"event":"sync_progress","sync_progress":{"synced_subaccounts":1,"total_subaccounts":2,"completed":false,"subaccounts":[{"subaccount":0,"external_scripts":40,"internal_scripts":20,"txs_discovered":10,"txs_downloaded":5,"synced":false}],"headers_height":null,"tip_height":120}});
+        let mut status = SyncStatus {
+            synced_subaccounts: 1,
+            total_subaccounts: 2,
+            tip_height: 120,
+            ..Default::default()
+        };
+        *status.subaccount_mut(0) = SubaccountSyncStatus {
+            subaccount: 0,
+            external_scripts: 40,
+            internal_scripts: 20,
+            txs_discovered: 10,
+            txs_downloaded: 5,
+            synced: false,
+        };
+        let obj = Notification::new_sync_progress(&status);
+        assert_eq!(expected, serde_json::to_value(&obj).unwrap());
+    }
+
+    #[test]
+    fn test_conflict_json() {
+        let expected = json!({"event":"conflict","conflict":{"subaccounts":[0],"txhash":"0000000000000000000000000000000000000000000000000000000000000000","conflicting_txhash":"0000000000000000000000000000000000000000000000000000000000000000","type":"replaced"}});
+        let obj = Notification::new_conflict(&ConflictNotification {
+            subaccounts: vec![0],
+            txid: bitcoin::Txid::default(),
+            conflicting_txid: bitcoin::Txid::default(),
+            type_: ConflictType::Replaced,
+        });
+        assert_eq!(expected, serde_json::to_value(&obj).unwrap());
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let notify = NativeNotif::new();
+        let receiver = notify.subscribe();
+        notify.reorg(&ReorgNotification {
+            disconnected_heights: vec![101],
+            txids: vec![],
+        });
+        notify.network(State::Disconnected, State::Connected);
+        match receiver.try_recv().unwrap() {
+            Event::Reorg(reorg) => assert_eq!(reorg.disconnected_heights, vec![101]),
+            e => panic!("unexpected event {:?}", e),
+        }
+        match receiver.try_recv().unwrap() {
+            Event::Network {
+                current,
+                next,
+            } => assert_eq!((current, next), (State::Disconnected, State::Connected)),
+            e => panic!("unexpected event {:?}", e),
+        }
+        assert!(receiver.try_recv().is_err());
+
+        // a subscriber not receiving doesn't block, the events beyond its capacity are dropped
+        for _ in 0..SUBSCRIPTION_CAPACITY + 2 {
+            notify.network(State::Connected, State::Connected);
+        }
+        assert_eq!(notify.subscribers.lock().unwrap()[0].dropped, 2);
+        assert_eq!(receiver.try_iter().count(), SUBSCRIPTION_CAPACITY);
+        assert_eq!(notify.subscribers.lock().unwrap().len(), 1);
+
+        notify.block(1, BEBlockHash::default());
+        match receiver.try_recv().unwrap() {
+            Event::Block(block) => assert_eq!(block.block_height, 1),
+            e => panic!("unexpected event {:?}", e),
+        }
+        assert_eq!(notify.subscribers.lock().unwrap()[0].dropped, 0);
+
+        drop(receiver);
+        notify.network(State::Connected, State::Connected);
+        assert!(notify.subscribers.lock().unwrap().is_empty());
+    }
+
     #[test]
     fn test_block_json() {
         let expected = json!({"block_height":0,"block_hash":"0000000000000000000000000000000000000000000000000000000000000000","previous_hash":"0000000000000000000000000000000000000000000000000000000000000000"});
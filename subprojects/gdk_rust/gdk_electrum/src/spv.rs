@@ -1,13 +1,17 @@
 use log::warn;
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use bitcoin::blockdata::constants::{max_target, DIFFCHANGE_INTERVAL, DIFFCHANGE_TIMESPAN};
 use bitcoin::BlockHash;
 use bitcoin::{util::uint::Uint256, util::BitArray, BlockHeader};
 use electrum_client::{Client as ElectrumClient, ElectrumApi};
 
+use gdk_common::model::{SPVFork, SPVServerStatus};
 use gdk_common::network::NetworkParameters;
 
 use crate::error::Error;
@@ -19,12 +23,23 @@ const MAX_CHUNK_SIZE: u32 = 200;
 const MAX_FORK_DEPTH: u32 = DIFFCHANGE_INTERVAL * 3;
 const SERVERS_PER_ROUND: usize = 3;
 
+/// The outcome of the last cross-validation against every server, by server url
+pub type ServersStatus = Arc<RwLock<HashMap<String, SPVServerStatus>>>;
+
 #[derive(Debug)]
 pub struct SpvCrossValidator {
     servers: Vec<ElectrumUrl>,
     proxy: Option<String>,
     last_result: CrossValidationResult,
     timeout: Option<u8>,
+    servers_status: ServersStatus,
+}
+
+/// The outcome of the cross-validation against a single server
+struct ServerCheck {
+    result: CrossValidationResult,
+    tip_height: u32,
+    fork: Option<SPVFork>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -93,21 +108,43 @@ impl SpvCrossValidator {
         // Cross-validate against the secondary servers, keeping track of the most severe
         // validation result seen so far
         for server_url in &round_servers {
-            let server_result = match spv_cross_validate(
+            let start = Instant::now();
+            let check = cross_validate_server(
                 chain,
                 &local_tip_hash,
                 server_url,
                 self.timeout,
                 &self.proxy,
-            ) {
-                Ok(r) => r,
+            );
+            let mut status = SPVServerStatus {
+                server: server_url.url().to_string(),
+                tip_height: None,
+                response_time_ms: start.elapsed().as_millis() as u64,
+                fork: None,
+                error: None,
+                last_checked: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+            };
+            let server_result = match check {
+                Ok(check) => {
+                    status.tip_height = Some(check.tip_height);
+                    status.fork = check.fork;
+                    Some(check.result)
+                }
                 Err(e) => {
                     warn!("SPV cross validation via {:?} failed with: {:?}", server_url, e);
-                    continue;
+                    status.error = Some(format!("{:?}", e));
+                    None
                 }
             };
-
-            curr_result = curr_result.merge(server_result);
+            if let Ok(mut servers_status) = self.servers_status.write() {
+                servers_status.insert(status.server.clone(), status);
+            }
+            if let Some(server_result) = server_result {
+                curr_result = curr_result.merge(server_result);
+            }
         }
 
         // Give some grace for minor digressions from the longest chain
@@ -127,6 +164,7 @@ impl SpvCrossValidator {
         network: &NetworkParameters,
         proxy: &Option<String>,
         timeout: Option<u8>,
+        servers_status: ServersStatus,
     ) -> Result<Option<Self>, Error> {
         Ok(if !network.liquid && network.spv_multi.unwrap_or(false) {
             Some(SpvCrossValidator {
@@ -134,6 +172,7 @@ impl SpvCrossValidator {
                 last_result: CrossValidationResult::Valid,
                 proxy: proxy.clone(),
                 timeout,
+                servers_status,
             })
         } else {
             None
@@ -154,21 +193,36 @@ pub fn spv_cross_validate(
     timeout: Option<u8>,
     proxy: &Option<String>,
 ) -> Result<CrossValidationResult, CrossValidationError> {
+    Ok(cross_validate_server(chain, local_tip_hash, server_url, timeout, proxy)?.result)
+}
+
+fn cross_validate_server(
+    chain: &HeadersChain,
+    local_tip_hash: &BlockHash,
+    server_url: &ElectrumUrl,
+    timeout: Option<u8>,
+    proxy: &Option<String>,
+) -> Result<ServerCheck, CrossValidationError> {
     let client = server_url.build_client(proxy.as_deref(), timeout)?;
     let remote_tip = client.block_headers_subscribe()?;
     let remote_tip_hash = remote_tip.header.block_hash();
     let remote_tip_height = remote_tip.height as u32;
+    let check = |result| ServerCheck {
+        result,
+        tip_height: remote_tip_height,
+        fork: None,
+    };
 
     // Both point to the same tip
     if remote_tip_height == chain.height() && remote_tip_hash == *local_tip_hash {
-        return Ok(CrossValidationResult::Valid);
+        return Ok(check(CrossValidationResult::Valid));
     }
 
     // The remote tip is lagging behind the local tip and can be fast-forwarded to it
     if chain.height() > remote_tip_height {
         let local_header = chain.get(remote_tip_height)?;
         if local_header.block_hash() == remote_tip_hash {
-            return Ok(CrossValidationResult::Valid);
+            return Ok(check(CrossValidationResult::Valid));
         }
     }
 
@@ -178,14 +232,14 @@ pub fn spv_cross_validate(
         if remote_header.block_hash() == *local_tip_hash {
             let fork = get_fork_branch(chain, &client, remote_tip_height, Some(chain.height()))?;
 
-            return Ok(CrossValidationResult::Invalid(CrossValidationInvalid {
+            return Ok(check(CrossValidationResult::Invalid(CrossValidationInvalid {
                 our_height: chain.height(),
                 longest_height: fork.tip_height,
                 longest_work: fork.total_fork_work,
                 common_ancestor: fork.common_ancestor,
                 work_diff: fork.total_fork_work,
                 origin_server: server_url.clone(),
-            }));
+            })));
         }
     }
 
@@ -196,21 +250,43 @@ pub fn spv_cross_validate(
 
     // The remote is on a minority fork chain
     if fork.total_fork_work <= our_work {
-        Ok(CrossValidationResult::Valid)
+        Ok(ServerCheck {
+            fork: Some(SPVFork {
+                common_ancestor: fork.common_ancestor,
+                fork_height: fork.tip_height,
+                work_diff: work_to_hex(&(our_work - fork.total_fork_work)),
+                local_is_minority: false,
+            }),
+            ..check(CrossValidationResult::Valid)
+        })
     }
     // We are on the minority fork
     else {
-        Ok(CrossValidationResult::Invalid(CrossValidationInvalid {
-            our_height: chain.height(),
-            longest_height: fork.tip_height,
-            longest_work: fork.total_fork_work,
-            common_ancestor: fork.common_ancestor,
-            work_diff: fork.total_fork_work - our_work,
-            origin_server: server_url.clone(),
-        }))
+        let work_diff = fork.total_fork_work - our_work;
+        Ok(ServerCheck {
+            fork: Some(SPVFork {
+                common_ancestor: fork.common_ancestor,
+                fork_height: fork.tip_height,
+                work_diff: work_to_hex(&work_diff),
+                local_is_minority: true,
+            }),
+            ..check(CrossValidationResult::Invalid(CrossValidationInvalid {
+                our_height: chain.height(),
+                longest_height: fork.tip_height,
+                longest_work: fork.total_fork_work,
+                common_ancestor: fork.common_ancestor,
+                work_diff,
+                origin_server: server_url.clone(),
+            }))
+        })
     }
 }
 
+/// Big endian hex representation of an amount of work
+pub fn work_to_hex(work: &Uint256) -> String {
+    work.0.iter().rev().map(|word| format!("{:016x}", word)).collect()
+}
+
 struct ForkBranch {
     tip_height: u32,
     common_ancestor: u32,
@@ -230,7 +306,7 @@ fn get_fork_branch(
     let sensible_target_threshold = chain.tip().target().mul_u32(4);
 
     // Will not reorg past that
-    let height_limit = known_ancestor.unwrap_or(0);
+    let height_limit = known_ancestor.unwrap_or(chain.base());
 
     let mut total_fork_work = Uint256::zero();
     let mut curr_retarget: Option<(u32, BlockHeader, Option<BlockHeader>)> = None;
@@ -356,6 +432,11 @@ pub fn calc_difficulty_retarget(first: &BlockHeader, last: &BlockHeader) -> Uint
 }
 
 impl CrossValidationInvalid {
+    /// Whether our local chain forked from the longest one, rather than just lagging behind it
+    pub fn is_minority_fork(&self) -> bool {
+        self.common_ancestor < self.our_height
+    }
+
     // Check whether the validation failure is still in effect, based
     // on the proof-of-work added to our local chain since the forking point
     fn is_resolved(&self, chain: &HeadersChain) -> bool {
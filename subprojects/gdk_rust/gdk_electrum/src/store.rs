@@ -1,5 +1,7 @@
 use crate::account::xpubs_equivalent;
+use crate::fees::FeeHistogram;
 use crate::spv::CrossValidationResult;
+use crate::unblind::verify_commitments;
 use crate::Error;
 use aes_gcm_siv::aead::{AeadInPlace, NewAead};
 use aes_gcm_siv::{Aes256GcmSiv, Key, Nonce};
@@ -9,9 +11,13 @@ use bitcoin::Transaction;
 use elements::TxOutSecrets;
 use gdk_common::be::BETxidConvert;
 use gdk_common::be::{
-    BEBlockHash, BEBlockHeader, BEScript, BETransaction, BETransactionEntry, BETransactions, BETxid,
+    BEBlockHash, BEBlockHeader, BEOutPoint, BEScript, BETransaction, BETransactionEntry,
+    BETransactions, BETxid,
+};
+use gdk_common::model::{
+    AccountSettings, FeeEstimate, ImportWalletBackupResult, SPVVerifyTxResult, Settings,
+    TxConflict, VerifyStoreResult,
 };
-use gdk_common::model::{AccountSettings, FeeEstimate, SPVVerifyTxResult, Settings};
 use gdk_common::wally::MasterBlindingKey;
 use gdk_common::NetworkId;
 use log::{info, warn};
@@ -19,14 +25,29 @@ use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::{Read, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use std::time::Instant;
 
 pub const BATCH_SIZE: u32 = 20;
 
+/// The cache log is compacted when it grows bigger than the cache file and at least this size
+const MIN_COMPACTION_SIZE: u64 = 1024 * 1024;
+
+/// Bytes added to each record in the cache log: length, nonce and authentication tag
+const RECORD_OVERHEAD: u64 = 4 + 12 + 16;
+
+/// Epoch of the cache log when there is no valid cache file, forces a compaction on next flush
+const NO_BASE: [u8; 12] = [0u8; 12];
+
+/// Magic bytes at the start of a wallet backup
+const BACKUP_MAGIC: &[u8; 4] = b"GDKB";
+
+/// Version of the wallet backup format
+const BACKUP_VERSION: u8 = 1;
+
 pub type Store = Arc<RwLock<StoreMeta>>;
 
 /// RawCache is a persisted and encrypted cache of wallet data, contains stuff like wallet transactions
@@ -45,6 +66,10 @@ pub struct RawCache {
     /// cached fee_estimates
     pub fee_estimates: Vec<FeeEstimate>,
 
+    /// mempool fee histogram used to compute the fee estimates, not persisted
+    #[serde(skip)]
+    pub fee_histogram: FeeHistogram,
+
     /// height and hash of tip of the blockchain
     #[deprecated(note = "Deprecated, use `tip_` instead")]
     pub tip: (u32, BEBlockHash),
@@ -105,6 +130,10 @@ pub struct RawAccountCache {
     /// download transactions.
     /// If None, the account was created before the addition of this field.
     pub bip44_discovered: Option<bool>,
+
+    /// The wallet txs double spent while unconfirmed, no longer in `heights` but still listed
+    #[serde(default)]
+    pub conflicts: HashMap<BETxid, TxConflict>,
 }
 
 /// RawStore contains data that are not extractable from xpub+blockchain
@@ -120,6 +149,27 @@ pub struct RawStore {
     // additional fields should always be appended at the end as an `Option` to retain db backwards compatibility
     /// account settings
     accounts_settings: Option<HashMap<u32, AccountSettings>>,
+
+    /// outgoing transactions broadcast by this wallet and not confirmed yet
+    pending_txs: Option<HashMap<BETxid, PendingTx>>,
+}
+
+/// A transaction broadcast by this wallet, kept until it confirms so that it can be rebroadcast
+/// and its inputs are not selected again while it's pending
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingTx {
+    pub tx: BETransaction,
+
+    /// unix time in seconds of the last broadcast
+    pub last_broadcast: u64,
+}
+
+/// The content of a wallet backup, the data of [`RawStore`] that can be restored on another device
+#[derive(Serialize, Deserialize)]
+struct WalletBackup {
+    settings: Option<Settings>,
+    memos: HashMap<bitcoin::Txid, String>,
+    accounts_settings: HashMap<u32, AccountSettings>,
 }
 
 pub struct StoreMeta {
@@ -128,14 +178,19 @@ pub struct StoreMeta {
     id: NetworkId,
     path: PathBuf,
     cipher: Aes256GcmSiv,
+    backup_cipher: Aes256GcmSiv,
     last: HashMap<Kind, sha256::Hash>,
+    cache_log: CacheLog,
+    cache_load_error: Option<String>,
     to_remove: bool,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Kind {
     Cache,
+    CacheLog,
     Store,
+    StoreKey,
 }
 
 impl Display for Kind {
@@ -143,15 +198,121 @@ impl Display for Kind {
         match self {
             Kind::Store => write!(f, "store"),
             Kind::Cache => write!(f, "cache"),
+            Kind::CacheLog => write!(f, "cache_log"),
+            Kind::StoreKey => write!(f, "store_key"),
         }
     }
 }
 
+/// The append-only log of the changes to the cache.
+///
+/// The cache is persisted in two files: the cache file, containing the whole cache as it was at
+/// the last compaction, and the log, containing the records changed since then. Loading the cache
+/// means loading the cache file and replaying the log on it.
+///
+/// The log starts with the nonce of the cache file it applies to (its epoch), so that a log left
+/// over by an interrupted compaction is discarded. Each record is then stored as its ciphertext
+/// length (u32 little endian), its nonce and its ciphertext.
+///
+/// To find the changes without serializing the whole cache, the log keeps the account fields as
+/// last persisted and diffs them with the current ones.
+struct CacheLog {
+    path: PathBuf,
+
+    /// nonce of the cache file the log applies to
+    epoch: [u8; 12],
+
+    /// length of the valid part of the log, 0 if the log must be rewritten
+    len: u64,
+
+    /// length of the cache file
+    base_len: u64,
+
+    /// hash of the last persisted global record
+    global: Option<sha256::Hash>,
+
+    /// the account fields as last persisted, except transactions, per account
+    accounts: HashMap<u32, RawAccountCache>,
+
+    /// persisted transactions, per account
+    txs: HashMap<u32, HashSet<BETxid>>,
+
+    /// hash of the persisted headers, per height
+    headers: HashMap<u32, BEBlockHash>,
+}
+
+/// A record of the cache log, borrowing the cache to avoid copies when serializing
+#[derive(Serialize)]
+enum RecordRef<'a> {
+    Global(GlobalRef<'a>),
+    AccountDiff(u32, &'a AccountDiff),
+    RemoveAccount(u32),
+    Tx(u32, &'a BETxid, &'a BETransactionEntry),
+    RemoveTx(u32, &'a BETxid),
+    Header(u32, &'a BEBlockHeader),
+    RemoveHeader(u32),
+}
+
+/// A record of the cache log, must deserialize what [`RecordRef`] serializes
+#[derive(Deserialize)]
+enum Record {
+    Global(GlobalRecord),
+    AccountDiff(u32, AccountDiff),
+    RemoveAccount(u32),
+    Tx(u32, BETxid, BETransactionEntry),
+    RemoveTx(u32, BETxid),
+    Header(u32, BEBlockHeader),
+    RemoveHeader(u32),
+}
+
+/// The cache fields not related to a specific account, except headers
+#[derive(Serialize)]
+struct GlobalRef<'a> {
+    txs_verif: &'a HashMap<BETxid, SPVVerifyTxResult>,
+    fee_estimates: &'a Vec<FeeEstimate>,
+    tip_: &'a Option<(u32, BEBlockHeader)>,
+    cross_validation_result: &'a Option<CrossValidationResult>,
+    accounts_recovered: bool,
+    master_blinding: &'a Option<MasterBlindingKey>,
+}
+
+#[derive(Deserialize)]
+struct GlobalRecord {
+    txs_verif: HashMap<BETxid, SPVVerifyTxResult>,
+    fee_estimates: Vec<FeeEstimate>,
+    tip_: Option<(u32, BEBlockHeader)>,
+    cross_validation_result: Option<CrossValidationResult>,
+    accounts_recovered: bool,
+    master_blinding: Option<MasterBlindingKey>,
+}
+
+/// The changes to the account cache fields, except transactions
+#[derive(Serialize, Deserialize)]
+struct AccountDiff {
+    paths: MapDiff<BEScript, DerivationPath>,
+    scripts: MapDiff<DerivationPath, BEScript>,
+    heights: MapDiff<BETxid, Option<u32>>,
+    unblinded: MapDiff<elements::OutPoint, TxOutSecrets>,
+    conflicts: MapDiff<BETxid, TxConflict>,
+    indexes: Indexes,
+    xpub: Option<ExtendedPubKey>,
+    bip44_discovered: Option<bool>,
+}
+
+/// The entries of a map inserted or changed, and the keys removed
+#[derive(Serialize, Deserialize)]
+struct MapDiff<K, V> {
+    inserted: Vec<(K, V)>,
+    removed: Vec<K>,
+}
+
 impl Drop for StoreMeta {
     fn drop(&mut self) {
         if self.to_remove && self.path.exists() {
             self.remove_file(Kind::Store);
             self.remove_file(Kind::Cache);
+            self.remove_file(Kind::CacheLog);
+            self.remove_file(Kind::StoreKey);
             std::fs::remove_dir(&self.path).unwrap();
         } else {
             self.flush().unwrap();
@@ -168,17 +329,39 @@ pub struct Indexes {
 impl RawCache {
     /// create a new RawCache, try to load data from a file or a fallback file
     /// errors such as corrupted file or model change in the db, result in a empty store that will be repopulated
-    fn new<P: AsRef<Path>>(path: P, cipher: &Aes256GcmSiv) -> Self {
-        Self::try_new(path, cipher).unwrap_or_else(|e| {
-            warn!("Initialize cache as default {:?}", e);
-            Default::default()
-        })
+    /// and the error is returned, unless the file is simply missing
+    /// A corrupted cache log tail is returned as error too, while the records before it are loaded
+    /// A cache file that can't be decrypted fails with `InvalidStoreKey` if `check_key`, that is
+    /// when a user key is in use and it has not already decrypted the store
+    fn new<P: AsRef<Path>>(
+        path: P,
+        cipher: &Aes256GcmSiv,
+        check_key: bool,
+    ) -> Result<(Self, CacheLog, Option<String>), Error> {
+        match Self::try_new(path.as_ref(), cipher) {
+            Ok((cache, log, log_error)) => Ok((cache, log, log_error)),
+            Err(Error::InvalidStoreKey) if check_key => Err(Error::InvalidStoreKey),
+            Err(e) => {
+                warn!("Initialize cache as default {:?}", e);
+                let exists = path.as_ref().join(Kind::Cache.to_string()).exists();
+                let error = Some(e.to_string()).filter(|_| exists);
+                Ok((Default::default(), CacheLog::new(path.as_ref(), NO_BASE), error))
+            }
+        }
     }
 
-    fn try_new<P: AsRef<Path>>(path: P, cipher: &Aes256GcmSiv) -> Result<Self, Error> {
-        let decrypted = load_decrypt(Kind::Cache, path, cipher)?;
-        let store = serde_cbor::from_slice(&decrypted)?;
-        Ok(store)
+    /// Load the cache file and replay the cache log on it.
+    ///
+    /// A cache file written before the introduction of the log is loaded as is, the log is created
+    /// on the next flush.
+    fn try_new<P: AsRef<Path>>(
+        path: P,
+        cipher: &Aes256GcmSiv,
+    ) -> Result<(Self, CacheLog, Option<String>), Error> {
+        let (nonce, decrypted) = load_decrypt(Kind::Cache, path.as_ref(), cipher)?;
+        let mut cache = serde_cbor::from_slice(&decrypted)?;
+        let (log, log_error) = CacheLog::replay(path.as_ref(), nonce, &mut cache, cipher)?;
+        Ok((cache, log, log_error))
     }
 
     // The following 3 functions are needed to handle the missing `tip_`.
@@ -210,25 +393,38 @@ impl RawCache {
 impl RawStore {
     /// create a new RawStore, try to load data from a file or a fallback file
     /// errors such as corrupted file or model change in the db, result in a empty store that will be repopulated
-    fn new<P: AsRef<Path>>(path: P, cipher: &Aes256GcmSiv) -> Self {
-        Self::try_new(path, cipher).unwrap_or_else(|e| {
-            warn!("Initialize store as default {:?}", e);
-            Default::default()
-        })
+    /// while, if `check_key`, a file that can't be decrypted fails with `InvalidStoreKey`, so that
+    /// it's not overwritten
+    /// Returns whether the store was loaded from the file too
+    fn new<P: AsRef<Path>>(
+        path: P,
+        cipher: &Aes256GcmSiv,
+        check_key: bool,
+    ) -> Result<(Self, bool), Error> {
+        match Self::try_new(path, cipher) {
+            Ok(store) => Ok((store, true)),
+            Err(Error::InvalidStoreKey) if check_key => Err(Error::InvalidStoreKey),
+            Err(e) => {
+                warn!("Initialize store as default {:?}", e);
+                Ok((Default::default(), false))
+            }
+        }
     }
 
     fn try_new<P: AsRef<Path>>(path: P, cipher: &Aes256GcmSiv) -> Result<Self, Error> {
-        let decrypted = load_decrypt(Kind::Store, path, cipher)?;
+        let (_, decrypted) = load_decrypt(Kind::Store, path, cipher)?;
         let store = serde_cbor::from_slice(&decrypted)?;
         Ok(store)
     }
 }
 
+/// Returns the nonce and the decrypted content of the file of `kind`, fails with `InvalidStoreKey`
+/// if it can't be decrypted with `cipher`, either because of a wrong key or a corrupted file
 fn load_decrypt<P: AsRef<Path>>(
     kind: Kind,
     path: P,
     cipher: &Aes256GcmSiv,
-) -> Result<Vec<u8>, Error> {
+) -> Result<([u8; 12], Vec<u8>), Error> {
     let now = Instant::now();
     let mut store_path = PathBuf::from(path.as_ref());
     store_path.push(kind.to_string());
@@ -242,11 +438,393 @@ fn load_decrypt<P: AsRef<Path>>(
     let mut ciphertext = vec![];
     file.read_to_end(&mut ciphertext)?;
 
-    cipher.decrypt_in_place(nonce, b"", &mut ciphertext)?;
+    cipher.decrypt_in_place(nonce, b"", &mut ciphertext).map_err(|_| Error::InvalidStoreKey)?;
     let plaintext = ciphertext;
 
     info!("loading {:?} took {}ms", &store_path, now.elapsed().as_millis());
-    Ok(plaintext)
+    Ok((nonce_bytes, plaintext))
+}
+
+fn backup_header() -> Vec<u8> {
+    let mut header = BACKUP_MAGIC.to_vec();
+    header.push(BACKUP_VERSION);
+    header
+}
+
+/// Encrypt `plaintext` with a random nonce, returns the nonce followed by the ciphertext
+fn encrypt(mut plaintext: Vec<u8>, cipher: &Aes256GcmSiv) -> Result<([u8; 12], Vec<u8>), Error> {
+    let mut nonce_bytes = [0u8; 12];
+    thread_rng().fill(&mut nonce_bytes);
+    let nonce = Nonce::from_slice(&nonce_bytes);
+    cipher.encrypt_in_place(nonce, b"", &mut plaintext)?;
+    Ok((nonce_bytes, plaintext))
+}
+
+impl CacheLog {
+    /// An empty log for the cache file with nonce `epoch` in `dir`
+    fn new(dir: &Path, epoch: [u8; 12]) -> Self {
+        let mut path = dir.to_path_buf();
+        path.push(Kind::CacheLog.to_string());
+        let mut base_path = dir.to_path_buf();
+        base_path.push(Kind::Cache.to_string());
+        CacheLog {
+            path,
+            epoch,
+            len: 0,
+            base_len: std::fs::metadata(&base_path).map(|m| m.len()).unwrap_or(0),
+            global: None,
+            accounts: HashMap::new(),
+            txs: HashMap::new(),
+            headers: HashMap::new(),
+        }
+    }
+
+    /// Replay the log in `dir` on `cache`, loaded from the cache file with nonce `epoch`.
+    ///
+    /// A log with a different epoch is discarded, while a truncated or corrupted record (for
+    /// instance because of a crash while appending) ends the replay, is returned as error and is
+    /// overwritten by the next append.
+    fn replay(
+        dir: &Path,
+        epoch: [u8; 12],
+        cache: &mut RawCache,
+        cipher: &Aes256GcmSiv,
+    ) -> Result<(Self, Option<String>), Error> {
+        let now = Instant::now();
+        let mut log = CacheLog::new(dir, epoch);
+        let error = match log.try_replay(cache, cipher) {
+            Ok(records) => {
+                info!(
+                    "replaying {} records of {:?} took {}ms",
+                    records,
+                    &log.path,
+                    now.elapsed().as_millis()
+                );
+                None
+            }
+            Err(e) => {
+                warn!("stop replaying {:?} {:?}", &log.path, e);
+                Some(format!("cache log: {}", e))
+            }
+        };
+        log.track(cache)?;
+        Ok((log, error))
+    }
+
+    fn try_replay(&mut self, cache: &mut RawCache, cipher: &Aes256GcmSiv) -> Result<usize, Error> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+        let mut data = vec![];
+        File::open(&self.path)?.read_to_end(&mut data)?;
+        if data.len() < self.epoch.len() || data[..self.epoch.len()] != self.epoch {
+            // left over by an interrupted compaction, the cache file already contains its records
+            info!("discarding {:?}, its epoch does not match", &self.path);
+            return Ok(0);
+        }
+        let mut pos = self.epoch.len();
+        self.len = pos as u64;
+        let mut records = 0;
+        while pos < data.len() {
+            if data.len() < pos + 16 {
+                return Err(Error::Generic("truncated cache log record".into()));
+            }
+            let mut len_bytes = [0u8; 4];
+            len_bytes.copy_from_slice(&data[pos..pos + 4]);
+            let end = pos + 16 + u32::from_le_bytes(len_bytes) as usize;
+            if data.len() < end {
+                return Err(Error::Generic("truncated cache log record".into()));
+            }
+            let nonce = Nonce::from_slice(&data[pos + 4..pos + 16]);
+            let mut plaintext = data[pos + 16..end].to_vec();
+            cipher.decrypt_in_place(nonce, b"", &mut plaintext)?;
+            let record: Record = serde_cbor::from_slice(&plaintext)?;
+            record.apply(cache);
+            pos = end;
+            self.len = pos as u64;
+            records += 1;
+        }
+        Ok(records)
+    }
+
+    /// Mark the content of `cache` as persisted
+    fn track(&mut self, cache: &RawCache) -> Result<(), Error> {
+        self.global = Some(sha256::Hash::hash(&global_record(cache)?));
+        self.accounts.clear();
+        self.txs.clear();
+        for (account_num, account) in cache.accounts.iter() {
+            let mut persisted = RawAccountCache::default();
+            AccountDiff::new(&persisted, account).apply(&mut persisted);
+            self.accounts.insert(*account_num, persisted);
+            self.txs.insert(*account_num, account.all_txs.keys().cloned().collect());
+        }
+        self.headers = cache.headers.iter().map(|(h, header)| (*h, header.block_hash())).collect();
+        Ok(())
+    }
+
+    /// Forget what has been persisted, so that the next flush writes everything
+    fn forget(&mut self) {
+        self.global = None;
+        self.accounts.clear();
+        self.txs.clear();
+        self.headers.clear();
+    }
+
+    /// Returns the serialized records of the changes to `cache` since the last call, and marks
+    /// them as persisted.
+    ///
+    /// Transactions are immutable, so they are serialized only when added, the account fields are
+    /// diffed with the persisted ones, while the global record is serialized and compared by hash.
+    fn changes(&mut self, cache: &RawCache) -> Result<Vec<Vec<u8>>, Error> {
+        let mut records = vec![];
+
+        let global = global_record(cache)?;
+        let hash = sha256::Hash::hash(&global);
+        if self.global != Some(hash) {
+            self.global = Some(hash);
+            records.push(global);
+        }
+
+        let removed: Vec<u32> =
+            self.accounts.keys().filter(|n| !cache.accounts.contains_key(*n)).copied().collect();
+        for account_num in removed {
+            self.accounts.remove(&account_num);
+            self.txs.remove(&account_num);
+            records.push(serde_cbor::to_vec(&RecordRef::RemoveAccount(account_num))?);
+        }
+
+        for (account_num, account) in cache.accounts.iter() {
+            let is_new = !self.accounts.contains_key(account_num);
+            let persisted = self.accounts.entry(*account_num).or_default();
+            let diff = AccountDiff::new(persisted, account);
+            if is_new || !diff.is_empty(persisted) {
+                records.push(serde_cbor::to_vec(&RecordRef::AccountDiff(*account_num, &diff))?);
+                diff.apply(persisted);
+            }
+
+            let persisted = self.txs.entry(*account_num).or_default();
+            let removed: Vec<BETxid> = persisted
+                .iter()
+                .filter(|txid| !account.all_txs.contains_key(*txid))
+                .cloned()
+                .collect();
+            for txid in removed {
+                persisted.remove(&txid);
+                records.push(serde_cbor::to_vec(&RecordRef::RemoveTx(*account_num, &txid))?);
+            }
+            for (txid, entry) in account.all_txs.iter() {
+                if persisted.insert(txid.clone()) {
+                    records.push(serde_cbor::to_vec(&RecordRef::Tx(*account_num, txid, entry))?);
+                }
+            }
+        }
+
+        let removed: Vec<u32> =
+            self.headers.keys().filter(|h| !cache.headers.contains_key(*h)).copied().collect();
+        for height in removed {
+            self.headers.remove(&height);
+            records.push(serde_cbor::to_vec(&RecordRef::RemoveHeader(height))?);
+        }
+        for (height, header) in cache.headers.iter() {
+            let hash = header.block_hash();
+            if self.headers.insert(*height, hash) != Some(hash) {
+                records.push(serde_cbor::to_vec(&RecordRef::Header(*height, header))?);
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Whether appending `records` would make the log too big compared to the cache file
+    fn needs_compaction(&self, records: &[Vec<u8>]) -> bool {
+        let size: u64 = records.iter().map(|r| r.len() as u64 + RECORD_OVERHEAD).sum();
+        self.epoch == NO_BASE || self.len + size > self.base_len.max(MIN_COMPACTION_SIZE)
+    }
+
+    fn append(&mut self, records: Vec<Vec<u8>>, cipher: &Aes256GcmSiv) -> Result<(), Error> {
+        let result = self.try_append(records, cipher);
+        if result.is_err() {
+            // The records are already marked as persisted, rewrite everything on the next flush
+            self.forget();
+        }
+        result
+    }
+
+    fn try_append(&mut self, records: Vec<Vec<u8>>, cipher: &Aes256GcmSiv) -> Result<(), Error> {
+        let now = Instant::now();
+        let mut buffer = vec![];
+        if self.len == 0 {
+            buffer.extend(&self.epoch);
+        }
+        let count = records.len();
+        for record in records {
+            let (nonce, ciphertext) = encrypt(record, cipher)?;
+            buffer.extend(&(ciphertext.len() as u32).to_le_bytes());
+            buffer.extend(&nonce);
+            buffer.extend(&ciphertext);
+        }
+        let mut file = OpenOptions::new().write(true).create(true).open(&self.path)?;
+        file.set_len(self.len)?;
+        file.seek(SeekFrom::Start(self.len))?;
+        file.write_all(&buffer)?;
+        file.sync_data()?;
+        self.len += buffer.len() as u64;
+        info!(
+            "appending {} records ({} bytes) on {:?} took {}ms",
+            count,
+            buffer.len(),
+            &self.path,
+            now.elapsed().as_millis()
+        );
+        Ok(())
+    }
+
+    /// Start an empty log for the cache file just written with nonce `epoch`
+    fn compacted(&mut self, epoch: [u8; 12], base_len: u64) -> Result<(), Error> {
+        self.epoch = epoch;
+        self.base_len = base_len;
+        self.len = 0;
+        let mut file = File::create(&self.path)?;
+        file.write_all(&epoch)?;
+        file.sync_data()?;
+        self.len = epoch.len() as u64;
+        Ok(())
+    }
+}
+
+fn global_record(cache: &RawCache) -> Result<Vec<u8>, Error> {
+    Ok(serde_cbor::to_vec(&RecordRef::Global(GlobalRef {
+        txs_verif: &cache.txs_verif,
+        fee_estimates: &cache.fee_estimates,
+        tip_: &cache.tip_,
+        cross_validation_result: &cache.cross_validation_result,
+        accounts_recovered: cache.accounts_recovered,
+        master_blinding: &cache.master_blinding,
+    }))?)
+}
+
+impl AccountDiff {
+    /// The changes from the `persisted` fields to the ones of `account`
+    fn new(persisted: &RawAccountCache, account: &RawAccountCache) -> Self {
+        AccountDiff {
+            paths: MapDiff::new(&persisted.paths, &account.paths),
+            scripts: MapDiff::new(&persisted.scripts, &account.scripts),
+            heights: MapDiff::new(&persisted.heights, &account.heights),
+            unblinded: MapDiff::new(&persisted.unblinded, &account.unblinded),
+            conflicts: MapDiff::new(&persisted.conflicts, &account.conflicts),
+            indexes: account.indexes.clone(),
+            xpub: account.xpub,
+            bip44_discovered: account.bip44_discovered,
+        }
+    }
+
+    /// Whether applying the diff to `persisted` would leave it unchanged
+    fn is_empty(&self, persisted: &RawAccountCache) -> bool {
+        self.paths.is_empty()
+            && self.scripts.is_empty()
+            && self.heights.is_empty()
+            && self.unblinded.is_empty()
+            && self.conflicts.is_empty()
+            && self.indexes == persisted.indexes
+            && self.xpub == persisted.xpub
+            && self.bip44_discovered == persisted.bip44_discovered
+    }
+
+    fn apply(self, account: &mut RawAccountCache) {
+        self.paths.apply(&mut account.paths);
+        self.scripts.apply(&mut account.scripts);
+        self.heights.apply(&mut account.heights);
+        self.unblinded.apply(&mut account.unblinded);
+        self.conflicts.apply(&mut account.conflicts);
+        account.indexes = self.indexes;
+        account.xpub = self.xpub;
+        account.bip44_discovered = self.bip44_discovered;
+    }
+}
+
+impl<K: Eq + std::hash::Hash + Clone, V: PartialEq + Clone> MapDiff<K, V> {
+    fn new(persisted: &HashMap<K, V>, current: &HashMap<K, V>) -> Self {
+        MapDiff {
+            inserted: current
+                .iter()
+                .filter(|(k, v)| persisted.get(k) != Some(v))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            removed: persisted.keys().filter(|k| !current.contains_key(k)).cloned().collect(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.removed.is_empty()
+    }
+
+    fn apply(self, map: &mut HashMap<K, V>) {
+        for k in self.removed {
+            map.remove(&k);
+        }
+        map.extend(self.inserted);
+    }
+}
+
+impl Record {
+    fn apply(self, cache: &mut RawCache) {
+        match self {
+            Record::Global(global) => {
+                cache.txs_verif = global.txs_verif;
+                cache.fee_estimates = global.fee_estimates;
+                cache.tip_ = global.tip_;
+                cache.cross_validation_result = global.cross_validation_result;
+                cache.accounts_recovered = global.accounts_recovered;
+                cache.master_blinding = global.master_blinding;
+            }
+            Record::AccountDiff(account_num, diff) => {
+                diff.apply(cache.accounts.entry(account_num).or_default());
+            }
+            Record::RemoveAccount(account_num) => {
+                cache.accounts.remove(&account_num);
+            }
+            Record::Tx(account_num, txid, entry) => {
+                cache.accounts.entry(account_num).or_default().all_txs.insert(txid, entry);
+            }
+            Record::RemoveTx(account_num, txid) => {
+                if let Some(account) = cache.accounts.get_mut(&account_num) {
+                    account.all_txs.remove(&txid);
+                }
+            }
+            Record::Header(height, header) => {
+                cache.headers.insert(height, header);
+            }
+            Record::RemoveHeader(height) => {
+                cache.headers.remove(&height);
+            }
+        }
+    }
+}
+
+/// Encrypt and write `plaintext` in the file at `path`, returns the nonce used and the file length
+fn write_encrypted(
+    path: &Path,
+    plaintext: Vec<u8>,
+    cipher: &Aes256GcmSiv,
+) -> Result<([u8; 12], u64), Error> {
+    let now = Instant::now();
+    let (nonce_bytes, ciphertext) = encrypt(plaintext, cipher)?;
+    let mut content = nonce_bytes.to_vec();
+    content.extend(&ciphertext);
+    write_atomically(path, &content)?;
+    info!("flushing {} bytes on {:?} took {}ms", content.len(), path, now.elapsed().as_millis());
+    Ok((nonce_bytes, content.len() as u64))
+}
+
+/// Replace the file at `path` with `content` so that a crash while writing doesn't lose it
+fn write_atomically(path: &Path, content: &[u8]) -> Result<(), Error> {
+    let mut tmp_path = path.to_path_buf();
+    tmp_path.set_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
 }
 
 fn get_cipher(xpub: &ExtendedPubKey) -> Aes256GcmSiv {
@@ -259,39 +837,216 @@ fn get_cipher(xpub: &ExtendedPubKey) -> Aes256GcmSiv {
     Aes256GcmSiv::new(&key)
 }
 
+/// The key of wallet backups, derived from the master xpub but independent from the store key, so
+/// that a backup can be restored whatever the key of the store
+fn get_backup_cipher(xpub: &ExtendedPubKey) -> Aes256GcmSiv {
+    let mut enc_key_data = b"gdk_wallet_backup".to_vec();
+    enc_key_data.extend(&xpub.public_key.to_bytes());
+    enc_key_data.extend(&xpub.chain_code.to_bytes());
+    enc_key_data.extend(&xpub.network.magic().to_be_bytes());
+    let key_bytes = sha256::Hash::hash(&enc_key_data).into_inner();
+    let key = Key::from_slice(&key_bytes);
+    Aes256GcmSiv::new(&key)
+}
+
+fn get_user_cipher(user_key: &[u8]) -> Aes256GcmSiv {
+    let mut enc_key_data = b"gdk_store_key".to_vec();
+    enc_key_data.extend(user_key);
+    let key_bytes = sha256::Hash::hash(&enc_key_data).into_inner();
+    let key = Key::from_slice(&key_bytes);
+    Aes256GcmSiv::new(&key)
+}
+
+/// Identifies a user key without revealing it
+fn user_key_fingerprint(user_key: &[u8]) -> sha256::Hash {
+    let mut data = b"gdk_store_key_fingerprint".to_vec();
+    data.extend(user_key);
+    sha256::Hash::hash(&data)
+}
+
+/// Path of the file of `kind` re-encrypted during a key rotation
+fn rotated_path(dir: &Path, kind: Kind) -> PathBuf {
+    dir.join(format!("{}.rotated", kind))
+}
+
+/// The content of the store key file, which exists only if the store is encrypted with a user key
+/// or while the key is being rotated
+#[derive(Serialize, Deserialize)]
+struct StoreKeyFile {
+    /// fingerprint of the user key, None if the key is derived from the master xpub
+    fingerprint: Option<sha256::Hash>,
+
+    /// whether the files re-encrypted with the key are waiting to replace the current ones
+    pending: bool,
+}
+
+impl StoreKeyFile {
+    fn path(dir: &Path) -> PathBuf {
+        dir.join(Kind::StoreKey.to_string())
+    }
+
+    fn read(dir: &Path) -> Result<Option<Self>, Error> {
+        let path = Self::path(dir);
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&std::fs::read(&path)?)?))
+    }
+
+    fn write(&self, dir: &Path) -> Result<(), Error> {
+        write_atomically(&Self::path(dir), &serde_json::to_vec(self)?)
+    }
+
+    /// Returns the fingerprint of the user key the store in `dir` is encrypted with, completing or
+    /// discarding an interrupted key rotation
+    fn load(dir: &Path) -> Result<Option<sha256::Hash>, Error> {
+        match Self::read(dir)? {
+            Some(key_file) if key_file.pending => {
+                warn!("completing interrupted store key rotation");
+                key_file.complete_rotation(dir)?;
+                Ok(key_file.fingerprint)
+            }
+            key_file => {
+                // A rotation interrupted before being committed leaves re-encrypted files behind
+                for kind in &[Kind::Store, Kind::Cache] {
+                    let rotated = rotated_path(dir, *kind);
+                    if rotated.exists() {
+                        std::fs::remove_file(&rotated)?;
+                    }
+                }
+                Ok(key_file.and_then(|k| k.fingerprint))
+            }
+        }
+    }
+
+    /// Replace the files in `dir` with the ones re-encrypted with the new key
+    fn complete_rotation(&self, dir: &Path) -> Result<(), Error> {
+        for kind in &[Kind::Store, Kind::Cache] {
+            let rotated = rotated_path(dir, *kind);
+            if rotated.exists() {
+                std::fs::rename(&rotated, dir.join(kind.to_string()))?;
+            }
+        }
+        // The log refers to the cache file encrypted with the previous key
+        let cache_log = dir.join(Kind::CacheLog.to_string());
+        if cache_log.exists() {
+            std::fs::remove_file(&cache_log)?;
+        }
+        match self.fingerprint {
+            Some(fingerprint) => StoreKeyFile {
+                fingerprint: Some(fingerprint),
+                pending: false,
+            }
+            .write(dir),
+            None => Ok(std::fs::remove_file(Self::path(dir))?),
+        }
+    }
+}
+
 impl StoreMeta {
     pub fn new<P: AsRef<Path>>(
         path: P,
         xpub: &ExtendedPubKey,
         id: NetworkId,
     ) -> Result<StoreMeta, Error> {
-        let cipher = get_cipher(xpub);
-        let cache = RawCache::new(path.as_ref(), &cipher);
+        Self::open(path, xpub, None, id)
+    }
+
+    /// Load the store in `path`, encrypted with a key derived from `user_key` if given, or from
+    /// the master xpub otherwise.
+    ///
+    /// A store encrypted with the key derived from the master xpub is migrated to `user_key`,
+    /// while a store encrypted with a user key can't be loaded without it. With a user key, existing
+    /// files that can't be decrypted fail with `InvalidStoreKey` and are left untouched, while with
+    /// the key derived from the xpub they are corrupted and replaced by empty ones.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        xpub: &ExtendedPubKey,
+        user_key: Option<&[u8]>,
+        id: NetworkId,
+    ) -> Result<StoreMeta, Error> {
+        let fingerprint = StoreKeyFile::load(path.as_ref())?;
+        let cipher = match (fingerprint, user_key) {
+            (None, _) => get_cipher(xpub),
+            (Some(fingerprint), Some(user_key))
+                if fingerprint == user_key_fingerprint(user_key) =>
+            {
+                get_user_cipher(user_key)
+            }
+            _ => return Err(Error::InvalidStoreKey),
+        };
+        // The key derived from the xpub can't be wrong, a file not decrypting is corrupted and it's
+        // replaced by a default one, as it's for a user key that already decrypted the store
+        let user_key_in_use = fingerprint.is_some() || user_key.is_some();
+        let (mut store, key_verified) = RawStore::new(path.as_ref(), &cipher, user_key_in_use)?;
+        let (cache, cache_log, cache_load_error) =
+            RawCache::new(path.as_ref(), &cipher, user_key_in_use && !key_verified)?;
 
-        let mut store = RawStore::new(path.as_ref(), &cipher);
         let path = path.as_ref().to_path_buf();
 
         std::fs::create_dir_all(&path)?; // does nothing if path exists
 
         store.accounts_settings.get_or_insert_with(|| Default::default());
 
-        let store = StoreMeta {
+        let mut store = StoreMeta {
             cache,
             store,
             id,
             cipher,
+            backup_cipher: get_backup_cipher(xpub),
             path,
             last: HashMap::new(),
+            cache_log,
+            cache_load_error,
             to_remove: false,
         };
+        if fingerprint.is_none() && user_key.is_some() {
+            info!("migrating the store to the user key");
+            store.rotate_key(xpub, user_key)?;
+        }
         Ok(store)
     }
 
+    /// Re-encrypt the store and cache files with a key derived from `user_key`, or from the
+    /// master xpub if None.
+    ///
+    /// The re-encrypted files are written aside and the key file commits the rotation before they
+    /// replace the current ones, so that an interrupted rotation is completed or discarded on the
+    /// next load.
+    pub fn rotate_key(
+        &mut self,
+        xpub: &ExtendedPubKey,
+        user_key: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        let cipher = match user_key {
+            Some(user_key) => get_user_cipher(user_key),
+            None => get_cipher(xpub),
+        };
+        let plaintext = serde_cbor::to_vec(&self.store)?;
+        write_encrypted(&rotated_path(&self.path, Kind::Store), plaintext, &cipher)?;
+        let plaintext = serde_cbor::to_vec(&self.cache)?;
+        let (epoch, base_len) =
+            write_encrypted(&rotated_path(&self.path, Kind::Cache), plaintext, &cipher)?;
+
+        let key_file = StoreKeyFile {
+            fingerprint: user_key.map(user_key_fingerprint),
+            pending: true,
+        };
+        key_file.write(&self.path)?;
+        self.cipher = cipher;
+        key_file.complete_rotation(&self.path)?;
+
+        self.cache_log.track(&self.cache)?;
+        self.cache_log.compacted(epoch, base_len)?;
+        info!("rotated store key, user key: {}", user_key.is_some());
+        Ok(())
+    }
+
     pub fn to_remove(&mut self) {
         self.to_remove = true;
     }
 
-    fn file_path(&mut self, kind: Kind) -> PathBuf {
+    fn file_path(&self, kind: Kind) -> PathBuf {
         let mut path = self.path.clone();
         path.push(kind.to_string());
         path
@@ -304,51 +1059,51 @@ impl StoreMeta {
         }
     }
 
-    fn flush_serializable(&mut self, kind: Kind) -> Result<(), Error> {
-        let now = Instant::now();
-        let mut nonce_bytes = [0u8; 12];
-        thread_rng().fill(&mut nonce_bytes);
-        let nonce = Nonce::from_slice(&nonce_bytes);
-        let mut plaintext = match kind {
-            Kind::Store => serde_cbor::to_vec(&self.store),
-            Kind::Cache => serde_cbor::to_vec(&self.cache),
-        }?;
+    /// Encrypt and write `plaintext` in the file of `kind`, returns the nonce used and the file
+    /// length.
+    ///
+    /// The file is replaced atomically, so that a crash while writing doesn't lose its content.
+    fn write_encrypted(&self, kind: Kind, plaintext: Vec<u8>) -> Result<([u8; 12], u64), Error> {
+        write_encrypted(&self.file_path(kind), plaintext, &self.cipher)
+    }
 
+    fn flush_store(&mut self) -> Result<(), Error> {
+        let plaintext = serde_cbor::to_vec(&self.store)?;
         let hash = sha256::Hash::hash(&plaintext);
-        if let Some(last_hash) = self.last.get(&kind) {
-            if last_hash == &hash {
-                info!("latest serialization hash matches, no need to flush");
-                return Ok(());
-            }
+        if self.last.get(&Kind::Store) == Some(&hash) {
+            info!("latest serialization hash matches, no need to flush");
+            return Ok(());
         }
-        self.last.insert(kind, hash);
-
-        self.cipher.encrypt_in_place(nonce, b"", &mut plaintext)?;
-        let ciphertext = plaintext;
-
-        let store_path = self.file_path(kind);
-        //TODO should avoid rewriting if not changed? it involves saving plaintext (or struct hash)
-        // in the front of the file
-        let mut file = File::create(&store_path)?;
-        file.write(&nonce_bytes)?;
-        file.write(&ciphertext)?;
-        info!(
-            "flushing {} bytes on {:?} took {}ms",
-            ciphertext.len() + 16,
-            &store_path,
-            now.elapsed().as_millis()
-        );
+        self.last.insert(Kind::Store, hash);
+        self.write_encrypted(Kind::Store, plaintext)?;
         Ok(())
     }
 
-    fn flush_store(&mut self) -> Result<(), Error> {
-        self.flush_serializable(Kind::Store)?;
+    /// Append the cache changes to the cache log, or compact the log in the cache file if it
+    /// would grow too big
+    fn flush_cache(&mut self) -> Result<(), Error> {
+        let records = self.cache_log.changes(&self.cache)?;
+        if records.is_empty() {
+            info!("no cache changes, no need to flush");
+            return Ok(());
+        }
+        if self.cache_log.needs_compaction(&records) {
+            if let Err(e) = self.compact_cache() {
+                // The records are already marked as persisted, rewrite everything on the next flush
+                self.cache_log.forget();
+                return Err(e);
+            }
+        } else {
+            self.cache_log.append(records, &self.cipher)?;
+        }
         Ok(())
     }
 
-    fn flush_cache(&mut self) -> Result<(), Error> {
-        self.flush_serializable(Kind::Cache)?;
-        Ok(())
+    /// Write the whole cache in the cache file and start an empty cache log
+    fn compact_cache(&mut self) -> Result<(), Error> {
+        let plaintext = serde_cbor::to_vec(&self.cache)?;
+        let (epoch, base_len) = self.write_encrypted(Kind::Cache, plaintext)?;
+        self.cache_log.compacted(epoch, base_len)
     }
 
     pub fn flush(&mut self) -> Result<(), Error> {
@@ -457,6 +1212,39 @@ impl StoreMeta {
         self.store.settings.clone()
     }
 
+    pub fn insert_pending_tx(&mut self, tx: &BETransaction, now: u64) -> Result<(), Error> {
+        let pending_tx = PendingTx {
+            tx: tx.clone(),
+            last_broadcast: now,
+        };
+        self.store.pending_txs.get_or_insert_with(HashMap::new).insert(tx.txid(), pending_tx);
+        self.flush_store()?;
+        Ok(())
+    }
+
+    pub fn remove_pending_tx(&mut self, txid: &BETxid) -> Result<(), Error> {
+        if let Some(pending_txs) = self.store.pending_txs.as_mut() {
+            if pending_txs.remove(txid).is_some() {
+                self.flush_store()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_pending_txs(&self) -> HashMap<BETxid, PendingTx> {
+        self.store.pending_txs.clone().unwrap_or_default()
+    }
+
+    /// The outputs spent by the pending transactions
+    pub fn pending_spent_outpoints(&self) -> HashSet<BEOutPoint> {
+        self.store
+            .pending_txs
+            .iter()
+            .flat_map(|pending_txs| pending_txs.values())
+            .flat_map(|pending_tx| pending_tx.tx.previous_outputs())
+            .collect()
+    }
+
     pub fn get_accounts_settings(&self) -> &HashMap<u32, AccountSettings> {
         // This field is an Option to retain backwards compatibility with the db serialization,
         // but is guaranteed to be initialized as a Some (via StoreMeta::new).
@@ -498,7 +1286,167 @@ impl StoreMeta {
 
     pub fn export_cache(&mut self) -> Result<RawCache, Error> {
         self.flush_cache()?;
-        RawCache::try_new(&self.path, &self.cipher)
+        RawCache::try_new(&self.path, &self.cipher).map(|(cache, _)| cache)
+    }
+
+    /// Export the data of the store in a wallet backup.
+    ///
+    /// The backup is made of a magic, a version byte, a nonce and the encrypted [`WalletBackup`].
+    /// It's encrypted with a key derived from the master xpub, not with the store key which may be
+    /// a user key, and the header is authenticated as associated data.
+    pub fn export_backup(&self) -> Result<Vec<u8>, Error> {
+        let backup = WalletBackup {
+            settings: self.store.settings.clone(),
+            memos: self.store.memos.clone(),
+            accounts_settings: self.get_accounts_settings().clone(),
+        };
+        let mut plaintext = serde_cbor::to_vec(&backup)?;
+        let mut nonce_bytes = [0u8; 12];
+        thread_rng().fill(&mut nonce_bytes);
+        let mut result = backup_header();
+        self.backup_cipher.encrypt_in_place(
+            Nonce::from_slice(&nonce_bytes),
+            &result,
+            &mut plaintext,
+        )?;
+        result.extend(&nonce_bytes);
+        result.extend(&plaintext);
+        Ok(result)
+    }
+
+    /// Merge a wallet backup returned by [`StoreMeta::export_backup`] in the store.
+    ///
+    /// Values missing or empty in the store are always imported, while conflicting ones replace
+    /// the values in the store only if `overwrite` is true.
+    pub fn import_backup(
+        &mut self,
+        backup: &[u8],
+        overwrite: bool,
+    ) -> Result<ImportWalletBackupResult, Error> {
+        let header = backup_header();
+        let ciphertext_start = header.len() + 12;
+        if backup.len() < ciphertext_start || backup[..BACKUP_MAGIC.len()] != BACKUP_MAGIC[..] {
+            return Err(Error::InvalidWalletBackup("unknown format".into()));
+        }
+        if backup[..header.len()] != header[..] {
+            let version = backup[BACKUP_MAGIC.len()];
+            return Err(Error::InvalidWalletBackup(format!("unsupported version {}", version)));
+        }
+        let nonce = Nonce::from_slice(&backup[header.len()..ciphertext_start]);
+        let mut plaintext = backup[ciphertext_start..].to_vec();
+        self.backup_cipher.decrypt_in_place(nonce, &header, &mut plaintext).map_err(|_| {
+            Error::InvalidWalletBackup("cannot decrypt, it may belong to another wallet".into())
+        })?;
+        let backup: WalletBackup = serde_cbor::from_slice(&plaintext)?;
+
+        let mut result = ImportWalletBackupResult::default();
+        for (txid, memo) in backup.memos {
+            let current = self.store.memos.get(&txid);
+            if current != Some(&memo) && (overwrite || current.map_or(true, |m| m.is_empty())) {
+                self.store.memos.insert(txid, memo);
+                result.memos += 1;
+            }
+        }
+
+        let accounts_settings = self.store.accounts_settings.get_or_insert_with(Default::default);
+        for (account_num, settings) in backup.accounts_settings {
+            let unset = accounts_settings
+                .get(&account_num)
+                .map_or(true, |current| current.name.is_empty() && !current.hidden);
+            if overwrite || unset {
+                accounts_settings.insert(account_num, settings);
+                result.subaccounts += 1;
+            }
+        }
+
+        if let Some(settings) = backup.settings {
+            if self.store.settings.as_ref() != Some(&settings)
+                && (overwrite || self.store.settings.is_none())
+            {
+                self.store.settings = Some(settings);
+                result.settings = true;
+            }
+        }
+
+        info!("imported wallet backup {:?}", result);
+        self.flush_store()?;
+        Ok(result)
+    }
+
+    /// Check the internal consistency of the cache
+    pub fn verify(&self) -> VerifyStoreResult {
+        let mut problems = vec![];
+        let mut account_nums: Vec<_> = self.cache.accounts.keys().copied().collect();
+        account_nums.sort_unstable();
+        for account_num in account_nums {
+            let account = &self.cache.accounts[&account_num];
+            let mut problem = |description: String| {
+                problems.push(format!("subaccount {}: {}", account_num, description))
+            };
+
+            for (script, path) in account.paths.iter() {
+                if account.scripts.get(path) != Some(script) {
+                    problem(format!(
+                        "path {} of script {} is not in scripts",
+                        path,
+                        script.to_hex()
+                    ));
+                }
+            }
+            for (path, script) in account.scripts.iter() {
+                if account.paths.get(script) != Some(path) {
+                    problem(format!("script {} of path {} is not in paths", script.to_hex(), path));
+                }
+            }
+
+            for txid in account.heights.keys() {
+                if !account.all_txs.contains_key(txid) {
+                    problem(format!("transaction {} has a height but is missing", txid));
+                }
+            }
+
+            for (outpoint, secrets) in account.unblinded.iter() {
+                let txid = BETxid::Elements(outpoint.txid);
+                let output = match account.all_txs.get(&txid).map(|entry| &entry.tx) {
+                    Some(BETransaction::Elements(tx)) => tx.output.get(outpoint.vout as usize),
+                    _ => None,
+                };
+                match output {
+                    None => problem(format!("unblinded output {} is missing", outpoint)),
+                    Some(output) if !verify_commitments(output, secrets) => problem(format!(
+                        "unblinded values of output {} do not match its commitments",
+                        outpoint
+                    )),
+                    Some(_) => {}
+                }
+            }
+        }
+
+        VerifyStoreResult {
+            valid: problems.is_empty() && self.cache_load_error.is_none(),
+            cache_load_error: self.cache_load_error.clone(),
+            problems,
+        }
+    }
+
+    /// Reset the cache, so that it's populated again from scratch by the next sync.
+    ///
+    /// The store, the subaccounts and the master blinding key are kept.
+    pub fn rebuild_cache(&mut self) -> Result<(), Error> {
+        let mut cache = RawCache::default();
+        cache.master_blinding = self.cache.master_blinding.take();
+        for (account_num, account) in self.cache.accounts.iter() {
+            let mut rebuilt = RawAccountCache::default();
+            rebuilt.xpub = account.xpub;
+            rebuilt.bip44_discovered = account.bip44_discovered;
+            cache.accounts.insert(*account_num, rebuilt);
+        }
+        info!("rebuilding cache of {} subaccounts", cache.accounts.len());
+        self.cache = cache;
+        self.cache_load_error = None;
+        // Rewrite the cache file and discard the log
+        self.cache_log = CacheLog::new(&self.path, NO_BASE);
+        self.flush_cache()
     }
 
     pub fn get_tx_entry(&self, txid: &BETxid) -> Result<&BETransactionEntry, Error> {
@@ -509,6 +1457,10 @@ impl StoreMeta {
         }
         Err(Error::TxNotFound(txid.clone()))
     }
+
+    pub fn get_unblinded(&self, outpoint: &elements::OutPoint) -> Option<&TxOutSecrets> {
+        self.cache.accounts.values().find_map(|acc_store| acc_store.unblinded.get(outpoint))
+    }
 }
 
 impl RawAccountCache {
@@ -534,6 +1486,7 @@ impl RawAccountCache {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bitcoin::hashes::hex::FromHex;
     use bitcoin::util::bip32::ExtendedPubKey;
     use bitcoin::Network;
     use gdk_common::{be::BETxid, NetworkId};
@@ -567,6 +1520,346 @@ mod tests {
         assert_eq!(store.store.memos.get(txid_btc), Some(&"memo".to_string()));
     }
 
+    #[test]
+    fn test_cache_log() {
+        let id = NetworkId::Bitcoin(Network::Testnet);
+        let dir = TempDir::new().unwrap().into_path();
+        let log_path = dir.join(Kind::CacheLog.to_string());
+        let log_len = || std::fs::metadata(&log_path).unwrap().len();
+        // abandon ... M/49'/0'/0'
+        let xpub = ExtendedPubKey::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![],
+        };
+        let txid = BETxid::Bitcoin(tx.txid());
+        let entry = BETransactionEntry {
+            tx: BETransaction::Bitcoin(tx),
+            size: 10,
+            weight: 40,
+        };
+
+        // A cache file in the single-file format is loaded as is
+        {
+            let mut cache = RawCache::default();
+            cache.accounts.insert(0, RawAccountCache::default());
+            cache.fee_estimates = vec![FeeEstimate(1000)];
+            let plaintext = serde_cbor::to_vec(&cache).unwrap();
+            let (nonce, ciphertext) = encrypt(plaintext, &get_cipher(&xpub)).unwrap();
+            let mut file = File::create(dir.join(Kind::Cache.to_string())).unwrap();
+            file.write_all(&nonce).unwrap();
+            file.write_all(&ciphertext).unwrap();
+        }
+
+        {
+            let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
+            assert_eq!(
+                store.cache.fee_estimates.iter().map(|f| f.0).collect::<Vec<_>>(),
+                vec![1000]
+            );
+
+            // Changes are appended to the log
+            let account = store.account_cache_mut(0).unwrap();
+            account.all_txs.insert(txid.clone(), entry);
+            account.heights.insert(txid.clone(), Some(1));
+            store.flush().unwrap();
+            let len = log_len();
+            assert!(len > 12);
+
+            // Nothing is appended without changes
+            store.flush().unwrap();
+            assert_eq!(log_len(), len);
+
+            // Only the changed account fields are appended
+            let account = store.account_cache_mut(0).unwrap();
+            for i in 0..100u8 {
+                let script = BEScript::Bitcoin(bitcoin::Script::from(vec![i]));
+                let path = DerivationPath::from_str(&format!("m/0/{}", i)).unwrap();
+                account.paths.insert(script, path);
+            }
+            store.flush().unwrap();
+            let paths_len = log_len() - len;
+            store.account_cache_mut(0).unwrap().heights.insert(txid.clone(), Some(2));
+            store.flush().unwrap();
+            assert!(log_len() - len - paths_len < paths_len / 10);
+            store.account_cache_mut(0).unwrap().heights.insert(txid.clone(), Some(1));
+        }
+
+        let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        assert_eq!(store.cache.fee_estimates.iter().map(|f| f.0).collect::<Vec<_>>(), vec![1000]);
+        assert!(store.account_cache(0).unwrap().all_txs.contains_key(&txid));
+        assert_eq!(store.account_cache(0).unwrap().heights.get(&txid), Some(&Some(1)));
+        assert_eq!(store.account_cache(0).unwrap().paths.len(), 100);
+        assert!(store.verify().cache_load_error.is_none());
+
+        // Removals are appended too
+        store.account_cache_mut(0).unwrap().all_txs.remove(&txid);
+        drop(store);
+
+        // A truncated record is ignored and reported
+        let len = log_len();
+        OpenOptions::new().append(true).open(&log_path).unwrap().write_all(&[1, 2, 3]).unwrap();
+        let store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        assert!(!store.account_cache(0).unwrap().all_txs.contains_key(&txid));
+        assert_eq!(store.account_cache(0).unwrap().heights.get(&txid), Some(&Some(1)));
+        assert!(store.verify().cache_load_error.is_some());
+        drop(store);
+        assert_eq!(log_len(), len + 3);
+
+        // A log of another cache file is discarded
+        let mut log = std::fs::read(&log_path).unwrap();
+        log[0] ^= 1;
+        std::fs::write(&log_path, &log).unwrap();
+        let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        assert!(store.account_cache(0).unwrap().heights.is_empty());
+        assert!(store.verify().cache_load_error.is_none());
+
+        // The log is compacted in the cache file when it grows too big
+        store.cache.fee_estimates = vec![FeeEstimate(1000); MIN_COMPACTION_SIZE as usize / 2];
+        store.flush().unwrap();
+        assert_eq!(log_len(), 12);
+        drop(store);
+        let store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        assert_eq!(store.cache.fee_estimates.len(), MIN_COMPACTION_SIZE as usize / 2);
+    }
+
+    #[test]
+    fn test_store_key() {
+        let id = NetworkId::Bitcoin(Network::Testnet);
+        let dir = TempDir::new().unwrap().into_path();
+        let key_path = dir.join(Kind::StoreKey.to_string());
+        // abandon ... M/49'/0'/0'
+        let xpub = ExtendedPubKey::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let txid = bitcoin::Txid::default();
+        let key = [1u8; 32];
+        let new_key = [2u8; 32];
+
+        {
+            let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
+            store.make_account(0, xpub, true).unwrap();
+            store.store.memos.insert(txid, "memo".to_string());
+        }
+
+        // The store is migrated to the user key, which is then required
+        drop(StoreMeta::open(&dir, &xpub, Some(&key[..]), id).unwrap());
+        assert!(key_path.exists());
+        assert!(matches!(StoreMeta::new(&dir, &xpub, id), Err(Error::InvalidStoreKey)));
+        let wrong = StoreMeta::open(&dir, &xpub, Some(&new_key[..]), id);
+        assert!(matches!(wrong, Err(Error::InvalidStoreKey)));
+
+        // Without the key file, the files encrypted with the user key are not overwritten
+        let key_file = std::fs::read(&key_path).unwrap();
+        std::fs::remove_file(&key_path).unwrap();
+        let missing = StoreMeta::open(&dir, &xpub, Some(&key[..]), id);
+        assert!(matches!(missing, Err(Error::InvalidStoreKey)));
+        std::fs::write(&key_path, &key_file).unwrap();
+
+        let mut store = StoreMeta::open(&dir, &xpub, Some(&key[..]), id).unwrap();
+        assert_eq!(store.store.memos.get(&txid), Some(&"memo".to_string()));
+        assert!(store.account_cache(0).is_ok());
+        store.rotate_key(&xpub, Some(&new_key[..])).unwrap();
+        drop(store);
+
+        let mut store = StoreMeta::open(&dir, &xpub, Some(&new_key[..]), id).unwrap();
+        assert_eq!(store.store.memos.get(&txid), Some(&"memo".to_string()));
+        store.rotate_key(&xpub, None).unwrap();
+        assert!(!key_path.exists());
+
+        // A rotation interrupted after being committed is completed on load
+        let cipher = get_user_cipher(&key);
+        let plaintext = serde_cbor::to_vec(&store.store).unwrap();
+        write_encrypted(&rotated_path(&dir, Kind::Store), plaintext, &cipher).unwrap();
+        let plaintext = serde_cbor::to_vec(&store.cache).unwrap();
+        write_encrypted(&rotated_path(&dir, Kind::Cache), plaintext, &cipher).unwrap();
+        let key_file = StoreKeyFile {
+            fingerprint: Some(user_key_fingerprint(&key)),
+            pending: true,
+        };
+        key_file.write(&dir).unwrap();
+        drop(store);
+
+        let store = StoreMeta::open(&dir, &xpub, Some(&key[..]), id).unwrap();
+        assert_eq!(store.store.memos.get(&txid), Some(&"memo".to_string()));
+        assert!(store.account_cache(0).is_ok());
+        assert!(!rotated_path(&dir, Kind::Store).exists());
+    }
+
+    #[test]
+    fn test_corrupted_files() {
+        let id = NetworkId::Bitcoin(Network::Testnet);
+        let dir = TempDir::new().unwrap().into_path();
+        let xpub = ExtendedPubKey::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let txid = bitcoin::Txid::default();
+        let store_path = dir.join(Kind::Store.to_string());
+        let cache_path = dir.join(Kind::Cache.to_string());
+
+        {
+            let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
+            store.make_account(0, xpub, true).unwrap();
+            store.store.memos.insert(txid, "memo".to_string());
+        }
+
+        // With the key derived from the xpub, files not decrypting are corrupted and replaced
+        let mut content = std::fs::read(&store_path).unwrap();
+        content.truncate(content.len() / 2);
+        std::fs::write(&store_path, &content).unwrap();
+        let store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        assert!(store.store.memos.is_empty());
+        assert!(store.account_cache(0).is_ok());
+        assert!(store.cache_load_error.is_none());
+        drop(store);
+
+        std::fs::remove_file(&store_path).unwrap();
+        let mut content = std::fs::read(&cache_path).unwrap();
+        let last = content.len() - 1;
+        content[last] ^= 1;
+        std::fs::write(&cache_path, &content).unwrap();
+        let store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        assert!(store.account_cache(0).is_err());
+        assert!(store.cache_load_error.is_some());
+    }
+
+    #[test]
+    fn test_verify_store() {
+        let id = NetworkId::Bitcoin(Network::Testnet);
+        let dir = TempDir::new().unwrap().into_path();
+        // abandon ... M/49'/0'/0'
+        let xpub = ExtendedPubKey::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let txid = BETxid::from_hex(
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+            id,
+        )
+        .unwrap();
+        let script = BEScript::Bitcoin(bitcoin::Script::new());
+        let path = DerivationPath::from_str("m/0/0").unwrap();
+
+        let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        assert!(store.verify().valid);
+        store.make_account(0, xpub, true).unwrap();
+        store.store.memos.insert(*txid.ref_bitcoin().unwrap(), "memo".to_string());
+        let account = store.account_cache_mut(0).unwrap();
+        account.paths.insert(script, path);
+        account.heights.insert(txid.clone(), Some(1));
+        let result = store.verify();
+        assert!(!result.valid);
+        assert_eq!(result.problems.len(), 2);
+        assert!(result.cache_load_error.is_none());
+
+        store.rebuild_cache().unwrap();
+        assert!(store.verify().valid);
+        assert!(store.account_cache(0).unwrap().paths.is_empty());
+        assert_eq!(store.account_cache(0).unwrap().xpub, Some(xpub));
+        assert!(store.get_memo(&txid).is_some());
+        drop(store);
+
+        // A cache that can't be loaded is reported
+        std::fs::write(dir.join(Kind::Cache.to_string()), b"corrupted").unwrap();
+        let store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        let result = store.verify();
+        assert!(!result.valid);
+        assert!(result.cache_load_error.is_some());
+    }
+
+    #[test]
+    fn test_wallet_backup() {
+        let id = NetworkId::Bitcoin(Network::Testnet);
+        // abandon ... M/49'/0'/0'
+        let xpub = ExtendedPubKey::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let other_xpub = ExtendedPubKey::from_str("xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8").unwrap();
+        let txid_a = bitcoin::Txid::from_hex(
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+        )
+        .unwrap();
+        let txid_b = bitcoin::Txid::default();
+        let key = [1u8; 32];
+
+        // The backup doesn't depend on the store key
+        let mut store =
+            StoreMeta::open(&TempDir::new().unwrap().into_path(), &xpub, Some(&key[..]), id)
+                .unwrap();
+        store.store.memos.insert(txid_a, "memo a".to_string());
+        store.store.memos.insert(txid_b, "memo b".to_string());
+        let account_settings = AccountSettings {
+            name: "savings".to_string(),
+            hidden: false,
+        };
+        store.set_account_settings(1, account_settings);
+        store.insert_settings(Some(Settings::default())).unwrap();
+        let backup = store.export_backup().unwrap();
+
+        // Values already in the store are kept, unless overwrite is requested
+        let mut restored = StoreMeta::new(&TempDir::new().unwrap().into_path(), &xpub, id).unwrap();
+        restored.store.memos.insert(txid_a, "local memo".to_string());
+        let result = restored.import_backup(&backup, false).unwrap();
+        let expected = ImportWalletBackupResult {
+            memos: 1,
+            subaccounts: 1,
+            settings: true,
+        };
+        assert_eq!(result, expected);
+        assert_eq!(restored.store.memos.get(&txid_a), Some(&"local memo".to_string()));
+        assert_eq!(restored.store.memos.get(&txid_b), Some(&"memo b".to_string()));
+        assert_eq!(restored.get_account_name(1), Some(&"savings".to_string()));
+        assert_eq!(restored.get_settings(), Some(Settings::default()));
+
+        let result = restored.import_backup(&backup, true).unwrap();
+        assert_eq!(result.memos, 1);
+        assert!(!result.settings);
+        assert_eq!(restored.store.memos.get(&txid_a), Some(&"memo a".to_string()));
+
+        // Backups of other wallets and tampered backups are rejected
+        let mut other =
+            StoreMeta::new(&TempDir::new().unwrap().into_path(), &other_xpub, id).unwrap();
+        assert!(other.import_backup(&backup, false).is_err());
+        let mut tampered = backup.clone();
+        tampered[4] = BACKUP_VERSION + 1;
+        assert!(restored.import_backup(&tampered, false).is_err());
+        let last = tampered.len() - 1;
+        tampered[4] = BACKUP_VERSION;
+        tampered[last] ^= 1;
+        assert!(restored.import_backup(&tampered, false).is_err());
+        assert!(restored.import_backup(b"not a backup", false).is_err());
+    }
+
+    #[test]
+    fn test_pending_txs() {
+        let id = NetworkId::Bitcoin(Network::Testnet);
+        let dir = TempDir::new().unwrap().into_path();
+        let xpub = ExtendedPubKey::from_str("tpubD97UxEEcrMpkE8yG3NQveraWveHzTAJx3KwPsUycx9ABfxRjMtiwfm6BtrY5yhF9yF2eyMg2hyDtGDYXx6gVLBox1m2Mq4u8zB2NXFhUZmm").unwrap();
+        let prev_txid = bitcoin::Txid::from_hex(
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+        )
+        .unwrap();
+        let tx = BETransaction::Bitcoin(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![bitcoin::TxIn {
+                previous_output: bitcoin::OutPoint::new(prev_txid, 1),
+                ..Default::default()
+            }],
+            output: vec![],
+        });
+        let txid = tx.txid();
+
+        {
+            let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
+            assert!(store.get_pending_txs().is_empty());
+            store.insert_pending_tx(&tx, 1).unwrap();
+        }
+
+        let mut store = StoreMeta::new(&dir, &xpub, id).unwrap();
+        assert_eq!(store.get_pending_txs().get(&txid).unwrap().last_broadcast, 1);
+        let spent = store.pending_spent_outpoints();
+        assert_eq!(spent.len(), 1);
+        assert!(spent.contains(&BEOutPoint::new_bitcoin(prev_txid, 1)));
+
+        store.remove_pending_tx(&txid).unwrap();
+        assert!(store.get_pending_txs().is_empty());
+        assert!(store.pending_spent_outpoints().is_empty());
+    }
+
     #[test]
     fn test_db_upgrade() {
         #[derive(Serialize, Deserialize)]
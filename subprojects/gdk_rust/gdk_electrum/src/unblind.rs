@@ -0,0 +1,108 @@
+use crate::error::Error;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::secp256k1::SecretKey;
+use elements::confidential::{Asset, Value};
+use elements::encode::deserialize;
+use elements::{Transaction, TxOut, TxOutSecrets};
+use gdk_common::model::{UnblindedOutput, UnblindedTransaction};
+use gdk_common::wally::{asset_blinding_key_to_ec_private_key, MasterBlindingKey};
+use log::info;
+use serde::Deserialize;
+use std::str::FromStr;
+
+#[derive(Debug, Deserialize)]
+pub struct UnblindTxParam {
+    /// The raw Liquid transaction in hex
+    pub transaction: String,
+
+    /// Blinding private keys in hex, tried on every confidential output
+    #[serde(default)]
+    pub blinding_keys: Vec<String>,
+
+    /// SLIP77 master blinding keys in hex, the blinding private key of each output is derived
+    /// from its script pubkey
+    #[serde(default)]
+    pub master_blinding_keys: Vec<MasterBlindingKey>,
+}
+
+/// Unblind the outputs of the given transaction using the given keys, without requiring a
+/// session.
+///
+/// Only the confidential outputs that can be unblinded are returned; the commitments of every
+/// returned output are recomputed from the unblinded values and compared against the ones in the
+/// transaction.
+pub fn unblind_transaction(param: &UnblindTxParam) -> Result<UnblindedTransaction, Error> {
+    let tx: Transaction = deserialize(&Vec::<u8>::from_hex(&param.transaction)?)?;
+    let keys = param
+        .blinding_keys
+        .iter()
+        .map(|k| SecretKey::from_str(k))
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() && param.master_blinding_keys.is_empty() {
+        return Err(Error::Generic("at least one blinding key is required".into()));
+    }
+
+    let mut outputs = vec![];
+    for (vout, output) in tx.output.iter().enumerate() {
+        if let (Asset::Explicit(_), _) | (_, Value::Explicit(_)) = (&output.asset, &output.value) {
+            continue;
+        }
+        let derived = param
+            .master_blinding_keys
+            .iter()
+            .map(|m| asset_blinding_key_to_ec_private_key(m, &output.script_pubkey));
+        let secrets =
+            keys.iter().cloned().chain(derived).find_map(|k| output.unblind(&crate::EC, k).ok());
+        if let Some(secrets) = secrets {
+            if !verify_commitments(output, &secrets) {
+                return Err(Error::InvalidCommitments(vout as u32));
+            }
+            info!("unblinded output {}:{}", tx.txid(), vout);
+            outputs.push(UnblindedOutput::new(vout as u32, &secrets));
+        }
+    }
+
+    Ok(UnblindedTransaction::new(tx.txid().to_string(), outputs))
+}
+
+/// Check that the asset and value commitments of `output` open to the given `secrets`
+pub(crate) fn verify_commitments(output: &TxOut, secrets: &TxOutSecrets) -> bool {
+    if let (Asset::Explicit(asset), Value::Explicit(value)) = (&output.asset, &output.value) {
+        return *asset == secrets.asset && *value == secrets.value;
+    }
+    let asset = Asset::new_confidential(&crate::EC, secrets.asset, secrets.asset_bf);
+    let generator = match asset {
+        Asset::Confidential(generator) => generator,
+        _ => return false,
+    };
+    let value = Value::new_confidential(&crate::EC, secrets.value, generator, secrets.value_bf);
+    output.asset == asset && output.value == value
+}
+
+#[cfg(test)]
+mod test {
+    use crate::unblind::*;
+
+    const EMPTY_TX: &str = "0200000000000000000000";
+
+    #[test]
+    fn test_unblind_transaction_params() {
+        let mut param = UnblindTxParam {
+            transaction: EMPTY_TX.to_string(),
+            blinding_keys: vec![],
+            master_blinding_keys: vec![],
+        };
+        assert_eq!(
+            "at least one blinding key is required",
+            unblind_transaction(&param).unwrap_err().to_string()
+        );
+
+        param.blinding_keys = vec!["XX".to_string()];
+        assert!(unblind_transaction(&param).is_err());
+
+        param.blinding_keys = vec!["01".repeat(32)];
+        let result = unblind_transaction(&param).unwrap();
+        assert!(result.outputs.is_empty());
+        assert_eq!(result.url_fragment, "");
+    }
+}
@@ -0,0 +1,228 @@
+//! The domain module verifies the link between an asset and the internet domain of its issuer, as
+//! declared in the `entity.domain` field of the asset contract.
+//!
+//! The issuer proves the link by publishing a well-known file on its domain, the verification
+//! outcome is cached locally and expires after [`DOMAIN_VERIFICATION_EXPIRY`] seconds.
+
+use crate::file::{self, ValueModified};
+use crate::registry::{CacheFile, Registry};
+use crate::{AssetEntry, DomainVerification, ElementsNetwork, Error};
+use elements::AssetId;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Seconds after which a cached domain verification is performed again
+pub const DOMAIN_VERIFICATION_EXPIRY: u64 = 24 * 60 * 60;
+
+/// Returns the url of the proof linking `domain` to `asset_id`
+fn proof_url(domain: &str, asset_id: &AssetId) -> String {
+    format!("https://{}/.well-known/liquid-asset-proof-{}", domain, asset_id)
+}
+
+/// Returns the content the proof linking `domain` to `asset_id` must have
+fn proof_content(domain: &str, asset_id: &AssetId) -> String {
+    format!("Authorize linking the domain name {} to the Liquid asset {}", domain, asset_id)
+}
+
+/// Only plain host names are accepted, so that the proof url can't be altered by the contract
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Fetch the proof at `url` and check it links `domain` to `asset_id`.
+///
+/// A missing proof (any HTTP error status) means the domain is not verified, while transport
+/// errors are returned so that the outcome is not cached.
+fn verify_proof(
+    url: &str,
+    domain: &str,
+    asset_id: &AssetId,
+    agent: &ureq::Agent,
+) -> Result<bool, Error> {
+    info!("verifying domain {} of asset {} at {}", domain, asset_id, url);
+    let response = match agent.get(url).timeout(Duration::from_secs(30)).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(status, _)) => {
+            warn!("domain proof for {} returned status {}", asset_id, status);
+            return Ok(false);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let body = response.into_string()?;
+    Ok(body.trim_end() == proof_content(domain, asset_id))
+}
+
+/// Verify the domain of `entry`, using the cached outcome if not expired and `refresh` is false
+pub(crate) fn verify(
+    registry: &Registry,
+    network: ElementsNetwork,
+    entry: &AssetEntry,
+    refresh: bool,
+    agent: &ureq::Agent,
+) -> Result<DomainVerification, Error> {
+    let domain = match entry.domain() {
+        Some(domain) if is_valid_domain(domain) => domain,
+        _ => return Err(Error::InvalidDomain(entry.asset_id.to_string())),
+    };
+    let now = now();
+
+    let cached = read(&mut registry.get_cache_file(network, CacheFile::Domains)?)?;
+    if let Some(verification) = cached.get(&entry.asset_id).filter(|_| !refresh) {
+        if verification.domain == domain && !verification.is_expired(now) {
+            info!("domain verification cache hit for {}", entry.asset_id);
+            return Ok(verification.clone());
+        }
+    }
+
+    // the file is not held while fetching the proof, so that other verifications and the
+    // annotation of the assets don't wait for it
+    let url = proof_url(domain, &entry.asset_id);
+    let verification = DomainVerification {
+        domain: domain.to_string(),
+        verified: verify_proof(&url, domain, &entry.asset_id, agent)?,
+        checked_at: now,
+    };
+    // re-read under the lock, the file may have been written meanwhile
+    let mut file = registry.get_cache_file(network, CacheFile::Domains)?;
+    let _lock = registry.lock_dir()?;
+    let mut cached = read(&mut file)?;
+    cached.insert(entry.asset_id, verification.clone());
+    write(&cached, &mut file)?;
+
+    Ok(verification)
+}
+
+/// Set the `domain_verified` field of the given assets according to the cached verifications
+pub(crate) fn annotate<'a>(
+    registry: &Registry,
+    network: ElementsNetwork,
+    assets: impl Iterator<Item = &'a mut AssetEntry>,
+) -> Result<(), Error> {
+    let cached = read(&mut registry.get_cache_file(network, CacheFile::Domains)?)?;
+    let now = now();
+    for entry in assets {
+        entry.domain_verified = cached
+            .get(&entry.asset_id)
+            .filter(|v| Some(v.domain.as_str()) == entry.domain() && !v.is_expired(now))
+            .map(|v| v.verified);
+    }
+    Ok(())
+}
+
+fn read(file: &mut File) -> Result<HashMap<AssetId, DomainVerification>, Error> {
+    Ok(serde_json::from_value(file::read(file)?.value)?)
+}
+
+fn write(value: &HashMap<AssetId, DomainVerification>, file: &mut File) -> Result<(), Error> {
+    let value_modified = ValueModified {
+        last_modified: "".to_string(),
+        value: serde_json::to_value(value)?,
+    };
+    file::write(&value_modified, file)
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl DomainVerification {
+    fn is_expired(&self, now: u64) -> bool {
+        now.saturating_sub(self.checked_at) > DOMAIN_VERIFICATION_EXPIRY
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use elements::hashes::hex::FromHex;
+
+    #[test]
+    fn test_verify_proof() {
+        use httptest::{matchers::*, responders::*, Expectation, Server};
+
+        let _ = env_logger::try_init();
+        let agent = ureq::agent();
+        let asset_id =
+            AssetId::from_hex("ce091c998b83c78bb71a632313ba3760f1763d9cfcffae02258ffa9865a37bd2")
+                .unwrap();
+        let domain = "tether.to";
+        let path = format!("/.well-known/liquid-asset-proof-{}", asset_id);
+        assert!(proof_url(domain, &asset_id).ends_with(&path));
+
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", path.clone())).times(3).respond_with(
+                cycle![
+                    status_code(200).body(proof_content(domain, &asset_id) + "\n"),
+                    status_code(200).body(proof_content("other.domain", &asset_id)),
+                    status_code(404),
+                ],
+            ),
+        );
+        let url = server.url_str(&path);
+        assert!(verify_proof(&url, domain, &asset_id, &agent).unwrap());
+        assert!(!verify_proof(&url, domain, &asset_id, &agent).unwrap());
+        assert!(!verify_proof(&url, domain, &asset_id, &agent).unwrap());
+    }
+
+    #[test]
+    fn test_verify_cached() {
+        let _ = env_logger::try_init();
+
+        let network = ElementsNetwork::Liquid;
+        let tether: AssetEntry =
+            serde_json::from_str(include_str!("data/test/tether.json")).unwrap();
+        let dir = tempfile::TempDir::new().unwrap();
+        let registry = Registry::new(&dir).unwrap();
+        let verification = DomainVerification {
+            domain: "tether.to".to_string(),
+            verified: true,
+            checked_at: now(),
+        };
+        let mut cached = HashMap::new();
+        cached.insert(tether.asset_id, verification.clone());
+        write(&cached, &mut registry.get_cache_file(network, CacheFile::Domains).unwrap()).unwrap();
+
+        // the agent can't reach anything, a cache hit doesn't need it
+        let proxy = ureq::Proxy::new("localhost:1").unwrap();
+        let agent = ureq::AgentBuilder::new().proxy(proxy).build();
+        assert_eq!(verify(&registry, network, &tether, false, &agent).unwrap(), verification);
+        assert!(verify(&registry, network, &tether, true, &agent).is_err());
+
+        let mut other_domain = tether.clone();
+        other_domain.entity["domain"] = "other.domain".into();
+        let mut assets = vec![tether.clone(), other_domain];
+        annotate(&registry, network, assets.iter_mut()).unwrap();
+        assert_eq!(assets[0].domain_verified, Some(true));
+        assert_eq!(assets[1].domain_verified, None);
+    }
+
+    #[test]
+    fn test_valid_domain() {
+        assert!(is_valid_domain("tether.to"));
+        assert!(is_valid_domain("artmirable.bfungible.network"));
+        assert!(!is_valid_domain(""));
+        assert!(!is_valid_domain("tether.to/path"));
+        assert!(!is_valid_domain("user@tether.to"));
+        assert!(!is_valid_domain("tether..to"));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let verification = DomainVerification {
+            domain: "tether.to".to_string(),
+            verified: true,
+            checked_at: 1000,
+        };
+        assert!(!verification.is_expired(1000 + DOMAIN_VERIFICATION_EXPIRY));
+        assert!(verification.is_expired(1001 + DOMAIN_VERIFICATION_EXPIRY));
+    }
+}
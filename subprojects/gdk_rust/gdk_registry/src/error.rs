@@ -1,8 +1,4 @@
-use std::{
-    fs::File,
-    io,
-    sync::{MutexGuard, PoisonError},
-};
+use std::{io, sync::PoisonError};
 
 /// Contains all the error variants possibly happening in this library
 #[derive(thiserror::Error, Debug)]
@@ -20,6 +16,22 @@ pub enum Error {
     #[error("Registry has not been initialized")]
     RegistryUninitialized,
 
+    /// The asset has not been found in the registry
+    #[error("Asset {0} not found in the registry")]
+    AssetNotFound(String),
+
+    /// The asset has no issuer domain or it is not a valid domain name
+    #[error("Asset {0} has no valid issuer domain")]
+    InvalidDomain(String),
+
+    /// The local asset does not verify or it is not fully specified
+    #[error("Invalid local asset {0}")]
+    InvalidLocalAsset(String),
+
+    /// Thrown when there are no registry urls to call
+    #[error("No registry url to call")]
+    NoRegistryUrl,
+
     /// An invalid network as been specified
     #[error("InvalidNetwork({0})")]
     InvalidNetwork(String),
@@ -49,8 +61,8 @@ pub enum Error {
     Poison(String),
 }
 
-impl From<PoisonError<MutexGuard<'_, File>>> for Error {
-    fn from(e: PoisonError<MutexGuard<'_, File>>) -> Self {
+impl<T> From<PoisonError<T>> for Error {
+    fn from(e: PoisonError<T>) -> Self {
         Error::Poison(e.to_string())
     }
 }
@@ -33,6 +33,8 @@ pub(crate) fn read(file: &mut File) -> Result<ValueModified, Error> {
 
 pub(crate) fn write(value: &ValueModified, file: &mut File) -> Result<(), Error> {
     file.seek(std::io::SeekFrom::Start(0))?;
+    // a shorter value must not leave trailing bytes of the previous one
+    file.set_len(0)?;
     let buffered = BufWriter::new(file);
     Ok(serde_cbor::to_writer(buffered, &value)?)
 }
@@ -64,5 +66,12 @@ mod test {
         write(&content, &mut tempfile).unwrap();
         let value = read(&mut tempfile).unwrap();
         assert_eq!(content, value, "roundtrip failing");
+
+        let shorter = ValueModified {
+            last_modified: "".into(),
+            value: Value::Null,
+        };
+        write(&shorter, &mut tempfile).unwrap();
+        assert_eq!(shorter, read(&mut tempfile).unwrap(), "overwrite failing");
     }
 }
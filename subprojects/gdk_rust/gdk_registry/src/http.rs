@@ -1,7 +1,41 @@
 use crate::{file::ValueModified, Error};
-use log::info;
+use log::{info, warn};
 use serde_json::Value;
-use std::{io::BufReader, time::Duration};
+use std::{io::BufReader, thread, time::Duration};
+
+/// Number of times the whole list of urls is tried
+const ATTEMPTS: u32 = 3;
+
+/// Waiting time before trying again the list of urls, doubling at every attempt
+const BACKOFF: Duration = Duration::from_millis(500);
+
+/// Call the given `urls` in order until one succeeds, retrying the whole list after an increasing
+/// waiting time. The error of the last call is returned if none succeeds.
+pub fn call_mirrors(
+    urls: &[String],
+    agent: &ureq::Agent,
+    last_modified: &str,
+) -> Result<ValueModified, Error> {
+    let mut last_error = Error::NoRegistryUrl;
+    for attempt in 0..ATTEMPTS {
+        if attempt > 0 {
+            thread::sleep(BACKOFF * 2u32.pow(attempt - 1));
+        }
+        for url in urls {
+            match call(url, agent, last_modified) {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    warn!("call {} attempt {} failed: {:?}", url, attempt, e);
+                    last_error = e;
+                }
+            }
+        }
+        if urls.is_empty() {
+            break;
+        }
+    }
+    Err(last_error)
+}
 
 pub fn call(url: &str, agent: &ureq::Agent, last_modified: &str) -> Result<ValueModified, Error> {
     let now = std::time::Instant::now();
@@ -70,4 +104,35 @@ mod test {
             assert_eq!(expected_last_modified, value.last_modified);
         }
     }
+
+    #[test]
+    fn test_call_mirrors() {
+        use httptest::{matchers::*, responders::*, Expectation, Server};
+
+        let _ = env_logger::try_init();
+        let agent = ureq::agent();
+        let endpoint = crate::AssetsOrIcons::Assets.endpoint();
+
+        let failing = Server::run();
+        failing.expect(
+            Expectation::matching(request::method_path("GET", endpoint))
+                .times(1..)
+                .respond_with(status_code(500)),
+        );
+        let working = Server::run();
+        working.expect(
+            Expectation::matching(request::method_path("GET", endpoint))
+                .times(1)
+                .respond_with(status_code(200).body("{}").append_header("last-modified", "date")),
+        );
+        let urls = vec![failing.url_str(endpoint), working.url_str(endpoint)];
+        let value = call_mirrors(&urls, &agent, "").unwrap();
+        assert_eq!("date", value.last_modified);
+
+        // all urls failing, the failing server is called at every attempt
+        let urls = vec![failing.url_str(endpoint)];
+        assert!(call_mirrors(&urls, &agent, "").is_err());
+
+        assert!(matches!(call_mirrors(&[], &agent, ""), Err(Error::NoRegistryUrl)));
+    }
 }
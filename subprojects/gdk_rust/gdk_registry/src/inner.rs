@@ -1,93 +1,28 @@
-//! The inner module contain the code needed to access the file containing the registry values
-//! It contains unsafe code since we need a `static mut` variable representing the files guarded
-//! by a `Mutex`
+//! The inner module contains the default [`Registry`] used by the free functions of the library,
+//! initialized once by [`init`]
 
-use crate::hard::hard_coded_values;
-use crate::{file, AssetsOrIcons, ElementsNetwork, Error, ValueModified};
-use std::fs::OpenOptions;
+use crate::{Error, Registry};
+use once_cell::sync::OnceCell;
 use std::path::Path;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::MutexGuard;
-use std::{collections::HashMap, fs::File, sync::Mutex};
-use std::{fs, hint};
 
-type RegistryFiles = Option<HashMap<(ElementsNetwork, AssetsOrIcons), Mutex<File>>>;
+static DEFAULT_REGISTRY: OnceCell<Registry> = OnceCell::new();
 
-const UNINITIALIZED: usize = 0;
-const INITIALIZING: usize = 1;
-const INITIALIZED: usize = 2;
-
-static mut REGISTRY_FILES: RegistryFiles = None;
-
-/// Initialize the library by giving the root directory `dir`, where will be persisted cached data.
+/// Initialize the default registry by giving the root directory `dir`, where will be persisted
+/// cached data.
 pub fn init<P: AsRef<Path>>(dir: P) -> Result<(), Error> {
-    /// By having the static `STATE` inside the function, it cannot be accessed out of this fn
-    static STATE: AtomicUsize = AtomicUsize::new(UNINITIALIZED);
-
-    let old_state = match STATE.compare_exchange(
-        UNINITIALIZED,
-        INITIALIZING,
-        Ordering::SeqCst,
-        Ordering::SeqCst,
-    ) {
-        Ok(s) | Err(s) => s,
-    };
-    match old_state {
-        UNINITIALIZED => {
-            let mut files = HashMap::new();
-            for b in AssetsOrIcons::iter() {
-                for n in ElementsNetwork::iter() {
-                    let mut file_path = dir.as_ref().to_path_buf();
-                    file_path.push(n.to_string());
-                    fs::create_dir_all(&file_path)?;
-                    file_path.push(b.to_string());
-                    let file_exists = file_path.exists();
-
-                    let mut file =
-                        OpenOptions::new().write(true).read(true).create(true).open(file_path)?;
-
-                    if !file_exists {
-                        let hard_coded_values = hard_coded_values(n, b);
-                        let value_modified = ValueModified {
-                            value: hard_coded_values,
-                            last_modified: "".to_string(),
-                        };
-                        file::write(&value_modified, &mut file)?;
-                    }
-
-                    files.insert((n, b), Mutex::new(file));
-                }
-            }
-            unsafe {
-                REGISTRY_FILES = Some(files);
-            }
-            STATE.store(INITIALIZED, Ordering::SeqCst);
-            Ok(())
-        }
-        INITIALIZING => {
-            while STATE.load(Ordering::SeqCst) == INITIALIZING {
-                // giving time to the thread entederd the UNITIALIZED case to finish his job
-                // when this loop finish even this thread is sure to be INITIALIZED
-                hint::spin_loop();
-            }
-            Err(Error::AlreadyInitialized)
-        }
-        _ => Err(Error::AlreadyInitialized),
+    let mut initialized = false;
+    DEFAULT_REGISTRY.get_or_try_init(|| {
+        initialized = true;
+        Registry::new(dir)
+    })?;
+    if initialized {
+        Ok(())
+    } else {
+        Err(Error::AlreadyInitialized)
     }
 }
 
-/// Only way to access to `File`s containing the registry information
-pub fn get_file(
-    network: ElementsNetwork,
-    t: AssetsOrIcons,
-) -> Result<MutexGuard<'static, File>, Error> {
-    unsafe {
-        match REGISTRY_FILES.as_ref() {
-            Some(registry_files) => Ok(registry_files
-                .get(&(network, t))
-                .expect("any combination is initialized")
-                .lock()?),
-            None => Err(Error::RegistryUninitialized),
-        }
-    }
+/// Only way to access the default registry
+pub fn default_registry() -> Result<&'static Registry, Error> {
+    DEFAULT_REGISTRY.get().ok_or(Error::RegistryUninitialized)
 }
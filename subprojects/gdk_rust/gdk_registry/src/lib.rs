@@ -10,6 +10,9 @@
 //! a default "asset registry" or a user-defined one.
 //!
 //! The main method is [`refresh_assets`] but the library must be initialized with a call to [`init`].
+//! Alternatively, any number of [`Registry`] could be created, each one with its own directory.
+//! Cached assets could be queried with [`get_assets`] without deserializing the whole registry.
+//! The issuer domain of an asset could be verified with [`validate_asset_domain_name`].
 //!
 //! Assets metadata are piece of information like the name of the assets, the ticker, and the
 //! precision (decimal places of amounts) which define how wallets show information to users.
@@ -23,89 +26,80 @@
 //! to fetch the whole registry.
 //!
 
-use hard::{hard_coded_assets, hard_coded_icons};
-use log::{debug, info, warn};
-
+pub use domain::DOMAIN_VERIFICATION_EXPIRY;
 pub use error::Error;
 pub use file::ValueModified;
 pub use hard::policy_asset_id;
 pub use inner::init;
-pub use param::{AssetsOrIcons, ElementsNetwork, RefreshAssetsParam};
-pub use result::{AssetEntry, RefreshAssetsResult};
-
+pub use param::{
+    AddLocalAssetParam, AssetsOrIcons, ElementsNetwork, GetAssetsParam,
+    ImportRegistrySnapshotParam, RefreshAssetsParam, ValidateAssetDomainParam,
+};
+pub use registry::Registry;
+pub use result::{
+    AssetEntry, AssetSource, DomainVerification, GetAssetsResult, ImportRegistrySnapshotResult,
+    Prevout, RefreshAssetsResult,
+};
+
+mod domain;
 mod error;
 mod file;
 mod hard;
 mod http;
 mod inner;
+mod local;
 mod param;
+mod registry;
 mod result;
 
 ///
-/// Returns information about assets and related icons.
+/// Returns information about assets and related icons, using the default registry.
 ///
-/// Results could come from the persisted cached value when `details.refresh` is `false` or could be
-/// fetched from an asset registry when it's `true`.
-/// By default, Liquid mainnet network is used and the asset registry used is managed by Blockstream
-/// and no proxy is used to access it. This default configuration could be overridden by providing
-/// the `details.config` parameter.
+/// See [`Registry::refresh_assets`].
 ///
 pub fn refresh_assets(details: &RefreshAssetsParam) -> Result<RefreshAssetsResult, Error> {
-    let now = std::time::Instant::now();
-    let network = details.network();
-    let mut return_value = RefreshAssetsResult::default();
-    let agent = details.agent()?;
-    for what in details.asked()? {
-        let mut file = inner::get_file(network, what)?;
-        let file_value = file::read(&mut file)?;
-        let value = match agent.as_ref() {
-            Some(agent) => {
-                let response_value =
-                    http::call(&details.url(what), agent, &file_value.last_modified)?;
-                debug!("response for {} modified: {}", what, response_value.last_modified);
-                if file_value.last_modified != response_value.last_modified {
-                    let last_modified = response_value.last_modified.clone();
-                    let value = match what {
-                        AssetsOrIcons::Assets => {
-                            let hard = hard_coded_assets(network);
-                            let mut downloaded = response_value.assets()?;
-                            let len = downloaded.len();
-                            debug!("downloaded {} assets metadata", len);
-                            downloaded.retain(|_k, v| v.verify().unwrap_or(false));
-                            if downloaded.len() != len {
-                                warn!("Some assets didn't verify!");
-                            }
-                            downloaded.extend(hard);
-                            serde_json::to_value(downloaded)?
-                        }
-                        AssetsOrIcons::Icons => {
-                            let hard = hard_coded_icons(network);
-                            let mut downloaded = response_value.icons()?;
-                            debug!("downloaded {} assets icons", downloaded.len());
-                            downloaded.extend(hard);
-                            serde_json::to_value(downloaded)?
-                        }
-                    };
-
-                    let new = ValueModified {
-                        last_modified,
-                        value,
-                    };
-                    file::write(&new, &mut file)?;
-                    new
-                } else {
-                    file_value
-                }
-            }
-            None => file_value,
-        };
-        match what {
-            AssetsOrIcons::Assets => return_value.assets = serde_json::from_value(value.value)?,
-            AssetsOrIcons::Icons => return_value.icons = serde_json::from_value(value.value)?,
-        }
-    }
-    info!("refresh_assets took: {:?}", now.elapsed());
-    Ok(return_value)
+    inner::default_registry()?.refresh_assets(details)
+}
+
+///
+/// Returns the locally cached assets matching the query in `details`, using the default registry.
+///
+/// See [`Registry::get_assets`].
+///
+pub fn get_assets(details: &GetAssetsParam) -> Result<GetAssetsResult, Error> {
+    inner::default_registry()?.get_assets(details)
+}
+
+///
+/// Verifies the issuer domain of a cached asset, using the default registry.
+///
+/// See [`Registry::validate_asset_domain_name`].
+///
+pub fn validate_asset_domain_name(
+    details: &ValidateAssetDomainParam,
+) -> Result<DomainVerification, Error> {
+    inner::default_registry()?.validate_asset_domain_name(details)
+}
+
+///
+/// Adds an asset not present in the registry, using the default registry.
+///
+/// See [`Registry::add_local_asset`].
+///
+pub fn add_local_asset(details: &AddLocalAssetParam) -> Result<AssetEntry, Error> {
+    inner::default_registry()?.add_local_asset(details)
+}
+
+///
+/// Replaces the cached registry values with the ones in a snapshot file, using the default
+/// registry.
+///
+/// See [`Registry::import_registry_snapshot`].
+///
+pub fn import_registry_snapshot(
+    details: &ImportRegistrySnapshotParam,
+) -> Result<ImportRegistrySnapshotResult, Error> {
+    inner::default_registry()?.import_registry_snapshot(details)
 }
 
 #[cfg(test)]
@@ -0,0 +1,43 @@
+//! The local module persists the assets added by the user, such as the ones of private issuances
+//! which are never published in a registry.
+
+use crate::file::{self, ValueModified};
+use crate::registry::{CacheFile, Registry};
+use crate::{AssetEntry, ElementsNetwork, Error};
+use elements::AssetId;
+use log::info;
+use std::collections::HashMap;
+
+/// Verify `entry` and persist it in the local assets of `network`
+pub(crate) fn add(
+    registry: &Registry,
+    network: ElementsNetwork,
+    mut entry: AssetEntry,
+) -> Result<AssetEntry, Error> {
+    if !entry.verify()? {
+        return Err(Error::InvalidLocalAsset(entry.asset_id.to_string()));
+    }
+    entry.domain_verified = None;
+    entry.source = None;
+
+    let mut file = registry.get_cache_file(network, CacheFile::LocalAssets)?;
+    let _lock = registry.lock_dir()?;
+    let mut local = file::read(&mut file)?.assets()?;
+    local.insert(entry.asset_id, entry.clone());
+    let value_modified = ValueModified {
+        last_modified: "".to_string(),
+        value: serde_json::to_value(&local)?,
+    };
+    file::write(&value_modified, &mut file)?;
+    info!("added local asset {}, local assets are {}", entry.asset_id, local.len());
+
+    Ok(entry)
+}
+
+/// Returns the local assets of `network`
+pub(crate) fn assets(
+    registry: &Registry,
+    network: ElementsNetwork,
+) -> Result<HashMap<AssetId, AssetEntry>, Error> {
+    file::read(&mut registry.get_cache_file(network, CacheFile::LocalAssets)?)?.assets()
+}
@@ -1,6 +1,8 @@
-use crate::Error;
+use crate::{AssetEntry, Error, Prevout};
+use elements::AssetId;
 use serde::{Deserialize, Serialize};
-use std::{fmt::Display, str::FromStr};
+use serde_json::Value;
+use std::{fmt::Display, path::PathBuf, str::FromStr};
 
 const BASE_URL: &str = "https://assets.blockstream.info";
 
@@ -27,6 +29,41 @@ pub struct RefreshAssetsParam {
     pub config: Config,
 }
 
+/// The parameters given to the [`crate::get_assets`] call to query the locally cached assets.
+///
+/// Every given filter must match for an asset to be returned, when no filter is given every cached
+/// asset is returned (subject to pagination).
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct GetAssetsParam {
+    /// Return only the assets with one of these ids
+    #[serde(default)]
+    pub assets_id: Vec<AssetId>,
+
+    /// Return only the assets with this ticker (case insensitive)
+    pub ticker: Option<String>,
+
+    /// Return only the assets with a name containing this string (case insensitive)
+    pub name: Option<String>,
+
+    /// Return only the assets issued by the entity with this domain
+    pub domain: Option<String>,
+
+    /// When true, the icons of the returned assets are returned too
+    #[serde(default)]
+    pub icons: bool,
+
+    /// Number of matching assets to skip, matching assets are ordered by asset id
+    #[serde(default)]
+    pub offset: usize,
+
+    /// Maximum number of assets returned
+    pub limit: Option<usize>,
+
+    /// Optional configuration for network used
+    #[serde(default)]
+    pub config: Config,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     /// Optional proxy to use
@@ -34,15 +71,74 @@ pub struct Config {
 
     pub url: String,
 
+    /// Registries tried in order if `url` can't be reached
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+
+    /// Onion registries tried in order before the others when `use_tor` is true
+    #[serde(default)]
+    pub onion_mirrors: Vec<String>,
+
+    /// When true, `onion_mirrors` are used
+    #[serde(default)]
+    pub use_tor: bool,
+
     /// defaults to Liquid mainnet
     pub network: ElementsNetwork,
 }
 
+/// The parameters given to the [`crate::add_local_asset`] call, either `asset` or both `contract`
+/// and `issuance_prevout` must be given.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct AddLocalAssetParam {
+    /// The asset to add, its contract must commit to its asset id
+    pub asset: Option<AssetEntry>,
+
+    /// The contract of the asset to add
+    pub contract: Option<Value>,
+
+    /// The previous output spent by the issuance of the asset to add
+    pub issuance_prevout: Option<Prevout>,
+
+    /// Optional configuration for network used
+    #[serde(default)]
+    pub config: Config,
+}
+
+/// The parameters given to the [`crate::import_registry_snapshot`] call.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportRegistrySnapshotParam {
+    /// Path of the snapshot file
+    pub path: PathBuf,
+
+    /// Optional configuration for network used
+    #[serde(default)]
+    pub config: Config,
+}
+
+/// The parameters given to the [`crate::validate_asset_domain_name`] call.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ValidateAssetDomainParam {
+    /// The asset whose issuer domain is verified, it must be present in the registry cache
+    pub asset_id: AssetId,
+
+    /// When true, the verification is performed again even if a cached outcome is not expired
+    #[serde(default)]
+    pub refresh: bool,
+
+    /// Optional configuration for network used and proxy to reach the domain
+    #[serde(default)]
+    pub config: Config,
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             proxy: None,
             url: BASE_URL.to_string(),
+            mirrors: vec![],
+            onion_mirrors: vec![],
+            use_tor: false,
             network: ElementsNetwork::Liquid,
         }
     }
@@ -69,25 +165,66 @@ impl RefreshAssetsParam {
         self.config.network
     }
 
-    pub(crate) fn url(&self, what: AssetsOrIcons) -> String {
-        format!("{}{}", self.config.url, what.endpoint())
+    /// The urls to try in order to fetch `what`
+    pub(crate) fn urls(&self, what: AssetsOrIcons) -> Vec<String> {
+        let onion_mirrors = self.config.onion_mirrors.iter().filter(|_| self.config.use_tor);
+        onion_mirrors
+            .chain(std::iter::once(&self.config.url))
+            .chain(self.config.mirrors.iter())
+            .map(|base| format!("{}{}", base, what.endpoint()))
+            .collect()
     }
 
     pub(crate) fn agent(&self) -> Result<Option<ureq::Agent>, Error> {
         if self.refresh {
-            match self.config.proxy.as_ref() {
-                Some(proxy) if !proxy.is_empty() => {
-                    let proxy = ureq::Proxy::new(&proxy)?;
-                    Ok(Some(ureq::AgentBuilder::new().proxy(proxy).build()))
-                }
-                _ => Ok(Some(ureq::agent())),
-            }
+            Ok(Some(self.config.agent()?))
         } else {
             Ok(None)
         }
     }
 }
 
+impl AddLocalAssetParam {
+    pub(crate) fn entry(&self) -> Result<AssetEntry, Error> {
+        match (&self.asset, &self.contract, &self.issuance_prevout) {
+            (Some(asset), _, _) => Ok(asset.clone()),
+            (None, Some(contract), Some(prevout)) => {
+                AssetEntry::from_contract(contract.clone(), prevout.clone())
+            }
+            _ => Err(Error::InvalidLocalAsset("missing asset or contract".to_string())),
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn agent(&self) -> Result<ureq::Agent, Error> {
+        match self.proxy.as_ref() {
+            Some(proxy) if !proxy.is_empty() => {
+                let proxy = ureq::Proxy::new(&proxy)?;
+                Ok(ureq::AgentBuilder::new().proxy(proxy).build())
+            }
+            _ => Ok(ureq::agent()),
+        }
+    }
+}
+
+impl GetAssetsParam {
+    pub(crate) fn network(&self) -> ElementsNetwork {
+        self.config.network
+    }
+
+    pub(crate) fn matches(&self, entry: &AssetEntry) -> bool {
+        let contains_ignore_case =
+            |value: &str, search: &str| value.to_lowercase().contains(&search.to_lowercase());
+        (self.assets_id.is_empty() || self.assets_id.contains(&entry.asset_id))
+            && self.ticker.as_ref().map_or(true, |t| {
+                entry.ticker.as_ref().map_or(false, |e| e.eq_ignore_ascii_case(t))
+            })
+            && self.name.as_ref().map_or(true, |n| contains_ignore_case(&entry.name, n))
+            && self.domain.as_ref().map_or(true, |d| entry.domain() == Some(d.as_str()))
+    }
+}
+
 /// Discriminate the elements network
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ElementsNetwork {
@@ -190,6 +327,16 @@ mod test {
         assert_eq!(refresh_assets.asked().unwrap(), vec![Assets]);
 
         assert_eq!(refresh_assets.network(), ElementsNetwork::Liquid);
+
+        refresh_assets.config.mirrors = vec!["https://mirror".to_string()];
+        refresh_assets.config.onion_mirrors = vec!["http://mirror.onion".to_string()];
+        assert_eq!(
+            refresh_assets.urls(Assets),
+            vec![format!("{}/index.json", BASE_URL), "https://mirror/index.json".to_string()]
+        );
+        refresh_assets.config.use_tor = true;
+        assert_eq!(refresh_assets.urls(Icons)[0], "http://mirror.onion/icons.json");
+        assert_eq!(refresh_assets.urls(Icons).len(), 3);
     }
 
     #[test]
@@ -218,6 +365,28 @@ mod test {
         assert_eq!(3, count);
     }
 
+    #[test]
+    fn test_get_assets_matches() {
+        let entry: AssetEntry = serde_json::from_value(serde_json::json!({
+            "asset_id": "ce091c998b83c78bb71a632313ba3760f1763d9cfcffae02258ffa9865a37bd2",
+            "name": "Tether USD",
+            "ticker": "USDt",
+            "entity": {"domain": "tether.to"},
+        }))
+        .unwrap();
+        let mut param = GetAssetsParam::default();
+        assert!(param.matches(&entry));
+        param.ticker = Some("usdt".to_string());
+        param.name = Some("tether".to_string());
+        param.domain = Some("tether.to".to_string());
+        assert!(param.matches(&entry));
+        param.assets_id = vec![AssetId::default()];
+        assert!(!param.matches(&entry));
+        param.assets_id = vec![entry.asset_id];
+        param.name = Some("euro".to_string());
+        assert!(!param.matches(&entry));
+    }
+
     #[test]
     fn test_deser() {
         let test_input = r#"{"assets":true,"icons":true,"refresh":true,"config":{"network":"liquid-testnet","url":"some url","proxy":"someproxy"}}"#;
@@ -0,0 +1,733 @@
+//! The registry module contains [`Registry`], the handle owning the files persisting the
+//! registry values of a directory.
+
+use crate::hard::{hard_coded_assets, hard_coded_icons, hard_coded_values};
+use crate::param::{
+    AddLocalAssetParam, AssetsOrIcons, ElementsNetwork, GetAssetsParam,
+    ImportRegistrySnapshotParam, RefreshAssetsParam, ValidateAssetDomainParam,
+};
+use crate::result::{
+    AssetEntry, AssetSource, DomainVerification, GetAssetsResult, ImportRegistrySnapshotResult,
+    RefreshAssetsResult,
+};
+use crate::{domain, file, http, local, Error, ValueModified};
+use elements::AssetId;
+use fs2::FileExt;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+/// Name of the file, in the root directory of a registry, locked while writing
+const LOCK_FILE_NAME: &str = "lock";
+
+/// Files containing data which is not fetched from the registry, one per network
+#[derive(Hash, Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum CacheFile {
+    /// The cached verifications of the assets issuer domain
+    Domains,
+
+    /// The assets added by the user
+    LocalAssets,
+}
+
+impl CacheFile {
+    fn iter() -> impl Iterator<Item = Self> {
+        [Self::Domains, Self::LocalAssets].into_iter()
+    }
+
+    fn file_name(&self) -> &str {
+        match self {
+            CacheFile::Domains => "domains",
+            CacheFile::LocalAssets => "local_assets",
+        }
+    }
+}
+
+/// A registry persisting its cached data in its own directory.
+///
+/// Any number of registries could be created, concurrent access to the files is guarded by the
+/// handle owning them and writes are serialized with a lock on the directory, so that registries
+/// sharing a directory, even from different processes, don't interleave them.
+/// The free functions of this library use a default registry initialized with [`crate::init`].
+pub struct Registry {
+    dir: PathBuf,
+    files: HashMap<(ElementsNetwork, AssetsOrIcons), Mutex<File>>,
+    cache_files: HashMap<(ElementsNetwork, CacheFile), Mutex<File>>,
+    parsed_assets: Mutex<HashMap<ElementsNetwork, ParsedAssets>>,
+    lock_file: Mutex<File>,
+}
+
+/// Exclusive lock on the directory of a [`Registry`], released when dropped
+pub(crate) struct DirLock<'a>(MutexGuard<'a, File>);
+
+impl Drop for DirLock<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.unlock() {
+            warn!("can't unlock the registry directory: {:?}", e);
+        }
+    }
+}
+
+/// The registry assets of a network as parsed from their file, reused until the file changes
+struct ParsedAssets {
+    /// modification time and length of the file when it was parsed
+    modified: (SystemTime, u64),
+
+    /// the assets, with their source set
+    assets: HashMap<AssetId, AssetEntry>,
+}
+
+impl Registry {
+    /// Create a registry persisting cached data in the root directory `dir`.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, Error> {
+        fs::create_dir_all(&dir)?;
+        let lock_file = Mutex::new(
+            OpenOptions::new().write(true).create(true).open(dir.as_ref().join(LOCK_FILE_NAME))?,
+        );
+        // missing files are created with their initial value
+        let lock = lock(&lock_file)?;
+        let mut files = HashMap::new();
+        for b in AssetsOrIcons::iter() {
+            for n in ElementsNetwork::iter() {
+                let mut file_path = dir.as_ref().to_path_buf();
+                file_path.push(n.to_string());
+                fs::create_dir_all(&file_path)?;
+                file_path.push(b.to_string());
+                let file = open_or_create(&file_path, || hard_coded_values(n, b))?;
+                files.insert((n, b), Mutex::new(file));
+            }
+        }
+        let mut cache_files = HashMap::new();
+        for c in CacheFile::iter() {
+            for n in ElementsNetwork::iter() {
+                let mut file_path = dir.as_ref().to_path_buf();
+                file_path.push(n.to_string());
+                file_path.push(c.file_name());
+                let file = open_or_create(&file_path, || serde_json::json!({}))?;
+                cache_files.insert((n, c), Mutex::new(file));
+            }
+        }
+        drop(lock);
+        Ok(Registry {
+            dir: dir.as_ref().to_path_buf(),
+            files,
+            cache_files,
+            parsed_assets: Mutex::new(HashMap::new()),
+            lock_file,
+        })
+    }
+
+    /// The root directory where this registry persists cached data
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Only way to access to `File`s containing the registry information
+    pub(crate) fn get_file(
+        &self,
+        network: ElementsNetwork,
+        t: AssetsOrIcons,
+    ) -> Result<MutexGuard<'_, File>, Error> {
+        Ok(self.files.get(&(network, t)).expect("any combination is initialized").lock()?)
+    }
+
+    /// Lock the directory of this registry, must be held while writing its files.
+    ///
+    /// To avoid deadlocks, it must be taken after the guard of the file to write.
+    pub(crate) fn lock_dir(&self) -> Result<DirLock<'_>, Error> {
+        lock(&self.lock_file)
+    }
+
+    /// Only way to access to `File`s containing data not fetched from the registry
+    pub(crate) fn get_cache_file(
+        &self,
+        network: ElementsNetwork,
+        c: CacheFile,
+    ) -> Result<MutexGuard<'_, File>, Error> {
+        Ok(self.cache_files.get(&(network, c)).expect("any combination is initialized").lock()?)
+    }
+
+    /// Returns information about assets and related icons.
+    ///
+    /// Results could come from the persisted cached value when `details.refresh` is `false` or
+    /// could be fetched from an asset registry when it's `true`.
+    /// By default, Liquid mainnet network is used and the asset registry used is managed by
+    /// Blockstream and no proxy is used to access it. This default configuration could be
+    /// overridden by providing the `details.config` parameter.
+    pub fn refresh_assets(
+        &self,
+        details: &RefreshAssetsParam,
+    ) -> Result<RefreshAssetsResult, Error> {
+        let now = std::time::Instant::now();
+        let network = details.network();
+        let mut return_value = RefreshAssetsResult::default();
+        let agent = details.agent()?;
+        for what in details.asked()? {
+            let mut file = self.get_file(network, what)?;
+            let file_value = file::read(&mut file)?;
+            let value = match agent.as_ref() {
+                Some(agent) => {
+                    let urls = details.urls(what);
+                    match http::call_mirrors(&urls, agent, &file_value.last_modified) {
+                        Ok(response_value) => {
+                            debug!(
+                                "response for {} modified: {}",
+                                what, response_value.last_modified
+                            );
+                            if file_value.last_modified != response_value.last_modified {
+                                let new = ValueModified {
+                                    last_modified: response_value.last_modified.clone(),
+                                    value: verified_value(network, what, response_value)?,
+                                };
+                                let _lock = self.lock_dir()?;
+                                file::write(&new, &mut file)?;
+                                self.parsed_assets.lock()?.remove(&network);
+                                new
+                            } else {
+                                file_value
+                            }
+                        }
+                        Err(e) => {
+                            warn!("can't refresh {}, returning cached value: {:?}", what, e);
+                            file_value
+                        }
+                    }
+                }
+                None => file_value,
+            };
+            match what {
+                AssetsOrIcons::Assets => {
+                    return_value.assets = serde_json::from_value(value.value)?;
+                    self.merge_local(network, &mut return_value.assets)?;
+                    domain::annotate(self, network, return_value.assets.values_mut())?;
+                }
+                AssetsOrIcons::Icons => return_value.icons = serde_json::from_value(value.value)?,
+            }
+        }
+        info!("refresh_assets took: {:?}", now.elapsed());
+        Ok(return_value)
+    }
+
+    /// Returns the locally cached assets matching the query in `details`, optionally with their
+    /// icons.
+    ///
+    /// No network call is made: to preserve privacy the registry is fetched whole with
+    /// [`Registry::refresh_assets`] and queried locally.
+    pub fn get_assets(&self, details: &GetAssetsParam) -> Result<GetAssetsResult, Error> {
+        let now = std::time::Instant::now();
+        let network = details.network();
+        let assets = self.cached_assets(network, |entry| details.matches(entry))?;
+
+        let mut matching: Vec<AssetEntry> = assets.into_values().collect();
+        matching.sort_by(|a, b| a.asset_id.cmp(&b.asset_id));
+        let total = matching.len();
+        let assets: HashMap<AssetId, AssetEntry> = matching
+            .into_iter()
+            .skip(details.offset)
+            .take(details.limit.unwrap_or(usize::MAX))
+            .map(|entry| (entry.asset_id, entry))
+            .collect();
+
+        let icons = if details.icons {
+            let mut icons =
+                file::read(&mut self.get_file(network, AssetsOrIcons::Icons)?)?.icons()?;
+            icons.retain(|asset_id, _| assets.contains_key(asset_id));
+            icons
+        } else {
+            HashMap::new()
+        };
+        info!("get_assets returned {} of {} assets took: {:?}", assets.len(), total, now.elapsed());
+
+        Ok(GetAssetsResult {
+            assets,
+            icons,
+            total,
+        })
+    }
+
+    /// Verifies the issuer domain of a cached asset, fetching the proof published by the issuer
+    /// at `https://<domain>/.well-known/liquid-asset-proof-<asset_id>`, through the proxy if
+    /// configured.
+    ///
+    /// The outcome is cached for [`crate::DOMAIN_VERIFICATION_EXPIRY`] seconds and reported in the
+    /// `domain_verified` field of the assets returned by [`Registry::refresh_assets`] and
+    /// [`Registry::get_assets`].
+    pub fn validate_asset_domain_name(
+        &self,
+        details: &ValidateAssetDomainParam,
+    ) -> Result<DomainVerification, Error> {
+        let network = details.config.network;
+        let mut assets = self.cached_assets(network, |entry| entry.asset_id == details.asset_id)?;
+        let entry = assets
+            .remove(&details.asset_id)
+            .ok_or_else(|| Error::AssetNotFound(details.asset_id.to_string()))?;
+        domain::verify(self, network, &entry, details.refresh, &details.config.agent()?)
+    }
+
+    /// Adds an asset not present in the registry, like the ones of private issuances.
+    ///
+    /// The asset is verified to commit to its contract and persisted locally, it is then returned
+    /// by [`Registry::refresh_assets`] and [`Registry::get_assets`] with [`AssetSource::Local`] as
+    /// source.
+    pub fn add_local_asset(&self, details: &AddLocalAssetParam) -> Result<AssetEntry, Error> {
+        local::add(self, details.config.network, details.entry()?)
+    }
+
+    /// Replace the cached registry values with the ones in the snapshot at `details.path`, useful
+    /// to install a registry without network access.
+    ///
+    /// The snapshot is either a JSON file containing an object with the `assets` and `icons`
+    /// fields, or a directory in the layout of the hard-coded values of this library, as written
+    /// by `make_hard_coded`: `<network>_assets.json` and `<network>_icons.json`, where a missing
+    /// file means no values to import. Assets not verifying are discarded.
+    /// The values are stored as never modified, so that the next refresh fetches them whole.
+    pub fn import_registry_snapshot(
+        &self,
+        details: &ImportRegistrySnapshotParam,
+    ) -> Result<ImportRegistrySnapshotResult, Error> {
+        let network = details.config.network;
+        let snapshot = read_snapshot(&details.path, network)?;
+        let mut result = ImportRegistrySnapshotResult::default();
+        for what in AssetsOrIcons::iter() {
+            let value = match what {
+                AssetsOrIcons::Assets if !snapshot.assets.is_empty() => {
+                    serde_json::to_value(&snapshot.assets)?
+                }
+                AssetsOrIcons::Icons if !snapshot.icons.is_empty() => {
+                    serde_json::to_value(&snapshot.icons)?
+                }
+                _ => continue,
+            };
+            let imported = ValueModified {
+                last_modified: "".to_string(),
+                value,
+            };
+            let new = ValueModified {
+                last_modified: "".to_string(),
+                value: verified_value(network, what, imported)?,
+            };
+            match what {
+                AssetsOrIcons::Assets => {
+                    result.assets = new.value.as_object().map_or(0, |o| o.len())
+                }
+                AssetsOrIcons::Icons => result.icons = new.value.as_object().map_or(0, |o| o.len()),
+            }
+            let mut file = self.get_file(network, what)?;
+            let _lock = self.lock_dir()?;
+            file::write(&new, &mut file)?;
+            self.parsed_assets.lock()?.remove(&network);
+        }
+        info!("imported registry snapshot for {}: {:?}", network, result);
+        Ok(result)
+    }
+
+    /// Returns the cached registry assets merged with the local ones, only the ones passing
+    /// `filter`
+    ///
+    /// The registry file is parsed only if it changed since the last call.
+    fn cached_assets<F>(
+        &self,
+        network: ElementsNetwork,
+        filter: F,
+    ) -> Result<HashMap<AssetId, AssetEntry>, Error>
+    where
+        F: Fn(&AssetEntry) -> bool,
+    {
+        let mut assets: HashMap<AssetId, AssetEntry> = {
+            let mut file = self.get_file(network, AssetsOrIcons::Assets)?;
+            let metadata = file.metadata()?;
+            let modified = (metadata.modified()?, metadata.len());
+            let mut parsed_assets = self.parsed_assets.lock()?;
+            if !matches!(parsed_assets.get(&network), Some(p) if p.modified == modified) {
+                debug!("parsing the registry assets of {}", network);
+                let mut assets = file::read(&mut file)?.assets()?;
+                set_source(network, &mut assets);
+                parsed_assets.insert(
+                    network,
+                    ParsedAssets {
+                        modified,
+                        assets,
+                    },
+                );
+            }
+            let parsed = &parsed_assets[&network];
+            parsed
+                .assets
+                .values()
+                .filter(|entry| filter(entry))
+                .map(|e| (e.asset_id, e.clone()))
+                .collect()
+        };
+        for (asset_id, mut entry) in local::assets(self, network)? {
+            if !assets.contains_key(&asset_id) && filter(&entry) {
+                entry.source = Some(AssetSource::Local);
+                assets.insert(asset_id, entry);
+            }
+        }
+        domain::annotate(self, network, assets.values_mut())?;
+        Ok(assets)
+    }
+
+    /// Merge the local assets in `assets` and set where every asset comes from, assets present in
+    /// the registry take precedence over the local ones
+    fn merge_local(
+        &self,
+        network: ElementsNetwork,
+        assets: &mut HashMap<AssetId, AssetEntry>,
+    ) -> Result<(), Error> {
+        set_source(network, assets);
+        for (asset_id, mut entry) in local::assets(self, network)? {
+            entry.source = Some(AssetSource::Local);
+            assets.entry(asset_id).or_insert(entry);
+        }
+        Ok(())
+    }
+}
+
+/// Set the source of the registry `assets`, which are either hard-coded or fetched
+fn set_source(network: ElementsNetwork, assets: &mut HashMap<AssetId, AssetEntry>) {
+    let hard = hard_coded_assets(network);
+    for entry in assets.values_mut() {
+        entry.source = Some(if hard.contains_key(&entry.asset_id) {
+            AssetSource::HardCoded
+        } else {
+            AssetSource::Registry
+        });
+    }
+}
+
+/// Returns the value to persist from the downloaded one, discarding assets not verifying and adding
+/// the hard-coded values
+fn verified_value(
+    network: ElementsNetwork,
+    what: AssetsOrIcons,
+    downloaded: ValueModified,
+) -> Result<serde_json::Value, Error> {
+    Ok(match what {
+        AssetsOrIcons::Assets => {
+            let hard = hard_coded_assets(network);
+            let mut downloaded = downloaded.assets()?;
+            let len = downloaded.len();
+            debug!("downloaded {} assets metadata", len);
+            downloaded.retain(|_k, v| v.verify().unwrap_or(false));
+            if downloaded.len() != len {
+                warn!("Some assets didn't verify!");
+            }
+            downloaded.extend(hard);
+            serde_json::to_value(downloaded)?
+        }
+        AssetsOrIcons::Icons => {
+            let hard = hard_coded_icons(network);
+            let mut downloaded = downloaded.icons()?;
+            debug!("downloaded {} assets icons", downloaded.len());
+            downloaded.extend(hard);
+            serde_json::to_value(downloaded)?
+        }
+    })
+}
+
+/// Read the snapshot at `path` for `network`, see [`Registry::import_registry_snapshot`]
+fn read_snapshot(path: &Path, network: ElementsNetwork) -> Result<RefreshAssetsResult, Error> {
+    if !path.is_dir() {
+        return Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?);
+    }
+    let mut snapshot = RefreshAssetsResult::default();
+    let mut found = false;
+    for what in AssetsOrIcons::iter() {
+        let file_path = path.join(format!("{}_{}.json", network, what));
+        if !file_path.exists() {
+            continue;
+        }
+        found = true;
+        let reader = BufReader::new(File::open(&file_path)?);
+        match what {
+            AssetsOrIcons::Assets => snapshot.assets = serde_json::from_reader(reader)?,
+            AssetsOrIcons::Icons => snapshot.icons = serde_json::from_reader(reader)?,
+        }
+    }
+    if !found {
+        let msg = format!("no {} snapshot in {:?}", network, path);
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, msg).into());
+    }
+    Ok(snapshot)
+}
+
+/// Take the exclusive lock on `lock_file`, waiting for other processes to release it
+fn lock(lock_file: &Mutex<File>) -> Result<DirLock<'_>, Error> {
+    let guard = lock_file.lock()?;
+    guard.lock_exclusive()?;
+    Ok(DirLock(guard))
+}
+
+/// Open the file at `file_path`, creating it with the `initial` value if it doesn't exist
+fn open_or_create<F>(file_path: &Path, initial: F) -> Result<File, Error>
+where
+    F: FnOnce() -> serde_json::Value,
+{
+    let file_exists = file_path.exists();
+    let mut file = OpenOptions::new().write(true).read(true).create(true).open(file_path)?;
+    if !file_exists {
+        let value_modified = ValueModified {
+            value: initial(),
+            last_modified: "".to_string(),
+        };
+        file::write(&value_modified, &mut file)?;
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_multiple_registries() {
+        let _ = env_logger::try_init();
+
+        let tether: AssetEntry =
+            serde_json::from_str(include_str!("data/test/tether.json")).unwrap();
+        let first_dir = TempDir::new().unwrap();
+        let second_dir = TempDir::new().unwrap();
+        let first = Registry::new(&first_dir).unwrap();
+        let second = Registry::new(&second_dir).unwrap();
+        assert_eq!(first.dir(), first_dir.path());
+
+        let param = AddLocalAssetParam {
+            asset: Some(tether.clone()),
+            ..Default::default()
+        };
+        first.add_local_asset(&param).unwrap();
+
+        let query = GetAssetsParam {
+            assets_id: vec![tether.asset_id],
+            ..Default::default()
+        };
+        assert_eq!(first.get_assets(&query).unwrap().total, 1);
+        assert_eq!(second.get_assets(&query).unwrap().total, 0);
+
+        // a registry on the same directory finds the persisted data
+        drop(first);
+        let first = Registry::new(&first_dir).unwrap();
+        assert_eq!(first.get_assets(&query).unwrap().total, 1);
+    }
+
+    #[test]
+    fn test_get_assets() {
+        let _ = env_logger::try_init();
+
+        let policy_asset = crate::policy_asset_id(ElementsNetwork::Liquid);
+        let hard_coded = hard_coded_assets(ElementsNetwork::Liquid);
+        let dir = TempDir::new().unwrap();
+        let registry = Registry::new(&dir).unwrap();
+
+        let query = GetAssetsParam {
+            ticker: Some("L-BTC".to_string()),
+            icons: true,
+            ..Default::default()
+        };
+        let queried = registry.get_assets(&query).unwrap();
+        assert_eq!(queried.total, 1);
+        assert!(queried.assets.get(&policy_asset).is_some());
+        assert!(queried.icons.get(&policy_asset).is_some());
+
+        let query = GetAssetsParam {
+            limit: Some(2),
+            offset: 1,
+            ..Default::default()
+        };
+        let queried = registry.get_assets(&query).unwrap();
+        assert_eq!(queried.assets.len(), 2);
+        assert!(queried.icons.is_empty());
+        assert_eq!(queried.total, hard_coded.len());
+
+        let query = GetAssetsParam {
+            offset: hard_coded.len(),
+            ..Default::default()
+        };
+        let queried = registry.get_assets(&query).unwrap();
+        assert!(queried.assets.is_empty());
+        assert_eq!(queried.total, hard_coded.len());
+    }
+
+    #[test]
+    fn test_validate_asset_domain_name() {
+        let _ = env_logger::try_init();
+
+        let dir = TempDir::new().unwrap();
+        let registry = Registry::new(&dir).unwrap();
+
+        // the policy asset has no issuer domain
+        let param = ValidateAssetDomainParam {
+            asset_id: crate::policy_asset_id(ElementsNetwork::Liquid),
+            ..Default::default()
+        };
+        let result = registry.validate_asset_domain_name(&param);
+        assert!(matches!(result, Err(Error::InvalidDomain(_))));
+
+        let param = ValidateAssetDomainParam {
+            asset_id: AssetId::default(),
+            ..Default::default()
+        };
+        let result = registry.validate_asset_domain_name(&param);
+        assert!(matches!(result, Err(Error::AssetNotFound(_))));
+    }
+
+    #[test]
+    fn test_add_local_asset() {
+        let _ = env_logger::try_init();
+
+        let tether: AssetEntry =
+            serde_json::from_str(include_str!("data/test/tether.json")).unwrap();
+        let dir = TempDir::new().unwrap();
+        let registry = Registry::new(&dir).unwrap();
+
+        let param = AddLocalAssetParam {
+            contract: Some(tether.contract.clone()),
+            issuance_prevout: Some(tether.issuance_prevout.clone()),
+            config: crate::param::Config {
+                network: ElementsNetwork::ElementsRegtest,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(registry.add_local_asset(&param).unwrap().asset_id, tether.asset_id);
+        let query = GetAssetsParam {
+            assets_id: vec![tether.asset_id],
+            config: param.config,
+            ..Default::default()
+        };
+        let queried = registry.get_assets(&query).unwrap();
+        assert_eq!(queried.assets[&tether.asset_id].source, Some(AssetSource::Local));
+
+        let mut wrong = tether.clone();
+        wrong.name = "Wrong".to_string();
+        let param = AddLocalAssetParam {
+            asset: Some(wrong),
+            ..Default::default()
+        };
+        let result = registry.add_local_asset(&param);
+        assert!(matches!(result, Err(Error::InvalidLocalAsset(_))));
+    }
+
+    #[test]
+    fn test_shared_directory() {
+        let _ = env_logger::try_init();
+
+        let tether: AssetEntry =
+            serde_json::from_str(include_str!("data/test/tether.json")).unwrap();
+        let dir = TempDir::new().unwrap();
+        let first = Registry::new(&dir).unwrap();
+        let second = Registry::new(&dir).unwrap();
+
+        let lock = first.lock_dir().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let param = AddLocalAssetParam {
+                asset: Some(tether.clone()),
+                ..Default::default()
+            };
+            second.add_local_asset(&param).unwrap();
+            sender.send(tether.asset_id).unwrap();
+        });
+        let timeout = std::time::Duration::from_millis(200);
+        assert!(receiver.recv_timeout(timeout).is_err(), "the write must wait for the lock");
+        drop(lock);
+        let asset_id = receiver.recv().unwrap();
+        handle.join().unwrap();
+
+        let query = GetAssetsParam {
+            assets_id: vec![asset_id],
+            ..Default::default()
+        };
+        assert_eq!(first.get_assets(&query).unwrap().total, 1);
+    }
+
+    #[test]
+    fn test_import_registry_snapshot() {
+        let _ = env_logger::try_init();
+
+        let tether: AssetEntry =
+            serde_json::from_str(include_str!("data/test/tether.json")).unwrap();
+        let mut wrong = tether.clone();
+        wrong.asset_id = AssetId::default();
+        let mut assets = HashMap::new();
+        assets.insert(tether.asset_id, tether.clone());
+        assets.insert(wrong.asset_id, wrong.clone());
+        let snapshot = serde_json::json!({ "assets": assets });
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("snapshot.json");
+        std::fs::write(&path, serde_json::to_vec(&snapshot).unwrap()).unwrap();
+
+        let registry = Registry::new(&dir).unwrap();
+        let other = Registry::new(&dir).unwrap();
+        let query = GetAssetsParam {
+            assets_id: vec![tether.asset_id, wrong.asset_id],
+            ..Default::default()
+        };
+        assert_eq!(other.get_assets(&query).unwrap().total, 0);
+        let param = ImportRegistrySnapshotParam {
+            path: path.clone(),
+            config: Default::default(),
+        };
+        let result = registry.import_registry_snapshot(&param).unwrap();
+        let hard_coded = hard_coded_assets(ElementsNetwork::Liquid);
+        assert_eq!(result.assets, hard_coded.len() + 1, "the wrong asset must be discarded");
+        assert_eq!(result.icons, 0);
+
+        let queried = registry.get_assets(&query).unwrap();
+        assert_eq!(queried.total, 1);
+        assert_eq!(
+            other.get_assets(&query).unwrap().total,
+            1,
+            "the assets parsed before the import must not be used"
+        );
+        assert_eq!(queried.assets[&tether.asset_id].source, Some(AssetSource::Registry));
+
+        let param = ImportRegistrySnapshotParam {
+            path: dir.path().join("missing.json"),
+            config: Default::default(),
+        };
+        assert!(registry.import_registry_snapshot(&param).is_err());
+    }
+
+    #[test]
+    fn test_import_hard_coded_snapshot() {
+        let _ = env_logger::try_init();
+
+        let hard = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/hard");
+        let dir = TempDir::new().unwrap();
+        let registry = Registry::new(&dir).unwrap();
+        for network in ElementsNetwork::iter() {
+            let param = ImportRegistrySnapshotParam {
+                path: hard.clone(),
+                config: crate::param::Config {
+                    network,
+                    ..Default::default()
+                },
+            };
+            let result = registry.import_registry_snapshot(&param).unwrap();
+            assert_eq!(result.assets, hard_coded_assets(network).len());
+            assert_eq!(result.icons, hard_coded_icons(network).len());
+
+            let query = GetAssetsParam {
+                assets_id: vec![crate::policy_asset_id(network)],
+                config: param.config,
+                ..Default::default()
+            };
+            let queried = registry.get_assets(&query).unwrap();
+            assert_eq!(queried.total, 1);
+        }
+
+        let param = ImportRegistrySnapshotParam {
+            path: dir.path().to_path_buf(),
+            config: Default::default(),
+        };
+        assert!(registry.import_registry_snapshot(&param).is_err(), "no snapshot files");
+    }
+}
@@ -18,6 +18,31 @@ pub struct RefreshAssetsResult {
     pub icons: HashMap<AssetId, String>,
 }
 
+/// Contains the number of assets and icons imported by the [`crate::import_registry_snapshot`]
+/// call, including the hard-coded ones.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ImportRegistrySnapshotResult {
+    /// Number of assets metadata imported
+    pub assets: usize,
+
+    /// Number of assets icons imported
+    pub icons: usize,
+}
+
+/// Contains the result of the [`crate::get_assets`] call, the assets matching the query and, if
+/// requested, their icons.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct GetAssetsResult {
+    /// Assets metadata
+    pub assets: HashMap<AssetId, AssetEntry>,
+
+    /// Assets icons: the hashmap value is a Base64 encoded image
+    pub icons: HashMap<AssetId, String>,
+
+    /// Number of assets matching the query, regardless of pagination
+    pub total: usize,
+}
+
 /// Contains information about an asset, including its asset id, the contract defining its
 /// property, and information about the transaction that issued the asset.
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
@@ -63,8 +88,46 @@ pub struct AssetEntry {
     /// Contains information regarding the internet domain of the asset issuer.
     #[serde(default)]
     pub entity: Value,
+
+    /// Whether the issuer domain in `entity` has been verified to be linked to the asset, `None`
+    /// if the verification has not been performed or it's expired.
+    /// See [`crate::validate_asset_domain_name`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain_verified: Option<bool>,
+
+    /// Where this asset information comes from, set on the returned assets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<AssetSource>,
 }
 
+/// Discriminate where the information of an asset comes from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetSource {
+    /// Hard-coded in this library
+    HardCoded,
+
+    /// Fetched from the asset registry
+    Registry,
+
+    /// Added by the user with [`crate::add_local_asset`]
+    Local,
+}
+
+/// Contains the outcome of the verification of the issuer domain of an asset
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DomainVerification {
+    /// The domain verified
+    pub domain: String,
+
+    /// True if the domain publishes a valid proof linking it to the asset
+    pub verified: bool,
+
+    /// Unix timestamp in seconds of the verification
+    pub checked_at: u64,
+}
+
+/// The previous output spent by an issuance
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 pub struct Prevout {
     txid: Txid,
@@ -78,6 +141,27 @@ pub struct Txin {
 }
 
 impl AssetEntry {
+    /// Build the entry of the asset issued with the given `contract` spending `issuance_prevout`,
+    /// the asset id and the first level fields are derived from the contract.
+    pub fn from_contract(contract: Value, issuance_prevout: Prevout) -> Result<Self, Error> {
+        let contract_hash = ContractHash::from_json_contract(&serde_json::to_string(&contract)?)?;
+        let outpoint = OutPoint::new(issuance_prevout.txid, issuance_prevout.vout);
+        let entropy = AssetId::generate_asset_entropy(outpoint, contract_hash);
+
+        Ok(AssetEntry {
+            asset_id: AssetId::from_entropy(entropy),
+            issuance_prevout,
+            version: contract["version"].as_u64().unwrap_or_default() as u8,
+            issuer_pubkey: contract["issuer_pubkey"].as_str().unwrap_or_default().to_string(),
+            name: contract["name"].as_str().unwrap_or_default().to_string(),
+            ticker: contract["ticker"].as_str().map(ToString::to_string),
+            precision: contract["precision"].as_u64().unwrap_or_default() as u8,
+            entity: contract["entity"].clone(),
+            contract,
+            ..Default::default()
+        })
+    }
+
     fn contract_string(&self) -> Result<String, Error> {
         Ok(serde_json::to_string(&self.contract)?)
     }
@@ -86,6 +170,11 @@ impl AssetEntry {
         OutPoint::new(self.issuance_prevout.txid, self.issuance_prevout.vout)
     }
 
+    /// Returns the internet domain of the asset issuer, if any
+    pub fn domain(&self) -> Option<&str> {
+        self.entity["domain"].as_str()
+    }
+
     /// Verify information in `self.contract` commits in `self.asset_id` ensuring the validity of the
     /// Contract data. Moreover information in the first level like `self.name` is verified to be the
     /// same of the one in the contract `self.contract.name`
@@ -142,6 +231,15 @@ mod test {
         assert_eq!(asset_id, tether_parsed.asset_id);
         assert!(tether_parsed.verify().unwrap());
 
+        let from_contract = AssetEntry::from_contract(
+            tether_parsed.contract.clone(),
+            tether_parsed.issuance_prevout.clone(),
+        )
+        .unwrap();
+        assert_eq!(from_contract.asset_id, tether_parsed.asset_id);
+        assert_eq!(from_contract.ticker, tether_parsed.ticker);
+        assert!(from_contract.verify().unwrap());
+
         let mut tether_wrong_id = tether_parsed.clone();
         tether_wrong_id.asset_id = AssetId::default();
         assert!(!tether_wrong_id.verify().unwrap());
@@ -19,12 +19,14 @@ use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use gdk_common::model::{
     CreateAccountOpt, GetNextAccountOpt, GetTransactionsOpt, InitParam, RenameAccountOpt,
-    SPVDownloadHeadersParams, SPVVerifyTxParams, SetAccountHiddenOpt, UpdateAccountOpt,
+    SPVDownloadHeadersParams, SPVVerifyTxParams, SPVVerifyTxsParams, SetAccountHiddenOpt,
+    UpdateAccountOpt,
 };
 
 use crate::error::Error;
 use gdk_electrum::error::Error as ElectrumError;
 use gdk_electrum::pset::{self, ExtractTxParam, FromTxParam, MergeTxParam};
+use gdk_electrum::unblind::{self, UnblindTxParam};
 use gdk_electrum::{determine_electrum_url, headers, ElectrumSession};
 use log::{LevelFilter, Metadata, Record};
 use serde::Serialize;
@@ -412,12 +414,26 @@ fn handle_session_call(
             })?)
             .map(|v| json!(v))
             .map_err(Into::into),
+        "get_unblinded_transaction" => session
+            .get_unblinded_transaction(input.as_str().ok_or_else(|| {
+                Error::Other("get_unblinded_transaction: input is not a string".into())
+            })?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
         "get_balance" => session
             .get_balance(&serde_json::from_value(input)?)
             .map(|v| json!(v))
             .map_err(Into::into),
         "set_transaction_memo" => set_transaction_memo(session, &input),
         "create_transaction" => serialize::create_transaction(session, input),
+        "get_max_sendable" => session
+            .get_max_sendable(&serde_json::from_value(input)?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "preview_fee" => session
+            .preview_fee(&serde_json::from_value(input)?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
         "sign_transaction" => session
             .sign_transaction(&serde_json::from_value(input)?)
             .map_err(Into::into)
@@ -462,6 +478,22 @@ fn handle_session_call(
         "refresh_assets" => gdk_registry::refresh_assets(&serde_json::from_value(input)?)
             .map(|v| json!(v))
             .map_err(Into::into),
+        "get_assets" => gdk_registry::get_assets(&serde_json::from_value(input)?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "validate_asset_domain_name" => {
+            gdk_registry::validate_asset_domain_name(&serde_json::from_value(input)?)
+                .map(|v| json!(v))
+                .map_err(Into::into)
+        }
+        "add_local_asset" => gdk_registry::add_local_asset(&serde_json::from_value(input)?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "import_registry_snapshot" => {
+            gdk_registry::import_registry_snapshot(&serde_json::from_value(input)?)
+                .map(|v| json!(v))
+                .map_err(Into::into)
+        }
         "get_unspent_outputs" => session
             .get_unspent_outputs(&serde_json::from_value(input)?)
             .map(|v| json!(v))
@@ -477,6 +509,28 @@ fn handle_session_call(
             .set_master_blinding_key(&serde_json::from_value(input)?)
             .map(|v| json!(v))
             .map_err(Into::into),
+        "export_wallet_backup" => {
+            session.export_wallet_backup().map(|v| json!(v)).map_err(Into::into)
+        }
+        "import_wallet_backup" => session
+            .import_wallet_backup(&serde_json::from_value(input)?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "rotate_store_encryption_key" => session
+            .rotate_store_encryption_key(&serde_json::from_value(input)?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
+        "verify_store" => session.verify_store().map(|v| json!(v)).map_err(Into::into),
+        "rebuild_cache" => session.rebuild_cache().map(|v| json!(v)).map_err(Into::into),
+        "get_spv_status" => session.get_spv_status().map(|v| json!(v)).map_err(Into::into),
+        "get_sync_status" => session.get_sync_status().map(|v| json!(v)).map_err(Into::into),
+        "get_fee_estimates_by_target" => {
+            session.get_fee_estimates_by_target().map(|v| json!(v)).map_err(Into::into)
+        }
+        "estimate_confirmation_time" => session
+            .estimate_confirmation_time(&serde_json::from_value(input)?)
+            .map(|v| json!(v))
+            .map_err(Into::into),
         "start_threads" => session.start_threads().map_err(Into::into).map(|s| json!(s)),
         "get_wallet_hash_id" => session.get_wallet_hash_id().map_err(Into::into).map(|s| json!(s)),
 
@@ -574,14 +628,38 @@ fn handle_call(method: &str, input: &str) -> Result<String, Error> {
             let param: SPVVerifyTxParams = serde_json::from_str(input)?;
             Ok(to_string(&headers::spv_verify_tx(&param)?.as_i32()))
         }
+        "spv_verify_txs" => {
+            let param: SPVVerifyTxsParams = serde_json::from_str(input)?;
+            Ok(to_string(&headers::spv_verify_txs(&param)?))
+        }
         "spv_download_headers" => {
             let param: SPVDownloadHeadersParams = serde_json::from_str(input)?;
             Ok(to_string(&headers::download_headers(&param)?))
         }
+        "unblind_transaction" => {
+            let param: UnblindTxParam = serde_json::from_str(input)?;
+            Ok(to_string(&unblind::unblind_transaction(&param)?))
+        }
         "refresh_assets" => {
             let param: gdk_registry::RefreshAssetsParam = serde_json::from_str(input)?;
             Ok(to_string(&gdk_registry::refresh_assets(&param)?))
         }
+        "get_assets" => {
+            let param: gdk_registry::GetAssetsParam = serde_json::from_str(input)?;
+            Ok(to_string(&gdk_registry::get_assets(&param)?))
+        }
+        "validate_asset_domain_name" => {
+            let param: gdk_registry::ValidateAssetDomainParam = serde_json::from_str(input)?;
+            Ok(to_string(&gdk_registry::validate_asset_domain_name(&param)?))
+        }
+        "add_local_asset" => {
+            let param: gdk_registry::AddLocalAssetParam = serde_json::from_str(input)?;
+            Ok(to_string(&gdk_registry::add_local_asset(&param)?))
+        }
+        "import_registry_snapshot" => {
+            let param: gdk_registry::ImportRegistrySnapshotParam = serde_json::from_str(input)?;
+            Ok(to_string(&gdk_registry::import_registry_snapshot(&param)?))
+        }
         _ => Err(Error::MethodNotFound {
             method: method.to_string(),
             in_session: false,
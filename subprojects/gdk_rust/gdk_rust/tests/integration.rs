@@ -1,23 +1,26 @@
 use bitcoin::util::bip32::DerivationPath;
+use electrsd::bitcoind::bitcoincore_rpc::RpcApi;
 use electrum_client::ElectrumApi;
 use gdk_common::be::BETransaction;
 use gdk_common::model::{
-    AddressAmount, CreateAccountOpt, CreateTransaction, CreateTxUtxos, Credentials, GetBalanceOpt,
-    GetNextAccountOpt, GetPreviousAddressesOpt, GetTransactionsOpt, GetUnspentOutputs,
-    RenameAccountOpt, SPVCommonParams, SPVDownloadHeadersParams, SPVVerifyTxResult,
-    TransactionType, UpdateAccountOpt, UtxoStrategy,
+    AddressAmount, ConflictType, CreateAccountOpt, CreateTransaction, CreateTxUtxos, Credentials,
+    GetBalanceOpt, GetNextAccountOpt, GetPreviousAddressesOpt, GetTransactionsOpt,
+    GetUnspentOutputs, RenameAccountOpt, SPVCommonParams, SPVDownloadHeadersParams,
+    SPVVerifyTxResult, TransactionType, TxConflict, UpdateAccountOpt, UtxoStrategy,
 };
 use gdk_common::scripts::ScriptType;
 use gdk_common::{NetworkId, NetworkParameters};
 use gdk_electrum::error::Error;
 use gdk_electrum::headers::bitcoin::HeadersChain;
 use gdk_electrum::interface::ElectrumUrl;
-use gdk_electrum::{determine_electrum_url, headers, spv, ElectrumSession, State};
+use gdk_electrum::unblind::{self, UnblindTxParam};
+use gdk_electrum::{determine_electrum_url, headers, spv, ElectrumSession, Event, State};
 
 use log::info;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::net::TcpListener;
+use std::str::FromStr;
 use std::time::{Duration, Instant};
 use std::{env, thread};
 use tempfile::TempDir;
@@ -100,6 +103,17 @@ fn roundtrip_liquid() {
     test_session.check_decryption(101, &[&txid]);
     test_session.test_set_get_memo(&txid, MEMO1, MEMO2);
     test_session.is_verified(&txid, SPVVerifyTxResult::Unconfirmed);
+    let unblinded = test_session.session.get_unblinded_transaction(&txid).unwrap();
+    assert!(!unblinded.outputs.is_empty(), "change output should be unblinded");
+    assert!(unblinded.url_fragment.starts_with("#blinded="));
+    let master_blinding_key =
+        test_session.session.get_master_blinding_key().unwrap().master_blinding_key.unwrap();
+    let param = UnblindTxParam {
+        transaction: test_session.session.get_transaction_hex(&txid).unwrap(),
+        blinding_keys: vec![],
+        master_blinding_keys: vec![master_blinding_key],
+    };
+    assert_eq!(unblind::unblind_transaction(&param).unwrap(), unblinded);
     test_session.send_tx(&node_bech32_address, 10_000, None, None, None, None, None);
     test_session.send_tx(&node_legacy_address, 10_000, None, None, None, None, None);
     test_session.send_tx(&node_address, 10_000, Some(assets[0].clone()), None, None, None, None);
@@ -137,6 +151,25 @@ fn roundtrip_liquid() {
 
     // test_session.check_decryption(103, &[&txid]); // TODO restore after sorting out https://github.com/ElementsProject/rust-elements/pull/61
 
+    // sweep every asset and all L-BTC to a single address
+    let mut create_opt = CreateTransaction::default();
+    create_opt.utxos = convertutxos(&test_session.utxos(0));
+    create_opt.send_all = true;
+    create_opt.all_assets = true;
+    create_opt.addressees.push(AddressAmount {
+        address: node_address.to_string(),
+        satoshi: 0,
+        asset_id: test_session.asset_id(),
+        allow_unconfidential: false,
+    });
+    let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+    assert_eq!(tx.changes_used, Some(0));
+    assert!(create_opt.addressees.len() > 1);
+    let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+    let txid = test_session.session.broadcast_transaction(&signed_tx.hex).unwrap();
+    test_session.wait_account_tx(0, &txid);
+    assert!(test_session.utxos(0).0.values().all(|utxos| utxos.is_empty()));
+
     test_session.stop();
 }
 
@@ -393,6 +426,20 @@ fn create_tx_err(is_liquid: bool) {
             Err(Error::NonConfidentialAddress)
         ));
 
+        // Unblinded with explicit opt-in
+        create_opt.addressees[0].allow_unconfidential = true;
+        let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
+        assert_eq!(tx.warnings.len(), 1);
+        let signed_tx = test_session.session.sign_transaction(&tx).unwrap();
+        let signed_tx = BETransaction::from_hex(&signed_tx.hex, test_session.network.id()).unwrap();
+        if let BETransaction::Elements(signed_tx) = signed_tx {
+            let unconf_script = elements::Address::from_str(&unconf_addr).unwrap().script_pubkey();
+            let explicit_output =
+                signed_tx.output.iter().find(|o| o.script_pubkey == unconf_script).unwrap();
+            assert_eq!(explicit_output.value, elements::confidential::Value::Explicit(sat));
+            assert!(signed_tx.output.iter().any(|o| o.witness.rangeproof.is_some()));
+        }
+
         // Missing asset_id
         let mut create_opt =
             test_session.create_opt(&addr, sat, None, fee_rate, subaccount, test_session.utxos(0));
@@ -521,6 +568,7 @@ fn coin_selection(is_liquid: bool) {
         address: node_address.to_string(),
         satoshi: sat8,
         asset_id: test_session.asset_id(),
+        allow_unconfidential: false,
     });
     create_opt.utxos = CreateTxUtxos::default();
     create_opt.utxo_strategy = UtxoStrategy::Manual;
@@ -546,6 +594,7 @@ fn coin_selection(is_liquid: bool) {
             address: node_address.to_string(),
             satoshi: sat2_a,
             asset_id: Some(asset_a.clone()),
+            allow_unconfidential: false,
         });
         utxos.0.remove_entry(&btc_key);
         create_opt.utxos = convertutxos(&utxos);
@@ -855,6 +904,7 @@ fn subaccounts(is_liquid: bool) {
     let credentials = Credentials {
         mnemonic: "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".to_string(),
         bip39_passphrase: "".to_string(),
+        encryption_key: None,
     };
     auth_handler_login(&mut new_session, &credentials);
 
@@ -962,6 +1012,80 @@ fn coinbase(is_liquid: bool) {
     // This coin is immature though, coinbase outputs cannot be spent until 101 blocks.
 }
 
+#[test]
+fn events_bitcoin() {
+    let mut test_session = setup_session(false, |_| ());
+
+    let mut settings = test_session.session.get_settings().unwrap();
+    settings.required_num_blocks = 2;
+    test_session.session.change_settings(&serde_json::to_value(settings).unwrap()).unwrap();
+
+    let events = test_session.session.subscribe();
+    let sat = 10_000;
+    let address = test_session.get_receive_address(0).address;
+    let txid = test_session.node_sendtoaddress(&address, sat, None);
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+    let completed = test_session.session.filter_events("sync_progress");
+    assert!(completed.iter().any(|e| e["sync_progress"]["completed"].as_bool().unwrap()));
+    let status = test_session.session.get_sync_status().unwrap();
+    assert!(status.completed);
+    assert_eq!(status.synced_subaccounts, status.total_subaccounts);
+    let account_status = status.subaccounts.iter().find(|s| s.subaccount == 0).unwrap();
+    assert!(account_status.synced);
+    assert!(account_status.external_scripts > 0 && account_status.internal_scripts > 0);
+    assert_eq!(account_status.txs_downloaded, account_status.txs_discovered);
+    assert!(account_status.txs_discovered >= 1);
+
+    test_session.mine_block();
+    test_session.mine_block();
+
+    let start = Instant::now();
+    let confirmation = loop {
+        let timeout = Duration::from_secs(60).checked_sub(start.elapsed()).expect("timeout");
+        match events.recv_timeout(timeout).unwrap() {
+            Event::Confirmation(ntf) if ntf.txid.to_string() == txid => break ntf,
+            _ => continue,
+        }
+    };
+    assert_eq!(confirmation.subaccounts, vec![0]);
+    assert!(confirmation.confirmations >= 2);
+    test_session.stop();
+}
+
+#[test]
+fn conflict_bitcoin() {
+    let mut test_session = setup_session(false, |_| ());
+
+    // The node sends a replaceable transaction, then replaces it bumping its fee
+    let sat = 10_000;
+    let address = test_session.get_receive_address(0).address;
+    let btc = sat as f64 / 100_000_000.0;
+    let params = [address.into(), btc.into(), "".into(), "".into(), false.into(), true.into()];
+    let txid = test_session.node.client.call::<Value>("sendtoaddress", &params).unwrap();
+    let txid = txid.as_str().unwrap().to_string();
+    test_session.wait_tx(vec![0], &txid, Some(sat), Some(TransactionType::Incoming));
+
+    let bumped = test_session.node.client.call::<Value>("bumpfee", &[txid.clone().into()]).unwrap();
+    let bumped = bumped["txid"].as_str().unwrap().to_string();
+    test_session.wait_tx(vec![0], &bumped, Some(sat), Some(TransactionType::Incoming));
+
+    let replaced = test_session.get_tx_from_list(0, &txid);
+    let expected = TxConflict {
+        type_: ConflictType::Replaced,
+        txhash: bumped.clone(),
+    };
+    assert_eq!(replaced.conflict, Some(expected));
+    assert!(!replaced.can_rbf);
+    assert!(test_session.get_tx_from_list(0, &bumped).conflict.is_none());
+    assert_eq!(test_session.balance_account(0, None, None), sat);
+
+    let events = test_session.session.filter_events("conflict");
+    let event = events.iter().find(|e| e["conflict"]["txhash"] == txid.as_str()).unwrap();
+    assert_eq!(event["conflict"]["conflicting_txhash"], bumped.as_str());
+    assert_eq!(event["conflict"]["type"], "replaced");
+    test_session.stop();
+}
+
 #[test]
 fn spend_unsynced_bitcoin() {
     spend_unsynced(false);
@@ -990,6 +1114,7 @@ fn spend_unsynced(is_liquid: bool) {
         address: address2.to_string(),
         satoshi: sat2,
         asset_id: test_session.asset_id(),
+        allow_unconfidential: false,
     });
     create_opt.utxos = convertutxos(&utxos);
     let tx = test_session.session.create_transaction(&mut create_opt).unwrap();
@@ -1016,6 +1141,7 @@ fn spend_unsynced(is_liquid: bool) {
         address: address2.to_string(),
         satoshi: sat2,
         asset_id: test_session.asset_id(),
+        allow_unconfidential: false,
     });
     create_opt.utxos = convertutxos(&utxos);
     let res = test_session.session.create_transaction(&mut create_opt);
@@ -1174,6 +1300,7 @@ fn labels() {
         address: test_session.get_receive_address(account2.account_num).address,
         satoshi: sat,
         asset_id: None,
+        allow_unconfidential: false,
     });
     create_opt.utxos = convertutxos(&test_session.utxos(create_opt.subaccount));
     create_opt.memo = Some("Foo, Bar Foo".into());
@@ -1204,6 +1331,7 @@ fn labels() {
         address: test_session.get_receive_address(account2.account_num).address,
         satoshi: sat,
         asset_id: None,
+        allow_unconfidential: false,
     });
     create_opt.utxos = convertutxos(&test_session.utxos(create_opt.subaccount));
     create_opt.memo = Some("Foo, Bar Foo".into());
@@ -1249,6 +1377,7 @@ fn rbf() {
         address: dest_address,
         satoshi: 50000,
         asset_id: None,
+        allow_unconfidential: false,
     });
     create_opt.utxos = convertutxos(&test_session.utxos(create_opt.subaccount));
     create_opt.fee_rate = Some(25000);
@@ -1460,6 +1589,12 @@ fn spv_cross_validation_session() {
     assert_eq!(inv.common_ancestor, 121);
     assert_eq!(inv.longest_height, 131);
     assert_eq!(test_session1.get_tx_from_list(0, &txid).spv_verified, "not_longest");
+    let status = test_session1.session.get_spv_status().unwrap();
+    assert!(status.minority_fork);
+    assert!(status.servers.iter().any(|s| s.fork.as_ref().map_or(false, |f| f.local_is_minority
+        && f.common_ancestor == 121
+        && f.fork_height == 131)));
+    assert!(!test_session1.session.filter_events("spv_minority_fork").is_empty());
     info!("extended session2, making session1 the minority");
 
     // Extend session1, making it the best chain
@@ -1576,6 +1711,7 @@ fn test_tor() {
     let credentials = Credentials {
         mnemonic: "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about".to_string(),
         bip39_passphrase: "".to_string(),
+        encryption_key: None,
     };
     auth_handler_login(&mut session, &credentials);
 
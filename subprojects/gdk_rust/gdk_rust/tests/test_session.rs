@@ -185,6 +185,7 @@ pub fn setup(
     let credentials = Credentials {
         mnemonic: mnemonic_str.clone(),
         bip39_passphrase: "".to_string(),
+        encryption_key: None,
     };
     info!("logging in gdk session");
     let login_data = session.login(credentials.clone()).unwrap();
@@ -330,6 +331,7 @@ impl TestSession {
         utxo_strategy: Option<UtxoStrategy>,
     ) -> (String, u64, u64) {
         let init_sat = self.balance_account(subaccount, asset_id.clone(), None);
+        let all_utxos = unspent_outputs.is_none() && utxo_strategy.is_none();
         let mut create_opt = CreateTransaction::default();
         create_opt.subaccount = subaccount;
         let utxos = unspent_outputs.unwrap_or(self.utxos(create_opt.subaccount));
@@ -347,9 +349,27 @@ impl TestSession {
             address: address.to_string(),
             satoshi: 0,
             asset_id: asset_id.clone().or(self.asset_id()),
+            allow_unconfidential: false,
         });
         create_opt.send_all = true;
+        let preview = self.session.preview_fee(&create_opt).unwrap();
         let tx = self.session.create_transaction(&mut create_opt).unwrap();
+        assert_eq!(preview.fee, tx.fee);
+        assert_eq!(preview.fee_rate, fee_rate);
+        if all_utxos {
+            let max_sendable = self
+                .session
+                .get_max_sendable(&GetMaxSendableOpt {
+                    subaccount,
+                    asset_id: create_opt.addressees[0].asset_id.clone(),
+                    fee_rate: Some(fee_rate),
+                    address: address.to_string(),
+                    ..Default::default()
+                })
+                .unwrap();
+            assert_eq!(max_sendable.satoshi, create_opt.addressees[0].satoshi);
+            assert_eq!(max_sendable.fee, tx.fee);
+        }
         let signed_tx = self.session.sign_transaction(&tx).unwrap();
 
         self.check_fee_rate(fee_rate, &signed_tx, MAX_FEE_PERCENT_DIFF);
@@ -390,6 +410,7 @@ impl TestSession {
             address: address.to_string(),
             satoshi,
             asset_id: asset.clone().or(self.asset_id()),
+            allow_unconfidential: false,
         });
         create_opt.memo = memo;
         create_opt.utxos = convertutxos(&unspent_outputs.unwrap_or_else(|| self.utxos(0)));
@@ -474,6 +495,7 @@ impl TestSession {
             address: address.to_string(),
             satoshi,
             asset_id: asset.clone().or(self.asset_id()),
+            allow_unconfidential: false,
         });
         create_opt.utxos = convertutxos(&self.utxos(create_opt.subaccount));
         let tx = self.session.create_transaction(&mut create_opt).unwrap();
@@ -591,6 +613,7 @@ impl TestSession {
                 address: address.to_string(),
                 satoshi: amount,
                 asset_id,
+                allow_unconfidential: false,
             });
             addressees.push(address);
         }
@@ -666,6 +689,7 @@ impl TestSession {
             address: node_address.to_string(),
             satoshi: init_sat, // not enough to pay the fee with confidential utxos only
             asset_id: self.asset_id(),
+            allow_unconfidential: false,
         });
         create_opt.utxos = convertutxos(&self.utxos(create_opt.subaccount));
         create_opt.confidential_utxos_only = true;
@@ -725,6 +749,7 @@ impl TestSession {
             address: address.to_string(),
             satoshi,
             asset_id: self.asset_id(),
+            allow_unconfidential: false,
         });
         create_opt.utxos = convertutxos(&self.utxos(create_opt.subaccount));
         let tx = self.session.create_transaction(&mut create_opt).unwrap();
@@ -760,6 +785,7 @@ impl TestSession {
             address: address.to_string(),
             satoshi: satoshi,
             asset_id: asset_id,
+            allow_unconfidential: false,
         });
         create_opt
     }
@@ -1128,7 +1154,7 @@ impl TestSession {
     }
 
     /// wait for the txid to show up in the given account
-    fn wait_account_tx(&self, subaccount: u32, txid: &str) {
+    pub fn wait_account_tx(&self, subaccount: u32, txid: &str) {
         for _ in 0..60 {
             let txs = self.get_tx_list(subaccount);
             if txs.iter().any(|tx| tx.txhash == txid) {
@@ -1350,6 +1376,25 @@ pub fn spv_verify_tx(
     // second should verify immediately, (and also hit cache)
     assert!(matches!(headers::spv_verify_tx(&param), Ok(SPVVerifyTxResult::Verified)));
 
+    // batch verification, without cache so that the proof is downloaded again
+    let tx = SPVTx {
+        txid: txid.to_string(),
+        height,
+    };
+    let param_batch = SPVVerifyTxsParams {
+        params: SPVCommonParams {
+            encryption_key: None,
+            ..common
+        },
+        txs: vec![tx.clone(), tx.clone()],
+    };
+    let result = headers::spv_verify_txs(&param_batch).unwrap();
+    assert_eq!(result.txs.len(), 2);
+    for item in result.txs {
+        assert_eq!(item.tx, tx);
+        assert!(matches!(item.result, SPVVerifyTxResult::Verified));
+    }
+
     if let Some(handle) = handle {
         handle.join().unwrap();
     }
@@ -1366,6 +1411,7 @@ pub fn auth_handler_login(session: &mut ElectrumSession, credentials: &Credentia
     // Load the rust persisted cache
     let opt = LoadStoreOpt {
         master_xpub: signer.master_xpub(),
+        encryption_key: None,
     };
     session.load_store(&opt).unwrap();
 